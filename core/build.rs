@@ -0,0 +1,4 @@
+fn main() {
+    // Migrations are embedded into the build, so it has to be redone whenever they change
+    println!("cargo:rerun-if-changed=../sql");
+}
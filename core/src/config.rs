@@ -1,15 +1,37 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use atrium_api::types::string::Did;
 use dotenv::dotenv;
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
 
+use crate::services::database::{ClassificationOrder, PoolConfig, RetentionConfig};
+
+/// Shortest admin token accepted, so that it can't be guessed
+const MIN_ADMIN_TOKEN_LENGTH: usize = 16;
+
+#[derive(Clone)]
 pub struct Config {
     pub anthropic_api_key: String,
     pub database_url: String,
+    pub database_pool: PoolConfig,
+    pub database_read_url: Option<String>,
     pub feed_generator_did: Did,
     pub publisher_did: Did,
     pub feed_generator_hostname: String,
     pub metrics_enabled: bool,
+    pub classifier_concurrency: usize,
+    pub classifier_batch_size: usize,
+    pub classifier_message_batch_threshold: Option<usize>,
+    pub classifier_max_attempts: i32,
+    pub classifier_order: ClassificationOrder,
+    pub classifier_enriched_country: Option<String>,
+    pub bluesky_requests_per_second: f64,
+    pub anthropic_requests_per_second: f64,
+    pub labeler_host: Option<String>,
+    pub admin_token: Option<String>,
+    pub admin_secure_cookie: bool,
+    pub retention: RetentionConfig,
 }
 
 impl Config {
@@ -19,6 +41,8 @@ impl Config {
         Ok(Self {
             anthropic_api_key: env::var("ANTHROPIC_API_KEY")?,
             database_url: env::var("DATABASE_URL")?,
+            database_pool: load_pool_config()?,
+            database_read_url: parse_var("DATABASE_READ_URL")?,
             feed_generator_hostname: env::var("FEED_GENERATOR_HOSTNAME")?,
             feed_generator_did: format!("did:web:{}", env::var("FEED_GENERATOR_HOSTNAME")?)
                 .parse()
@@ -29,6 +53,106 @@ impl Config {
             metrics_enabled: env::var("METRICS_ENABLED")
                 .map(|v| v != "false")
                 .unwrap_or(true),
+            classifier_concurrency: parse_var_or("CLASSIFIER_CONCURRENCY", 4)?,
+            classifier_batch_size: parse_var_or("CLASSIFIER_BATCH_SIZE", 1)?,
+            classifier_message_batch_threshold: parse_var("CLASSIFIER_MESSAGE_BATCH_THRESHOLD")?,
+            classifier_max_attempts: parse_var_or("CLASSIFIER_MAX_ATTEMPTS", 5)?,
+            classifier_order: parse_var_or("CLASSIFIER_ORDER", ClassificationOrder::default())?,
+            classifier_enriched_country: parse_var("CLASSIFIER_ENRICHED_COUNTRY")?,
+            bluesky_requests_per_second: parse_rate_or("BLUESKY_REQUESTS_PER_SECOND", 5.0)?,
+            anthropic_requests_per_second: parse_rate_or("ANTHROPIC_REQUESTS_PER_SECOND", 1.0)?,
+            labeler_host: parse_var("LABELER_HOST")?,
+            admin_token: load_admin_token()?,
+            admin_secure_cookie: env::var("ADMIN_SECURE_COOKIE")
+                .map(|v| v != "false")
+                .unwrap_or(true),
+            retention: load_retention_config()?,
         })
     }
 }
+
+fn load_pool_config() -> Result<PoolConfig> {
+    let default = PoolConfig::default();
+
+    Ok(PoolConfig {
+        max_connections: parse_var_or("DATABASE_MAX_CONNECTIONS", default.max_connections)?,
+        acquire_timeout: parse_var("DATABASE_ACQUIRE_TIMEOUT_SECONDS")?
+            .map(Duration::from_secs)
+            .unwrap_or(default.acquire_timeout),
+        idle_timeout: parse_var("DATABASE_IDLE_TIMEOUT_SECONDS")?
+            .map(Duration::from_secs)
+            .or(default.idle_timeout),
+        statement_timeout: parse_var("DATABASE_STATEMENT_TIMEOUT_SECONDS")?
+            .map(Duration::from_secs)
+            .or(default.statement_timeout),
+    })
+}
+
+/// Leaves the admin API disabled if the token is empty, and refuses tokens
+/// too short to be hard to guess
+fn load_admin_token() -> Result<Option<String>> {
+    match parse_var::<String>("ADMIN_TOKEN")? {
+        Some(token) if token.is_empty() => Ok(None),
+        Some(token) if token.chars().count() < MIN_ADMIN_TOKEN_LENGTH => {
+            bail!("ADMIN_TOKEN must be at least {MIN_ADMIN_TOKEN_LENGTH} characters long")
+        }
+        token => Ok(token),
+    }
+}
+
+fn load_retention_config() -> Result<RetentionConfig> {
+    let mut config = RetentionConfig::default();
+
+    if let Ok(policies) = env::var("RETENTION_POLICIES") {
+        config
+            .parse_policies(&policies)
+            .with_context(|| format!("RETENTION_POLICIES has an invalid value: {policies:?}"))?;
+    }
+
+    config.batch_size = parse_var_or("JANITOR_BATCH_SIZE", config.batch_size)?;
+
+    if config.batch_size == 0 {
+        bail!("JANITOR_BATCH_SIZE must be at least 1");
+    }
+
+    config.dry_run = env::var("JANITOR_DRY_RUN")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+
+    Ok(config)
+}
+
+/// Parses a number of requests per second, which has to be positive for
+/// requests to be made at all
+fn parse_rate_or(name: &str, default: f64) -> Result<f64> {
+    let rate = parse_var_or(name, default)?;
+
+    if !(rate > 0.0 && rate.is_finite()) {
+        bail!("{name} must be a positive number, got {rate}");
+    }
+
+    Ok(rate)
+}
+
+fn parse_var<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(Into::into)
+            .with_context(|| format!("{name} has an invalid value: {value:?}")),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_var_or<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    Ok(parse_var(name)?.unwrap_or(default))
+}
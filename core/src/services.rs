@@ -2,6 +2,9 @@ mod ai;
 pub mod bluesky;
 pub mod database;
 
-pub use ai::AI;
+pub use ai::{
+    AI, BatchedProfile, ProfileDescription, RateLimited, ResidencyContext, ResidencyGuess,
+    is_valid_country_code,
+};
 pub use bluesky::Bluesky;
 pub use database::Database;
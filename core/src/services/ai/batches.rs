@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    AI, AnthropicRequest, AnthropicResponse, ProfileDescription, check_response,
+    country_of_living_request, extract_text, parse_country_code,
+};
+
+/// A profile submitted for classification through the Message Batches API,
+/// identified within the batch by its custom id
+pub struct BatchedProfile<'a> {
+    pub custom_id: String,
+    pub profile: ProfileDescription<'a>,
+}
+
+#[derive(Serialize)]
+struct BatchRequestItem<'a> {
+    custom_id: &'a str,
+    params: AnthropicRequest,
+}
+
+#[derive(Serialize)]
+struct CreateBatchRequest<'a> {
+    requests: Vec<BatchRequestItem<'a>>,
+}
+
+#[derive(Deserialize)]
+struct MessageBatch {
+    id: String,
+    processing_status: String,
+    results_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BatchResultLine {
+    custom_id: String,
+    result: BatchResult,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BatchResult {
+    Succeeded {
+        message: AnthropicResponse,
+    },
+    #[serde(other)]
+    Failed,
+}
+
+impl AI {
+    /// Submits profiles to be classified asynchronously, returning the batch id
+    pub async fn submit_countries_of_living_batch(
+        &self,
+        profiles: &[BatchedProfile<'_>],
+    ) -> Result<String> {
+        let request = CreateBatchRequest {
+            requests: profiles
+                .iter()
+                .map(|p| BatchRequestItem {
+                    custom_id: &p.custom_id,
+                    params: country_of_living_request(
+                        p.profile.display_name,
+                        p.profile.description,
+                    ),
+                })
+                .collect(),
+        };
+
+        let response = self
+            .request(Method::POST, &self.url("/v1/messages/batches"))
+            .json(&request)
+            .send()
+            .await?;
+
+        let batch = check_response(response)
+            .await?
+            .json::<MessageBatch>()
+            .await?;
+
+        Ok(batch.id)
+    }
+
+    /// Fetches results of a previously submitted batch.
+    ///
+    /// Returns `None` while the batch is still being processed, and a map from
+    /// custom id to country code once it has ended. Requests that failed, expired
+    /// or produced an invalid country code are left out of the map.
+    pub async fn fetch_countries_of_living_batch_results(
+        &self,
+        batch_id: &str,
+    ) -> Result<Option<HashMap<String, String>>> {
+        let response = self
+            .request(
+                Method::GET,
+                &self.url(&format!("/v1/messages/batches/{batch_id}")),
+            )
+            .send()
+            .await?;
+
+        let batch = check_response(response)
+            .await?
+            .json::<MessageBatch>()
+            .await?;
+
+        if batch.processing_status != "ended" {
+            return Ok(None);
+        }
+
+        let results_url = batch
+            .results_url
+            .ok_or_else(|| anyhow!("Batch {} has ended but has no results", batch.id))?;
+
+        let response = self.request(Method::GET, &results_url).send().await?;
+        let body = check_response(response).await?.text().await?;
+
+        parse_batch_results(&body).map(Some)
+    }
+}
+
+fn parse_batch_results(body: &str) -> Result<HashMap<String, String>> {
+    let mut countries = HashMap::new();
+
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let line: BatchResultLine = serde_json::from_str(line)?;
+
+        if let BatchResult::Succeeded { message } = line.result
+            && let Ok(country) = extract_text(message).and_then(|t| parse_country_code(&t))
+        {
+            countries.insert(line.custom_id, country);
+        }
+    }
+
+    Ok(countries)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{Path, State};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    async fn start_stub() -> String {
+        async fn create_batch(Json(body): Json<Value>) -> Json<Value> {
+            let requests = body["requests"].as_array().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert_eq!(requests[0]["custom_id"], "p0");
+            assert_eq!(requests[0]["params"]["model"], "claude-haiku-4-5-20251001");
+
+            Json(json!({"id": "msgbatch_ended", "processing_status": "in_progress"}))
+        }
+
+        async fn get_batch(State(base): State<String>, Path(id): Path<String>) -> Json<Value> {
+            if id == "msgbatch_ended" {
+                Json(json!({
+                    "id": id,
+                    "processing_status": "ended",
+                    "results_url": format!("{base}/v1/messages/batches/{id}/results"),
+                }))
+            } else {
+                Json(json!({"id": id, "processing_status": "in_progress", "results_url": null}))
+            }
+        }
+
+        async fn get_results() -> String {
+            [
+                json!({"custom_id": "p0", "result": {"type": "succeeded", "message": {"content": [{"type": "text", "text": "NL"}]}}}),
+                json!({"custom_id": "p1", "result": {"type": "succeeded", "message": {"content": [{"type": "text", "text": "zz"}]}}}),
+                json!({"custom_id": "p2", "result": {"type": "errored", "error": {"type": "overloaded_error"}}}),
+                json!({"custom_id": "p3", "result": {"type": "expired"}}),
+            ]
+            .map(|v| v.to_string())
+            .join("\n")
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let app = Router::new()
+            .route("/v1/messages/batches", post(create_batch))
+            .route("/v1/messages/batches/{id}", get(get_batch))
+            .route("/v1/messages/batches/{id}/results", get(get_results))
+            .with_state(base.clone());
+
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        base
+    }
+
+    fn batched(custom_id: &str) -> BatchedProfile<'_> {
+        BatchedProfile {
+            custom_id: custom_id.to_owned(),
+            profile: ProfileDescription {
+                did: "did:plc:test",
+                display_name: "Name",
+                description: "Bio",
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn submits_batch() {
+        let ai = AI::with_base_url("key", &start_stub().await);
+
+        let batch_id = ai
+            .submit_countries_of_living_batch(&[batched("p0"), batched("p1")])
+            .await
+            .unwrap();
+
+        assert_eq!(batch_id, "msgbatch_ended");
+    }
+
+    #[tokio::test]
+    async fn returns_nothing_for_batch_in_progress() {
+        let ai = AI::with_base_url("key", &start_stub().await);
+
+        let results = ai
+            .fetch_countries_of_living_batch_results("msgbatch_pending")
+            .await
+            .unwrap();
+
+        assert!(results.is_none());
+    }
+
+    #[tokio::test]
+    async fn returns_only_valid_results_for_ended_batch() {
+        let ai = AI::with_base_url("key", &start_stub().await);
+
+        let results = ai
+            .fetch_countries_of_living_batch_results("msgbatch_ended")
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results["p0"], "nl");
+    }
+}
@@ -1,8 +1,15 @@
 mod client;
+mod embeds;
 mod internals;
+mod labels;
+mod repo;
 mod streaming;
 
+pub use atrium_api::app::bsky::actor::profile::RecordData as ProfileRecordData;
 pub use client::Bluesky;
+pub use embeds::{EmbedKind, PostEmbed};
+pub use labels::{Label, LabelProcessor, LabelsDetails, self_labels_of};
+pub use repo::{RepoPost, read_posts_from_repo};
 pub use streaming::{
-    CommitDetails, CommitProcessor, FollowRecord, LikeRecord, Operation, PostRecord,
+    CommitDetails, CommitProcessor, FollowRecord, LikeRecord, Operation, PostRecord, RepostRecord,
 };
@@ -14,7 +14,8 @@ use tokio_tungstenite::{connect_async, tungstenite};
 
 use atrium_api::app::bsky::actor::profile::RecordData as ProfileRecordData;
 
-use super::streaming::{CommitProcessor, handle_message};
+use super::labels::{LabelProcessor, handle_labels_message};
+use super::streaming::{CommitProcessor, FollowRecord, handle_message};
 
 pub struct Bluesky {
     agent: AtpAgent<MemorySessionStore, ReqwestClient>,
@@ -125,6 +126,88 @@ impl Bluesky {
         Ok(Some(serde_ipld_dagcbor::from_slice(&bytes)?))
     }
 
+    /// Fetches DIDs of up to `limit` profiles that the given profile follows,
+    /// most recent follows first
+    pub async fn fetch_follows(&self, did: &str, limit: usize) -> Result<Vec<String>> {
+        use atrium_api::com::atproto::repo::list_records::ParametersData;
+
+        let mut follows = Vec::new();
+        let mut cursor = None;
+
+        while follows.len() < limit {
+            let result = self
+                .agent
+                .api
+                .com
+                .atproto
+                .repo
+                .list_records(
+                    ParametersData {
+                        collection: atrium_api::app::bsky::graph::Follow::nsid(),
+                        cursor,
+                        limit: Some(100.try_into().map_err(anyhow::Error::msg)?),
+                        repo: did.parse().map_err(anyhow::Error::msg)?,
+                        reverse: None,
+                    }
+                    .into(),
+                )
+                .await;
+
+            let output = match result {
+                Ok(output) => output,
+                Err(e) if is_missing_repo_error(&e) => break,
+                Err(e) => return Err(e.into()),
+            };
+
+            for record in &output.data.records {
+                let bytes = serde_ipld_dagcbor::to_vec(&record.value)?;
+                let follow: FollowRecord = serde_ipld_dagcbor::from_slice(&bytes)?;
+                follows.push(follow.subject.to_string());
+            }
+
+            cursor = match output.data.cursor {
+                Some(cursor) if !output.data.records.is_empty() => Some(cursor),
+                _ => break,
+            };
+        }
+
+        follows.truncate(limit);
+
+        Ok(follows)
+    }
+
+    /// Downloads the whole repository of the given profile as a CAR file.
+    /// Returns `None` if the repository is gone or unavailable.
+    pub async fn fetch_repo(&self, did: &str) -> Result<Option<Vec<u8>>> {
+        use atrium_api::com::atproto::sync::get_repo::ParametersData;
+        use atrium_xrpc::error::{Error, XrpcError, XrpcErrorKind};
+
+        let result = self
+            .agent
+            .api
+            .com
+            .atproto
+            .sync
+            .get_repo(
+                ParametersData {
+                    did: did.parse().map_err(anyhow::Error::msg)?,
+                    since: None,
+                }
+                .into(),
+            )
+            .await;
+
+        match result {
+            Ok(car) => Ok(Some(car)),
+            Err(Error::XrpcResponse(XrpcError {
+                error: Some(XrpcErrorKind::Custom(_)),
+                ..
+            })) => Ok(None),
+            Err(e) if is_missing_repo_error(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub async fn resolve_handle(&self, handle: &str) -> Result<Option<String>> {
         use atrium_api::com::atproto::identity::resolve_handle::ParametersData;
 
@@ -175,6 +258,35 @@ impl Bluesky {
 
         Ok(())
     }
+
+    /// Subscribes to labels published by the labeler at the given host,
+    /// e.g. `wss://mod.bsky.app`
+    pub async fn subscribe_to_labels<P: LabelProcessor>(
+        &self,
+        host: &str,
+        processor: &P,
+        cursor: Option<i64>,
+    ) -> Result<()> {
+        let url = match cursor {
+            Some(cursor) => format!(
+                "{}/xrpc/com.atproto.label.subscribeLabels?cursor={}",
+                host, cursor
+            ),
+            None => format!("{}/xrpc/com.atproto.label.subscribeLabels", host),
+        };
+
+        let (stream, _) = connect_async(url).await?;
+        let stream = stream.timeout(Self::STREAMING_TIMEOUT);
+        let mut stream = Box::pin(stream);
+
+        while let Some(Ok(tungstenite::Message::Binary(message))) = stream.try_next().await? {
+            if let Err(e) = handle_labels_message(&message, processor).await {
+                error!("Error handling a labels message: {:?}", e);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 fn is_missing_repo_error<T>(error: &atrium_xrpc::error::Error<T>) -> bool
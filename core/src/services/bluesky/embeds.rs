@@ -0,0 +1,121 @@
+use atrium_api::app::bsky::embed::record_with_media::MainMediaRefs;
+use atrium_api::app::bsky::feed::post::RecordEmbedRefs;
+use atrium_api::types::Union;
+
+use super::streaming::PostRecord;
+
+/// Kind of content embedded into a post, with quotes that also carry media
+/// counted as that media
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EmbedKind {
+    Images,
+    Video,
+    External,
+    Record,
+}
+
+impl EmbedKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Images => "images",
+            Self::Video => "video",
+            Self::External => "external",
+            Self::Record => "record",
+        }
+    }
+}
+
+/// What a post embeds, as far as filtering feeds is concerned
+#[derive(Debug, PartialEq, Eq)]
+pub struct PostEmbed<'a> {
+    pub kind: EmbedKind,
+    /// URI of the quoted record, if the post is a quote
+    pub quoted_uri: Option<&'a str>,
+}
+
+impl<'a> PostEmbed<'a> {
+    pub fn of(post: &'a PostRecord) -> Option<Self> {
+        let Some(Union::Refs(embed)) = &post.embed else {
+            return None;
+        };
+
+        Some(match embed {
+            RecordEmbedRefs::AppBskyEmbedImagesMain(_) => Self::media(EmbedKind::Images),
+            RecordEmbedRefs::AppBskyEmbedVideoMain(_) => Self::media(EmbedKind::Video),
+            RecordEmbedRefs::AppBskyEmbedExternalMain(_) => Self::media(EmbedKind::External),
+            RecordEmbedRefs::AppBskyEmbedRecordMain(quote) => Self {
+                kind: EmbedKind::Record,
+                quoted_uri: Some(&quote.record.uri),
+            },
+            RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(quote) => Self {
+                kind: match &quote.media {
+                    Union::Refs(MainMediaRefs::AppBskyEmbedImagesMain(_)) => EmbedKind::Images,
+                    Union::Refs(MainMediaRefs::AppBskyEmbedVideoMain(_)) => EmbedKind::Video,
+                    Union::Refs(MainMediaRefs::AppBskyEmbedExternalMain(_)) => EmbedKind::External,
+                    Union::Unknown(_) => EmbedKind::Record,
+                },
+                quoted_uri: Some(&quote.record.record.uri),
+            },
+        })
+    }
+
+    fn media(kind: EmbedKind) -> Self {
+        Self {
+            kind,
+            quoted_uri: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const QUOTED_URI: &str = "at://did:plc:someone/app.bsky.feed.post/3kquoted";
+    const QUOTED_CID: &str = "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm";
+
+    fn post_with_embed(embed: serde_json::Value) -> PostRecord {
+        serde_json::from_value(json!({
+            "$type": "app.bsky.feed.post",
+            "text": "Привет",
+            "createdAt": "2024-01-01T00:00:00.000Z",
+            "embed": embed,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn detects_plain_quote() {
+        let post = post_with_embed(json!({
+            "$type": "app.bsky.embed.record",
+            "record": {"uri": QUOTED_URI, "cid": QUOTED_CID},
+        }));
+
+        assert_eq!(
+            PostEmbed::of(&post),
+            Some(PostEmbed {
+                kind: EmbedKind::Record,
+                quoted_uri: Some(QUOTED_URI),
+            })
+        );
+    }
+
+    #[test]
+    fn counts_quote_with_media_as_media() {
+        let post = post_with_embed(json!({
+            "$type": "app.bsky.embed.recordWithMedia",
+            "record": {"record": {"uri": QUOTED_URI, "cid": QUOTED_CID}},
+            "media": {"$type": "app.bsky.embed.images", "images": []},
+        }));
+
+        assert_eq!(
+            PostEmbed::of(&post),
+            Some(PostEmbed {
+                kind: EmbedKind::Images,
+                quoted_uri: Some(QUOTED_URI),
+            })
+        );
+    }
+}
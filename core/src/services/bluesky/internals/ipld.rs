@@ -27,20 +27,20 @@ impl TryFrom<Ipld> for FrameHeader {
     type Error = anyhow::Error;
 
     fn try_from(value: Ipld) -> Result<Self, <FrameHeader as TryFrom<Ipld>>::Error> {
-        if let Ipld::Map(map) = value {
-            if let Some(Ipld::Integer(i)) = map.get("op") {
-                match i {
-                    1 => {
-                        let t = if let Some(Ipld::String(s)) = map.get("t") {
-                            Some(s.clone())
-                        } else {
-                            None
-                        };
-                        return Ok(FrameHeader::Message(t));
-                    }
-                    -1 => return Ok(FrameHeader::Error),
-                    _ => {}
+        if let Ipld::Map(map) = value
+            && let Some(Ipld::Integer(i)) = map.get("op")
+        {
+            match i {
+                1 => {
+                    let t = if let Some(Ipld::String(s)) = map.get("t") {
+                        Some(s.clone())
+                    } else {
+                        None
+                    };
+                    return Ok(FrameHeader::Message(t));
                 }
+                -1 => return Ok(FrameHeader::Error),
+                _ => {}
             }
         }
         Err(anyhow::anyhow!("invalid frame type"))
@@ -97,7 +97,7 @@ mod tests {
     use super::*;
 
     fn serialized_data(s: &str) -> Vec<u8> {
-        assert!(s.len() % 2 == 0);
+        assert!(s.len().is_multiple_of(2));
         let b2u = |b: u8| match b {
             b'0'..=b'9' => b - b'0',
             b'a'..=b'f' => b - b'a' + 10,
@@ -0,0 +1,184 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use atrium_api::app::bsky::feed::post::RecordLabelsRefs;
+use atrium_api::com::atproto::label::subscribe_labels::LabelsData;
+use atrium_api::types::Union;
+
+use super::internals::ipld::Frame;
+use super::streaming::PostRecord;
+
+pub type Label = atrium_api::com::atproto::label::defs::Label;
+
+#[async_trait]
+pub trait LabelProcessor {
+    async fn process_labels(&self, labels: &LabelsDetails) -> Result<()>;
+}
+
+pub struct LabelsDetails {
+    pub seq: i64,
+    pub labels: Vec<Label>,
+}
+
+pub async fn handle_labels_message<P: LabelProcessor>(message: &[u8], processor: &P) -> Result<()> {
+    let labels = match parse_labels_from_message(message)? {
+        Some(labels) => labels,
+        None => return Ok(()),
+    };
+
+    processor
+        .process_labels(&LabelsDetails {
+            seq: labels.seq,
+            labels: labels.labels,
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn parse_labels_from_message(message: &[u8]) -> Result<Option<LabelsData>> {
+    match Frame::try_from(message)? {
+        Frame::Message(Some(t), message) => {
+            if t == "#labels" {
+                Ok(Some(serde_ipld_dagcbor::from_slice(&message.body)?))
+            } else {
+                Ok(None)
+            }
+        }
+        Frame::Message(None, _) => Ok(None),
+        Frame::Error(err) => Err(anyhow!("Labeler sent an error frame: {err:?}")),
+    }
+}
+
+/// Returns values of the labels the author has put on their own post
+pub fn self_labels_of(post: &PostRecord) -> Vec<&str> {
+    match &post.labels {
+        Some(Union::Refs(RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels))) => {
+            labels.values.iter().map(|l| l.val.as_str()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use atrium_api::com::atproto::label::defs::LabelData;
+    use atrium_api::types::string::Datetime;
+    use futures_util::SinkExt;
+    use serde::Serialize;
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+    use crate::services::Bluesky;
+
+    #[derive(Default)]
+    struct RecordingProcessor {
+        received: Mutex<Vec<(i64, String, String)>>,
+    }
+
+    #[async_trait]
+    impl LabelProcessor for RecordingProcessor {
+        async fn process_labels(&self, labels: &LabelsDetails) -> Result<()> {
+            let mut received = self.received.lock().unwrap();
+
+            for label in &labels.labels {
+                received.push((labels.seq, label.uri.clone(), label.val.clone()));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Header {
+        op: i64,
+        t: &'static str,
+    }
+
+    fn frame(t: &'static str, body: &impl Serialize) -> Vec<u8> {
+        let mut frame = serde_ipld_dagcbor::to_vec(&Header { op: 1, t }).unwrap();
+        frame.extend(serde_ipld_dagcbor::to_vec(body).unwrap());
+        frame
+    }
+
+    /// Starts a stand-in labeler that sends a fixed set of frames to whoever
+    /// connects and then hangs up
+    async fn start_labeler(frames: Vec<Vec<u8>>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("ws://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut websocket = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+            for frame in frames {
+                websocket.send(Message::binary(frame)).await.unwrap();
+            }
+
+            websocket.close(None).await.unwrap();
+        });
+
+        host
+    }
+
+    #[tokio::test]
+    async fn receives_labels_from_labeler() {
+        let labels = LabelsData {
+            seq: 42,
+            labels: vec![
+                LabelData {
+                    cid: None,
+                    cts: Datetime::now(),
+                    exp: None,
+                    neg: None,
+                    sig: None,
+                    src: "did:plc:labeler".parse().unwrap(),
+                    uri: "at://did:plc:author/app.bsky.feed.post/3kpost".to_owned(),
+                    val: "porn".to_owned(),
+                    ver: Some(1),
+                }
+                .into(),
+            ],
+        };
+
+        let host = start_labeler(vec![
+            frame("#info", &json!({"name": "OutdatedCursor"})),
+            frame("#labels", &labels),
+        ])
+        .await;
+
+        let processor = RecordingProcessor::default();
+
+        Bluesky::unauthenticated()
+            .subscribe_to_labels(&host, &processor, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *processor.received.lock().unwrap(),
+            [(
+                42,
+                "at://did:plc:author/app.bsky.feed.post/3kpost".to_owned(),
+                "porn".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn reads_self_labels() {
+        let post: PostRecord = serde_json::from_value(json!({
+            "$type": "app.bsky.feed.post",
+            "text": "Привет",
+            "createdAt": "2024-01-01T00:00:00.000Z",
+            "labels": {
+                "$type": "com.atproto.label.defs#selfLabels",
+                "values": [{"val": "graphic-media"}],
+            },
+        }))
+        .unwrap();
+
+        assert_eq!(self_labels_of(&post), ["graphic-media"]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result, anyhow};
+use atrium_api::app::bsky::feed::Post;
+use atrium_api::types::Collection;
+use ipld_core::cid::Cid;
+use log::warn;
+use serde::Deserialize;
+
+use super::internals::cbor::read_record;
+use super::streaming::PostRecord;
+
+/// A post read out of an exported repository
+#[derive(Debug)]
+pub struct RepoPost {
+    pub author_did: String,
+    pub cid: String,
+    pub uri: String,
+    pub post: PostRecord,
+}
+
+#[derive(Deserialize)]
+struct SignedCommit {
+    did: String,
+    data: Cid,
+}
+
+/// A node of the Merkle Search Tree that maps record paths to record CIDs
+#[derive(Deserialize)]
+struct TreeNode {
+    l: Option<Cid>,
+    e: Vec<TreeEntry>,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    /// How many bytes of the previous key in the node this key starts with
+    p: usize,
+    /// The rest of the key
+    #[serde(with = "serde_bytes")]
+    k: Vec<u8>,
+    v: Cid,
+    t: Option<Cid>,
+}
+
+/// Reads all posts out of a repository exported as a CAR file, like the
+/// ones served by `com.atproto.sync.getRepo`. Posts whose blocks are missing
+/// from the export or that can't be decoded are skipped.
+pub async fn read_posts_from_repo(car: &[u8]) -> Result<Vec<RepoPost>> {
+    let (blocks, header) = rs_car::car_read_all(&mut &car[..], true).await?;
+    let blocks: HashMap<_, _> = blocks.into_iter().collect();
+
+    let root = header
+        .roots
+        .first()
+        .context("Repository export has no root")?;
+    let commit: SignedCommit = read_record(block(&blocks, root)?)?;
+
+    let mut records = Vec::new();
+    walk_tree(&blocks, &commit.data, &mut records)?;
+
+    let mut posts = Vec::new();
+
+    for (path, cid) in records {
+        let is_post = path
+            .split_once('/')
+            .is_some_and(|(collection, _)| collection == Post::NSID);
+
+        if !is_post {
+            continue;
+        }
+
+        let Some(block) = blocks.get(&cid) else {
+            continue;
+        };
+
+        let uri = format!("at://{}/{}", commit.did, path);
+
+        match read_record(block) {
+            Ok(post) => posts.push(RepoPost {
+                author_did: commit.did.clone(),
+                cid: cid.to_string(),
+                uri,
+                post,
+            }),
+            Err(e) => warn!("Skipping post {uri} that could not be decoded: {e:?}"),
+        }
+    }
+
+    Ok(posts)
+}
+
+/// Collects paths and CIDs of all records under the given node of the tree
+fn walk_tree(
+    blocks: &HashMap<Cid, Vec<u8>>,
+    node: &Cid,
+    records: &mut Vec<(String, Cid)>,
+) -> Result<()> {
+    let node: TreeNode = read_record(block(blocks, node)?)?;
+
+    if let Some(left) = &node.l {
+        walk_tree(blocks, left, records)?;
+    }
+
+    let mut key = Vec::new();
+
+    for entry in node.e {
+        if entry.p > key.len() {
+            return Err(anyhow!("Malformed tree entry in repository export"));
+        }
+
+        key.truncate(entry.p);
+        key.extend_from_slice(&entry.k);
+
+        records.push((String::from_utf8(key.clone())?, entry.v));
+
+        if let Some(right) = &entry.t {
+            walk_tree(blocks, right, records)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn block<'a>(blocks: &'a HashMap<Cid, Vec<u8>>, cid: &Cid) -> Result<&'a [u8]> {
+    blocks
+        .get(cid)
+        .map(Vec::as_slice)
+        .with_context(|| format!("Block {cid} is missing from repository export"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repository with two posts and a like, the latter in a subtree
+    const FIXTURE: &[u8] = include_bytes!("../../../fixtures/repo.car");
+
+    #[tokio::test]
+    async fn reads_posts_from_repository_export() {
+        let posts = read_posts_from_repo(FIXTURE).await.unwrap();
+
+        assert_eq!(
+            posts
+                .iter()
+                .map(|p| (p.uri.as_str(), p.post.text.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "at://did:plc:fixture/app.bsky.feed.post/3kccccccccccc",
+                    "Goedemorgen allemaal"
+                ),
+                (
+                    "at://did:plc:fixture/app.bsky.feed.post/3kddddddddddd",
+                    "Привет из Амстердама"
+                ),
+            ]
+        );
+        assert!(posts.iter().all(|p| p.author_did == "did:plc:fixture"));
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 use async_trait::async_trait;
-use atrium_api::app::bsky::feed::{Like, Post};
+use atrium_api::app::bsky::feed::{Like, Post, Repost};
 use atrium_api::app::bsky::graph::Follow;
 use atrium_api::com::atproto::sync::subscribe_repos::Commit;
 use atrium_api::types::Collection;
@@ -13,6 +13,7 @@ use super::internals::ipld::Frame;
 
 pub type PostRecord = <Post as Collection>::Record;
 pub type LikeRecord = <Like as Collection>::Record;
+pub type RepostRecord = <Repost as Collection>::Record;
 pub type FollowRecord = <Follow as Collection>::Record;
 
 const ACTION_CREATE: &str = "create";
@@ -30,6 +31,7 @@ pub struct CommitDetails {
 }
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Operation {
     CreatePost {
         author_did: String,
@@ -43,6 +45,12 @@ pub enum Operation {
         uri: String,
         like: LikeRecord,
     },
+    CreateRepost {
+        author_did: String,
+        cid: String,
+        uri: String,
+        repost: RepostRecord,
+    },
     CreateFollow {
         author_did: String,
         cid: String,
@@ -55,6 +63,9 @@ pub enum Operation {
     DeleteLike {
         uri: String,
     },
+    DeleteRepost {
+        uri: String,
+    },
     DeleteFollow {
         uri: String,
     },
@@ -140,6 +151,16 @@ async fn extract_operations(commit: &Commit) -> Result<Vec<Operation>> {
                             like,
                         }
                     }
+                    atrium_api::app::bsky::feed::Repost::NSID => {
+                        let repost: RepostRecord = read_record(block)?;
+
+                        Operation::CreateRepost {
+                            author_did: commit.repo.to_string(),
+                            cid: cid.to_string(),
+                            uri,
+                            repost,
+                        }
+                    }
                     atrium_api::app::bsky::graph::Follow::NSID => {
                         let follow: FollowRecord = read_record(block)?;
 
@@ -156,6 +177,7 @@ async fn extract_operations(commit: &Commit) -> Result<Vec<Operation>> {
             ACTION_DELETE => match collection {
                 atrium_api::app::bsky::feed::Post::NSID => Operation::DeletePost { uri },
                 atrium_api::app::bsky::feed::Like::NSID => Operation::DeleteLike { uri },
+                atrium_api::app::bsky::feed::Repost::NSID => Operation::DeleteRepost { uri },
                 atrium_api::app::bsky::graph::Follow::NSID => Operation::DeleteFollow { uri },
                 _ => continue,
             },
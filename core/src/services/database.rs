@@ -1,314 +1,1077 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use scooby::postgres::{
-    Aliasable, Joinable, Orderable, Parameters, delete_from, insert_into, select, update,
-};
-use sqlx::Row;
-use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
-use sqlx::query;
+use std::iter;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+
+use self::replica::Replica;
+use super::bluesky::EmbedKind;
+
+mod memory;
+mod migrations;
+mod pool;
+mod postgres;
+mod replica;
+mod retention;
+mod sqlite;
+
+pub use memory::InMemoryStorage;
+pub use postgres::PostgresStorage;
+pub use retention::{PostTimestamp, RetentionConfig, RetentionPolicy, RetentionScope};
+pub use sqlite::SqliteStorage;
 
 pub struct Post {
     pub created_at: DateTime<Utc>,
     pub author_did: String,
     pub cid: String,
     pub uri: String,
+    /// Labels currently applied to the post. Only filled in when fetching
+    /// posts for feeds that mark labeled content.
+    pub labels: Vec<String>,
 }
 
-pub struct Database {
-    connection_pool: PgPool,
+pub struct Repost {
+    pub created_at: DateTime<Utc>,
+    pub author_did: String,
+    pub cid: String,
+    pub uri: String,
+    pub post_uri: String,
 }
 
-impl Database {
-    pub async fn connect(url: &str) -> Result<Self> {
-        Ok(Self {
-            connection_pool: PgPoolOptions::new().max_connections(5).connect(url).await?,
-        })
+pub struct NewPost<'a> {
+    pub author_did: &'a str,
+    pub cid: &'a str,
+    pub uri: &'a str,
+    pub created_at: DateTime<Utc>,
+    pub text: &'a str,
+    pub language: Option<&'a str>,
+    pub reply_parent_uri: Option<&'a str>,
+    pub reply_root_uri: Option<&'a str>,
+    pub embed_kind: Option<EmbedKind>,
+    pub quoted_uri: Option<&'a str>,
+}
+
+/// Where a profile lives, as far as one country goes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Residency {
+    /// The profile is waiting to be classified
+    Unclassified,
+    InCountry,
+    Elsewhere,
+}
+
+impl Residency {
+    /// Tells where a known profile lives based on its classification state.
+    /// Profiles the classifier gave up on are treated as unknown.
+    fn of(
+        has_been_processed: bool,
+        has_failed_classification: bool,
+        likely_country_of_living: Option<&str>,
+        country: &str,
+    ) -> Option<Self> {
+        match (has_been_processed, has_failed_classification) {
+            (true, _) if likely_country_of_living == Some(country) => Some(Self::InCountry),
+            (true, _) => Some(Self::Elsewhere),
+            (false, false) => Some(Self::Unclassified),
+            (false, true) => None,
+        }
+    }
+}
+
+/// Order in which unprocessed profiles are handed out for classification
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClassificationOrder {
+    /// Oldest profiles first
+    #[default]
+    FirstSeen,
+    /// Profiles with the most posts waiting for them to be classified first,
+    /// since those are the ones holding back the most posts from feeds
+    PostActivity,
+}
+
+impl FromStr for ClassificationOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first_seen" => Ok(Self::FirstSeen),
+            "post_activity" => Ok(Self::PostActivity),
+            _ => Err(anyhow!(
+                "Unknown classification order {s:?}, expected first_seen or post_activity"
+            )),
+        }
+    }
+}
+
+/// Which replies to serve when fetching posts for a feed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ReplyPolicy {
+    /// Serve replies like any other post
+    #[default]
+    Show,
+    /// Only serve posts that aren't replies
+    Hide,
+    /// Only serve replies to posts by authors from the same country, leaving
+    /// out replies deep in threads of people outside of the feed
+    OnlyToFeedAuthors,
+    /// Only serve the latest post of each thread
+    CollapseThreads,
+}
+
+/// Which posts to serve when fetching them for a feed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PostFilters {
+    pub replies: ReplyPolicy,
+    /// Only serve posts embedding one of these kinds of content. Posts with
+    /// any embed or none at all are served if this is empty.
+    pub embed_kinds: Vec<EmbedKind>,
+    /// Leave out posts quoting posts by authors from other countries
+    pub hide_quotes_from_outside: bool,
+    /// Leave out posts carrying any of these labels
+    pub hidden_labels: Vec<String>,
+    /// Fetch labels of the posts, so that feeds can mark labeled content
+    pub mark_labeled: bool,
+}
+
+/// A profile along with what it was classified as, for reviewing by hand
+pub struct ClassifiedProfile {
+    pub did: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub likely_country_of_living: Option<String>,
+    pub residency_probability: Option<f64>,
+    pub classified_at: DateTime<Utc>,
+}
+
+/// A post along with its contents, for reviewing by hand
+pub struct PostPreview {
+    pub uri: String,
+    pub author_did: String,
+    pub author_display_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub text: Option<String>,
+    pub language: Option<String>,
+    pub like_count: i32,
+}
+
+/// Everything stored about a profile, for inspecting it by hand
+pub struct ProfileDetails {
+    pub did: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub has_been_processed: bool,
+    pub likely_country_of_living: Option<String>,
+    pub residency_probability: Option<f64>,
+    pub classification_attempts: i32,
+    pub next_classification_attempt_at: Option<DateTime<Utc>>,
+    pub has_failed_classification: bool,
+    pub last_classification_error: Option<String>,
+    pub post_count: i64,
+    pub is_banned: bool,
+}
+
+/// Whether a ban applies to everything by a profile, or a single post
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BanKind {
+    Profile,
+    Post,
+}
+
+impl BanKind {
+    fn table(&self) -> &'static str {
+        match self {
+            Self::Profile => "BannedProfile",
+            Self::Post => "BannedPost",
+        }
+    }
+
+    fn subject_column(&self) -> &'static str {
+        match self {
+            Self::Profile => "did",
+            Self::Post => "uri",
+        }
+    }
+
+    fn action(&self, banned: bool) -> &'static str {
+        match (self, banned) {
+            (Self::Profile, true) => "ban_profile",
+            (Self::Profile, false) => "unban_profile",
+            (Self::Post, true) => "ban_post",
+            (Self::Post, false) => "unban_post",
+        }
+    }
+}
+
+/// A profile DID or a post URI that is kept out of feeds
+pub struct Ban {
+    pub kind: BanKind,
+    pub subject: String,
+    pub reason: String,
+    pub banned_at: DateTime<Utc>,
+}
+
+/// An entry of the moderation audit log
+pub struct ModerationLogEntry {
+    pub performed_at: DateTime<Utc>,
+    pub action: String,
+    pub subject: String,
+    pub reason: Option<String>,
+    pub moderator: Option<String>,
+}
+
+/// Where the database schema stands compared to the migrations this build knows about
+pub struct SchemaStatus {
+    /// Latest migration applied to the database, if any
+    pub current_version: Option<i64>,
+    /// Latest migration this build knows about
+    pub expected_version: i64,
+    /// How many migrations are yet to be applied
+    pub pending: usize,
+}
+
+/// How the connection pool to the database is sized and how long things
+/// are allowed to take. Every process gets one pool, so the parts of the
+/// all-in-one process share theirs.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    /// How long to wait for a connection to free up before giving up
+    pub acquire_timeout: Duration,
+    /// How long a connection can sit unused before it gets closed
+    pub idle_timeout: Option<Duration>,
+    /// How long a single statement can run before the database cancels it.
+    /// Only applies to Postgres.
+    pub statement_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            statement_timeout: None,
+        }
+    }
+}
+
+/// Snapshot of how busy the connection pool is
+pub struct PoolStatus {
+    pub max_connections: u32,
+    pub in_use: u32,
+    pub idle: u32,
+}
+
+/// Everything the processes store and look up. Implemented on top of
+/// Postgres for actual deployments, SQLite for small ones, and in memory
+/// for tests.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Brings the schema up to date, refusing to touch a database whose
+    /// schema this build doesn't recognize
+    async fn migrate(&self) -> Result<SchemaStatus>;
+
+    /// Checks that the schema is one this build can work with and reports
+    /// how far behind it is
+    async fn schema_status(&self) -> Result<SchemaStatus>;
+
+    /// Records migrations up to the given version as applied without running
+    /// them, for databases set up before migrations were tracked
+    async fn baseline_schema(&self, up_to: i64) -> Result<usize>;
+
+    /// Tells how busy the connection pool is, if there is one
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
     }
 
-    pub async fn insert_post(
+    /// Stores a post. Returns false if it was already stored.
+    async fn insert_post(&self, post: &NewPost<'_>) -> Result<bool>;
+
+    async fn fetch_recent_post_texts(&self, author_did: &str, limit: usize) -> Result<Vec<String>>;
+
+    /// Returns how many of the author's indexed posts are in each language
+    async fn fetch_post_language_counts(&self, author_did: &str) -> Result<Vec<(String, i64)>>;
+
+    async fn fetch_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>>;
+
+    /// Fetches recent posts by authors from the given country, ranked by likes
+    /// decaying with age the way Hacker News does it.
+    ///
+    /// Scores are computed as of the given moment rather than the current one,
+    /// so that paging through the results with an offset stays consistent.
+    async fn fetch_top_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        offset: usize,
+        as_of: DateTime<Utc>,
+        max_age: TimeDelta,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>>;
+
+    /// Deletes a post, whether it has been indexed or is still pending
+    async fn delete_post(&self, uri: &str) -> Result<bool>;
+
+    /// Counts posts in the scope that the policy says should be deleted as of
+    /// the given moment, without deleting them
+    async fn count_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<u64>;
+
+    /// Deletes up to `limit` of the posts in the scope that the policy says
+    /// should be deleted as of the given moment, oldest first
+    async fn delete_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<u64>;
+
+    /// Holds on to a post by a profile that hasn't been classified yet, along
+    /// with the labels its author put on it. The post gets indexed once the
+    /// profile is classified as living in `awaited_country`, and dropped if
+    /// it's classified as living anywhere else. Returns false if the post
+    /// was pending already, or if the profile has been classified in the
+    /// meantime and the post should be judged again.
+    async fn insert_pending_post(
+        &self,
+        post: &NewPost<'_>,
+        self_labels: &[&str],
+        awaited_country: &str,
+    ) -> Result<bool>;
+
+    /// Drops pending posts whose authors haven't been classified in time,
+    /// e.g. because the classifier gave up on them
+    async fn delete_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64>;
+
+    /// Counts pending posts that `delete_old_pending_posts` would drop,
+    /// without dropping them
+    async fn count_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64>;
+
+    /// Stores a repost, but only if it was made by a profile known to live in
+    /// the given country. Returns true if the repost was stored.
+    async fn insert_repost_if_relevant(
         &self,
         author_did: &str,
         cid: &str,
         uri: &str,
+        post_uri: &str,
         created_at: DateTime<Utc>,
-    ) -> Result<()> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &insert_into("Post")
-                .columns(("author_did", "cid", "uri", "created_at"))
-                .values([params.next_array()])
-                .to_string(),
-        )
-        .bind(author_did)
-        .bind(cid)
-        .bind(uri)
-        .bind(created_at)
-        .execute(&self.connection_pool)
-        .await
-        .map(|_| ())?)
-    }
+        reposter_country: &str,
+    ) -> Result<bool>;
 
-    pub async fn fetch_posts_by_authors_country(
+    async fn fetch_reposts_by_reposters_country(
         &self,
-        author_country: &str,
+        reposter_country: &str,
         limit: usize,
         earlier_than: Option<(DateTime<Utc>, &str)>,
-    ) -> Result<Vec<Post>> {
-        let mut params = Parameters::new();
-        let mut sql_builder = select(("p.created_at", "p.author_did", "p.cid", "p.uri"))
-            .from(
-                "Post"
-                    .as_("p")
-                    .inner_join("Profile".as_("pr"))
-                    .on("pr.did = p.author_did"),
-            )
-            .where_(format!("pr.likely_country_of_living = {}", params.next()))
-            .order_by(("p.created_at".desc(), "p.cid".desc()))
-            .limit(limit);
-
-        if earlier_than.is_some() {
-            sql_builder = sql_builder
-                .where_(format!("p.created_at <= {}", params.next()))
-                .where_(format!("p.cid < {}", params.next()));
-        }
+    ) -> Result<Vec<Repost>>;
 
-        let sql_string = sql_builder.to_string();
+    async fn delete_repost(&self, uri: &str) -> Result<bool>;
 
-        let mut query_object = query(&sql_string).bind(author_country);
+    /// Stores a label, but only if it's for a post we've indexed.
+    /// Returns true if the label was stored.
+    async fn insert_label_if_relevant(
+        &self,
+        uri: &str,
+        src: &str,
+        value: &str,
+        created_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<bool>;
 
-        if let Some((last_created_at, last_cid)) = earlier_than {
-            query_object = query_object.bind(last_created_at).bind(last_cid);
+    /// Removes a label, as labelers do by publishing a negation of it
+    async fn delete_label(&self, uri: &str, src: &str, value: &str) -> Result<bool>;
+
+    /// Stores a like, but only if it's for a post we've indexed, and bumps the
+    /// like count of that post. Returns true if the like was stored.
+    async fn insert_like_if_relevant(
+        &self,
+        author_did: &str,
+        post_uri: &str,
+        uri: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<bool>;
+
+    /// Deletes a like and lowers the like count of the post it was for.
+    /// Returns true if the like was known.
+    async fn delete_like(&self, uri: &str) -> Result<bool>;
+
+    /// Returns DIDs of authors of indexed posts, the only ones whose posts
+    /// can have likes stored
+    async fn fetch_post_author_dids(&self) -> Result<Vec<String>>;
+
+    /// Returns DIDs of profiles that have likes stored
+    async fn fetch_liker_dids(&self) -> Result<Vec<String>>;
+
+    /// Stores a follow, but only if either side of it is a profile we know of.
+    /// Returns true if the follow was stored.
+    async fn insert_follow_if_relevant(
+        &self,
+        author_did: &str,
+        subject_did: &str,
+        uri: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<bool>;
+
+    async fn delete_follow(&self, uri: &str) -> Result<bool>;
+
+    /// Returns DIDs of profiles that the given profile follows, as seen on the
+    /// firehose
+    async fn fetch_followed_dids(&self, did: &str) -> Result<Vec<String>>;
+
+    /// Returns how many followers of the given profile have been classified,
+    /// and how many of those were classified as living in the given country
+    async fn count_classified_followers(&self, did: &str, country: &str) -> Result<(i64, i64)>;
+
+    async fn insert_profile_if_it_doesnt_exist(&self, did: &str) -> Result<bool>;
+
+    /// Claims the next page of up to `limit` profiles that are due for
+    /// classification, in the given order.
+    ///
+    /// Claimed profiles are not handed out again until `lease` runs out, so that
+    /// several classifiers can work through the queue at the same time.
+    async fn claim_unprocessed_profile_dids(
+        &self,
+        limit: usize,
+        lease: TimeDelta,
+        order: ClassificationOrder,
+    ) -> Result<Vec<String>>;
+
+    async fn count_unprocessed_profiles(&self) -> Result<i64>;
+
+    /// Records a failed classification attempt, scheduling the next one with
+    /// exponential backoff. Returns true if the profile has run out of attempts
+    /// and won't be retried anymore.
+    async fn record_profile_classification_failure(
+        &self,
+        did: &str,
+        error: &str,
+        max_attempts: i32,
+        backoff: TimeDelta,
+    ) -> Result<bool>;
+
+    async fn create_classification_batch(
+        &self,
+        batch_id: &str,
+        profiles: &[(String, String)],
+    ) -> Result<()>;
+
+    /// Returns ids of batches yet to be collected along with when they were
+    /// submitted, oldest first
+    async fn fetch_unfinished_classification_batches(&self)
+    -> Result<Vec<(String, DateTime<Utc>)>>;
+
+    /// Returns pairs of custom id and DID for profiles submitted in a batch
+    async fn fetch_classification_batch_profiles(
+        &self,
+        batch_id: &str,
+    ) -> Result<Vec<(String, String)>>;
+
+    async fn finish_classification_batch(&self, batch_id: &str) -> Result<bool>;
+
+    /// Stores the country the profile was classified as living in, indexing
+    /// its pending posts that were waiting for that country and dropping the
+    /// rest of them
+    async fn store_profile_details(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        residency_probability: Option<f64>,
+    ) -> Result<bool>;
+
+    /// Same as `store_profile_details`, but for countries set by hand
+    async fn force_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+    ) -> Result<bool>;
+
+    /// Same as `force_profile_country`, recording the override in the
+    /// moderation log
+    async fn override_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        moderator: Option<&str>,
+    ) -> Result<()>;
+
+    /// Remembers the name and bio the profile had when it was last fetched
+    async fn store_profile_description(
+        &self,
+        did: &str,
+        display_name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<bool>;
+
+    /// Fetches the most recently classified profiles, optionally only those
+    /// classified as living in the given country
+    async fn fetch_recently_classified_profiles(
+        &self,
+        country: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ClassifiedProfile>>;
+
+    /// Fetches the latest posts by authors from the given country, as they'd
+    /// appear in the chronological feed before any filtering
+    async fn fetch_recent_post_previews(
+        &self,
+        author_country: &str,
+        limit: usize,
+    ) -> Result<Vec<PostPreview>>;
+
+    async fn fetch_profile_details(&self, did: &str) -> Result<Option<ProfileDetails>>;
+
+    /// Puts a profile back into the classification queue, as if it had just
+    /// been seen for the first time. Returns false if there's no such profile.
+    async fn reset_profile_classification(&self, did: &str) -> Result<bool>;
+
+    /// Tells whether the profile lives in the given country. Returns `None`
+    /// for profiles we haven't seen before.
+    async fn fetch_profile_residency(&self, did: &str, country: &str) -> Result<Option<Residency>>;
+
+    async fn count_posts(&self) -> Result<i64>;
+
+    /// Returns how many of the given profiles have been classified, and how many
+    /// of those were classified as living in the given country
+    async fn count_classified_profiles_among(
+        &self,
+        dids: &[String],
+        country: &str,
+    ) -> Result<(i64, i64)>;
+
+    /// Counts profiles that ran out of classification attempts
+    async fn count_failed_profiles(&self) -> Result<i64>;
+
+    async fn count_profiles_in_country(&self, country: &str) -> Result<i64>;
+
+    /// Returns DIDs of all profiles classified as living in the given country
+    async fn fetch_profile_dids_in_country(&self, country: &str) -> Result<Vec<String>>;
+
+    /// Bans a profile or a post, recording it in the moderation log.
+    /// Returns false if it was already banned.
+    async fn ban(
+        &self,
+        kind: BanKind,
+        subject: &str,
+        reason: &str,
+        moderator: Option<&str>,
+    ) -> Result<bool>;
+
+    /// Lifts a ban, recording it in the moderation log.
+    /// Returns false if there was no such ban.
+    async fn unban(&self, kind: BanKind, subject: &str, moderator: Option<&str>) -> Result<bool>;
+
+    async fn fetch_bans(&self, kind: BanKind) -> Result<Vec<Ban>>;
+
+    /// Returns true if either the author or the post itself has been banned
+    async fn is_post_banned(&self, author_did: &str, uri: &str) -> Result<bool>;
+
+    /// Records a moderation action that isn't a ban, e.g. a manual override
+    /// of a profile's country
+    async fn record_moderation_action(
+        &self,
+        action: &str,
+        subject: &str,
+        reason: Option<&str>,
+        moderator: Option<&str>,
+    ) -> Result<()>;
+
+    async fn fetch_moderation_log(&self, limit: usize) -> Result<Vec<ModerationLogEntry>>;
+
+    async fn fetch_subscription_cursor(&self, host: &str, did: &str) -> Result<Option<i64>>;
+
+    async fn create_subscription_state(&self, host: &str, did: &str) -> Result<bool>;
+
+    async fn update_subscription_cursor(&self, host: &str, did: &str, cursor: i64) -> Result<bool>;
+}
+
+/// Handle to whichever storage the processes were configured with.
+///
+/// Queries serving feeds and stats go to the read replica if there is one,
+/// and everything else goes to the primary storage.
+pub struct Database {
+    storage: Box<dyn Storage>,
+    replica: Option<Replica>,
+}
+
+impl Database {
+    const POOL_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+    /// Connects to the database behind the URL, picking the storage by its
+    /// scheme: `sqlite:` for a SQLite file and Postgres for anything else
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::connect_with_pool(url, &PoolConfig::default()).await
+    }
+
+    pub async fn connect_with_pool(url: &str, pool: &PoolConfig) -> Result<Self> {
+        if url.starts_with("sqlite:") {
+            Ok(Self::new(SqliteStorage::connect(url, pool).await?))
+        } else {
+            Ok(Self::new(PostgresStorage::connect(url, pool).await?))
         }
+    }
 
-        Ok(query_object
-            .map(|r: PgRow| Post {
-                created_at: r.get("created_at"),
-                author_did: r.get("author_did"),
-                cid: r.get("cid"),
-                uri: r.get("uri"),
-            })
-            .fetch_all(&self.connection_pool)
-            .await?)
+    pub fn new(storage: impl Storage + 'static) -> Self {
+        Self {
+            storage: Box::new(storage),
+            replica: None,
+        }
     }
 
-    pub async fn delete_post(&self, uri: &str) -> Result<bool> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &delete_from("Post")
-                .where_(format!("uri = {}", params.next()))
-                .to_string(),
-        )
-        .bind(uri)
-        .execute(&self.connection_pool)
-        .await
-        .map(|result| result.rows_affected() > 0)?)
+    /// Sends feed and stats queries to the given storage, falling back to the
+    /// primary one while it's failing
+    pub fn with_replica(self, replica: impl Storage + 'static) -> Self {
+        Self {
+            replica: Some(Replica::new(Box::new(replica))),
+            ..self
+        }
     }
 
-    pub async fn delete_old_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &delete_from("Post")
-                .where_(format!("indexed_at < {}", params.next()))
-                .to_string(),
-        )
-        .bind(earlier_than)
-        .execute(&self.connection_pool)
-        .await
-        .map(|result| result.rows_affected())?)
+    /// Sends feed and stats queries to the Postgres database behind the URL,
+    /// which is only connected to once it's needed so that it being down
+    /// doesn't keep the process from starting
+    pub fn with_replica_url(self, url: &str, pool: &PoolConfig) -> Result<Self> {
+        if url.starts_with("sqlite:") {
+            return Err(anyhow!("Read replicas are only supported for PostgreSQL"));
+        }
+
+        // Don't keep requests waiting long on a replica that's down, since
+        // they can be served by the primary database instead
+        let pool = PoolConfig {
+            acquire_timeout: pool.acquire_timeout.min(Replica::ACQUIRE_TIMEOUT),
+            ..pool.clone()
+        };
+
+        Ok(self.with_replica(PostgresStorage::connect_lazy(url, &pool)?))
     }
 
-    pub async fn insert_profile_if_it_doesnt_exist(&self, did: &str) -> Result<bool> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &insert_into("Profile")
-                .columns(("did",))
-                .values([params.next()])
-                .on_conflict()
-                .do_nothing()
-                .to_string(),
-        )
-        .bind(did)
-        .execute(&self.connection_pool)
-        .await
-        .map(|result| result.rows_affected() > 0)?)
+    /// Creates an empty database that lives only as long as the process,
+    /// for testing things that use it
+    pub fn in_memory() -> Self {
+        Self::new(InMemoryStorage::default())
     }
 
-    pub async fn fetch_unprocessed_profile_dids(&self) -> Result<Vec<String>> {
-        Ok(query(
-            &select("did")
-                .from("Profile")
-                .where_("has_been_processed = FALSE")
-                .to_string(),
-        )
-        .map(|r: PgRow| r.get(0))
-        .fetch_all(&self.connection_pool)
-        .await?)
+    /// Keeps exporting metrics on how busy the connection pools are, for as
+    /// long as the process runs
+    pub async fn report_pool_metrics(self: Arc<Self>) {
+        loop {
+            let statuses = self.pool_statuses();
+
+            if statuses.is_empty() {
+                return;
+            }
+
+            for (pool, status) in statuses {
+                metrics::gauge!("database_pool_max_connections", "pool" => pool)
+                    .set(status.max_connections as f64);
+                metrics::gauge!(
+                    "database_pool_connections",
+                    "pool" => pool,
+                    "state" => "in_use"
+                )
+                .set(status.in_use as f64);
+                metrics::gauge!(
+                    "database_pool_connections",
+                    "pool" => pool,
+                    "state" => "idle"
+                )
+                .set(status.idle as f64);
+            }
+
+            tokio::time::sleep(Self::POOL_METRICS_INTERVAL).await;
+        }
     }
 
-    pub async fn store_profile_details(
-        &self,
-        did: &str,
-        likely_country_of_living: &str,
-    ) -> Result<bool> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &update("Profile")
-                .set("has_been_processed", "TRUE")
-                .set("likely_country_of_living", params.next())
-                .where_(format!("did = {}", params.next()))
-                .to_string(),
-        )
-        .bind(likely_country_of_living)
-        .bind(did)
-        .execute(&self.connection_pool)
-        .await
-        .map(|result| result.rows_affected() > 0)?)
+    /// Tells how busy the connection pools of the primary database and the
+    /// replica are, leaving out storages without a pool
+    fn pool_statuses(&self) -> Vec<(&'static str, PoolStatus)> {
+        iter::once(("primary", self.storage.as_ref()))
+            .chain(self.replica.as_ref().map(|r| ("replica", r.storage())))
+            .filter_map(|(pool, storage)| Some((pool, storage.pool_status()?)))
+            .collect()
     }
+}
 
-    pub async fn force_profile_country(
+/// Queries that are fine to run against a replica lagging slightly behind
+impl Database {
+    pub async fn fetch_posts_by_authors_country(
         &self,
-        did: &str,
-        likely_country_of_living: &str,
-    ) -> Result<bool> {
-        let mut transaction = self.connection_pool.begin().await?;
-
-        {
-            let mut params = Parameters::new();
-
-            query(
-                &insert_into("Profile")
-                    .columns(("did",))
-                    .values([params.next()])
-                    .on_conflict()
-                    .do_nothing()
-                    .to_string(),
-            )
-            .bind(did)
-            .execute(&mut *transaction)
-            .await?;
+        author_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        if let Some(replica) = self.available_replica() {
+            match replica
+                .fetch_posts_by_authors_country(author_country, limit, earlier_than, filters)
+                .await
+            {
+                Ok(posts) => return Ok(posts),
+                Err(e) => self.fall_back_from_replica(e)?,
+            }
         }
 
-        {
-            let mut params = Parameters::new();
-            query(
-                &update("Profile")
-                    .set("has_been_processed", "TRUE")
-                    .set("likely_country_of_living", params.next())
-                    .where_(format!("did = {}", params.next()))
-                    .to_string(),
-            )
-            .bind(likely_country_of_living)
-            .bind(did)
-            .execute(&mut *transaction)
-            .await?;
-        }
+        self.storage
+            .fetch_posts_by_authors_country(author_country, limit, earlier_than, filters)
+            .await
+    }
 
-        transaction.commit().await?;
+    pub async fn fetch_top_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        offset: usize,
+        as_of: DateTime<Utc>,
+        max_age: TimeDelta,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        if let Some(replica) = self.available_replica() {
+            match replica
+                .fetch_top_posts_by_authors_country(
+                    author_country,
+                    limit,
+                    offset,
+                    as_of,
+                    max_age,
+                    filters,
+                )
+                .await
+            {
+                Ok(posts) => return Ok(posts),
+                Err(e) => self.fall_back_from_replica(e)?,
+            }
+        }
 
-        Ok(true)
+        self.storage
+            .fetch_top_posts_by_authors_country(
+                author_country,
+                limit,
+                offset,
+                as_of,
+                max_age,
+                filters,
+            )
+            .await
     }
 
-    pub async fn is_profile_in_this_country(
+    pub async fn fetch_reposts_by_reposters_country(
         &self,
-        did: &str,
-        country: &str,
-    ) -> Result<Option<bool>> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &select("likely_country_of_living")
-                .from("Profile")
-                .where_(format!("did = {}", params.next()))
-                .where_("has_been_processed = TRUE")
-                .to_string(),
-        )
-        .bind(did)
-        .map(|r: PgRow| r.get("likely_country_of_living"))
-        .map(|c: String| c == country)
-        .fetch_optional(&self.connection_pool)
-        .await?)
+        reposter_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+    ) -> Result<Vec<Repost>> {
+        if let Some(replica) = self.available_replica() {
+            match replica
+                .fetch_reposts_by_reposters_country(reposter_country, limit, earlier_than)
+                .await
+            {
+                Ok(reposts) => return Ok(reposts),
+                Err(e) => self.fall_back_from_replica(e)?,
+            }
+        }
+
+        self.storage
+            .fetch_reposts_by_reposters_country(reposter_country, limit, earlier_than)
+            .await
     }
 
     pub async fn count_posts(&self) -> Result<i64> {
-        Ok(query(&select("COUNT(*)").from("Post").to_string())
-            .map(|r: PgRow| r.get(0))
-            .fetch_one(&self.connection_pool)
-            .await?)
+        if let Some(replica) = self.available_replica() {
+            match replica.count_posts().await {
+                Ok(count) => return Ok(count),
+                Err(e) => self.fall_back_from_replica(e)?,
+            }
+        }
+
+        self.storage.count_posts().await
     }
 
     pub async fn count_profiles_in_country(&self, country: &str) -> Result<i64> {
-        let mut params = Parameters::new();
-        Ok(query(
-            &select("COUNT(*)")
-                .from("Profile")
-                .where_(format!("likely_country_of_living = {}", params.next()))
-                .to_string(),
-        )
-        .bind(country)
-        .map(|r: PgRow| r.get(0))
-        .fetch_one(&self.connection_pool)
-        .await?)
+        if let Some(replica) = self.available_replica() {
+            match replica.count_profiles_in_country(country).await {
+                Ok(count) => return Ok(count),
+                Err(e) => self.fall_back_from_replica(e)?,
+            }
+        }
+
+        self.storage.count_profiles_in_country(country).await
     }
 
-    pub async fn fetch_subscription_cursor(&self, host: &str, did: &str) -> Result<Option<i64>> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &select("cursor")
-                .from("SubscriptionState")
-                .where_(format!("service = {}", params.next()))
-                .where_(format!("host = {}", params.next()))
-                .to_string(),
-        )
-        .bind(did)
-        .bind(host)
-        .map(|r: PgRow| r.get("cursor"))
-        .fetch_optional(&self.connection_pool)
-        .await?)
+    fn available_replica(&self) -> Option<&dyn Storage> {
+        self.replica.as_ref().and_then(Replica::available)
     }
 
-    pub async fn create_subscription_state(&self, host: &str, did: &str) -> Result<bool> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &insert_into("SubscriptionState")
-                .columns(("service", "cursor", "host"))
-                .values([params.next_array()])
-                .to_string(),
-        )
-        .bind(did)
-        .bind(0)
-        .bind(host)
-        .execute(&self.connection_pool)
-        .await
-        .map(|result| result.rows_affected() > 0)?)
+    fn fall_back_from_replica(&self, error: anyhow::Error) -> Result<()> {
+        match &self.replica {
+            Some(replica) => replica.fall_back(error),
+            None => Err(error),
+        }
     }
+}
 
-    pub async fn update_subscription_cursor(
-        &self,
-        host: &str,
-        did: &str,
-        cursor: i64,
-    ) -> Result<bool> {
-        let mut params = Parameters::new();
-
-        Ok(query(
-            &update("SubscriptionState")
-                .set("cursor", params.next())
-                .where_(format!("service = {}", params.next()))
-                .where_(format!("host = {}", params.next()))
-                .to_string(),
-        )
-        .bind(cursor)
-        .bind(did)
-        .bind(host)
-        .execute(&self.connection_pool)
-        .await
-        .map(|result| result.rows_affected() > 0)?)
+impl Deref for Database {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        self.storage.as_ref()
+    }
+}
+
+/// Helpers shared by tests of the storages and of the processes using them
+#[cfg(any(test, feature = "testing"))]
+pub mod tests {
+    use super::*;
+
+    /// Makes a post in Russian with the CID taken from the end of its URI
+    pub fn new_post<'a>(author_did: &'a str, uri: &'a str, minutes_ago: i64) -> NewPost<'a> {
+        NewPost {
+            author_did,
+            cid: uri.rsplit('/').next().unwrap(),
+            uri,
+            created_at: Utc::now() - TimeDelta::minutes(minutes_ago),
+            text: "привет",
+            language: Some("ru"),
+            reply_parent_uri: None,
+            reply_root_uri: None,
+            embed_kind: None,
+            quoted_uri: None,
+        }
+    }
+
+    /// Checks that posts follow their author into the feed and retention scope
+    /// of whichever country they end up classified as living in
+    pub async fn moves_posts_between_feeds(database: &Database) {
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+        database
+            .insert_post(&new_post("did:nl", "at://did:nl/app.bsky.feed.post/a", 1))
+            .await
+            .unwrap();
+
+        let feed = |country| async move {
+            database
+                .fetch_posts_by_authors_country(country, 10, None, &PostFilters::default())
+                .await
+                .unwrap()
+                .len()
+        };
+        let expired = |country| async move {
+            let policy = RetentionPolicy {
+                max_age: TimeDelta::zero(),
+                ..Default::default()
+            };
+
+            database
+                .count_posts_past_retention(
+                    RetentionScope::Country(country),
+                    &policy,
+                    Utc::now() + TimeDelta::days(1),
+                )
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(feed("nl").await, 1);
+        assert_eq!(expired("nl").await, 1);
+
+        database
+            .store_profile_details("did:nl", "de", None)
+            .await
+            .unwrap();
+        assert_eq!((feed("nl").await, feed("de").await), (0, 1));
+        assert_eq!((expired("nl").await, expired("de").await), (0, 1));
+
+        database
+            .reset_profile_classification("did:nl")
+            .await
+            .unwrap();
+        assert_eq!(feed("de").await, 0);
+        assert_eq!(expired("de").await, 0);
+
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+        assert_eq!(feed("nl").await, 1);
+    }
+
+    /// Checks that a profile whose classification keeps failing is retried
+    /// with a growing backoff, and given up on once it runs out of attempts
+    pub async fn retries_classification_with_backoff(database: &Database) {
+        database
+            .insert_profile_if_it_doesnt_exist("did:flaky")
+            .await
+            .unwrap();
+
+        let claim = |lease| {
+            database.claim_unprocessed_profile_dids(10, lease, ClassificationOrder::FirstSeen)
+        };
+
+        // Profiles are handed out again once their lease runs out, but not before
+        assert_eq!(claim(TimeDelta::zero()).await.unwrap(), ["did:flaky"]);
+        assert_eq!(claim(TimeDelta::minutes(5)).await.unwrap(), ["did:flaky"]);
+        assert!(claim(TimeDelta::minutes(5)).await.unwrap().is_empty());
+
+        assert!(
+            !database
+                .record_profile_classification_failure(
+                    "did:flaky",
+                    "oops",
+                    3,
+                    TimeDelta::minutes(1)
+                )
+                .await
+                .unwrap()
+        );
+
+        // Second attempt failed, so the next one waits twice the backoff
+        let details = database
+            .fetch_profile_details("did:flaky")
+            .await
+            .unwrap()
+            .unwrap();
+        let delay = details.next_classification_attempt_at.unwrap() - Utc::now();
+        assert!(delay > TimeDelta::seconds(110) && delay <= TimeDelta::minutes(2));
+        assert!(claim(TimeDelta::minutes(5)).await.unwrap().is_empty());
+
+        assert!(
+            database
+                .record_profile_classification_failure(
+                    "did:flaky",
+                    "oops again",
+                    2,
+                    TimeDelta::zero()
+                )
+                .await
+                .unwrap()
+        );
+
+        let details = database
+            .fetch_profile_details("did:flaky")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(details.classification_attempts, 2);
+        assert!(details.has_failed_classification);
+        assert_eq!(
+            details.last_classification_error.as_deref(),
+            Some("oops again")
+        );
+        assert!(claim(TimeDelta::zero()).await.unwrap().is_empty());
+        assert_eq!(database.count_unprocessed_profiles().await.unwrap(), 0);
+    }
+
+    /// Checks that overriding a profile's country by hand moves it there and
+    /// leaves a trace in the moderation log
+    pub async fn overrides_profile_country(database: &Database) {
+        database
+            .override_profile_country("did:nl", "nl", Some("admin"))
+            .await
+            .unwrap();
+
+        let details = database
+            .fetch_profile_details("did:nl")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(details.likely_country_of_living.as_deref(), Some("nl"));
+
+        let log = database.fetch_moderation_log(10).await.unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].action, "force_country");
+        assert_eq!(log[0].subject, "did:nl");
+        assert_eq!(log[0].reason.as_deref(), Some("nl"));
+        assert_eq!(log[0].moderator.as_deref(), Some("admin"));
+    }
+
+    /// Checks that follows are stored only when they involve a known profile,
+    /// can be deleted, and that counting them only looks at classified profiles
+    pub async fn stores_and_counts_follows(database: &Database) {
+        database
+            .insert_profile_if_it_doesnt_exist("did:target")
+            .await
+            .unwrap();
+        database.force_profile_country("did:a", "nl").await.unwrap();
+        database
+            .insert_profile_if_it_doesnt_exist("did:b")
+            .await
+            .unwrap();
+        database
+            .store_profile_details("did:b", "de", None)
+            .await
+            .unwrap();
+        database
+            .insert_profile_if_it_doesnt_exist("did:unclassified")
+            .await
+            .unwrap();
+
+        let follow = |author_did: &'static str, subject_did: &'static str| {
+            let uri = format!("at://{author_did}/app.bsky.graph.follow/{subject_did}");
+            async move {
+                database
+                    .insert_follow_if_relevant(author_did, subject_did, &uri, Utc::now())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        for follower in ["did:a", "did:b", "did:unclassified", "did:unknown"] {
+            assert!(follow(follower, "did:target").await);
+        }
+        assert!(follow("did:target", "did:a").await);
+        assert!(follow("did:target", "did:b").await);
+
+        // Already stored, or involving nobody we know of
+        assert!(!follow("did:a", "did:target").await);
+        assert!(!follow("did:unknown", "did:stranger").await);
+
+        let mut followed = database.fetch_followed_dids("did:target").await.unwrap();
+        followed.sort();
+        assert_eq!(followed, ["did:a", "did:b"]);
+
+        assert_eq!(
+            database
+                .count_classified_followers("did:target", "nl")
+                .await
+                .unwrap(),
+            (2, 1)
+        );
+
+        let dids = ["did:a", "did:b", "did:unclassified", "did:unknown"].map(String::from);
+        assert_eq!(
+            database
+                .count_classified_profiles_among(&dids, "nl")
+                .await
+                .unwrap(),
+            (2, 1)
+        );
+
+        let uri = "at://did:a/app.bsky.graph.follow/did:target";
+        assert!(database.delete_follow(uri).await.unwrap());
+        assert!(!database.delete_follow(uri).await.unwrap());
+
+        assert_eq!(
+            database
+                .count_classified_followers("did:target", "nl")
+                .await
+                .unwrap(),
+            (1, 0)
+        );
     }
 }
@@ -0,0 +1,1566 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+
+use super::{
+    Ban, BanKind, ClassificationOrder, ClassifiedProfile, ModerationLogEntry, NewPost, Post,
+    PostFilters, PostPreview, PostTimestamp, ProfileDetails, ReplyPolicy, Repost, Residency,
+    RetentionPolicy, RetentionScope, SchemaStatus, Storage,
+};
+use crate::services::bluesky::EmbedKind;
+
+/// Storage that keeps everything in memory, mirroring what the Postgres
+/// queries do, so that anything using the database can be tested without one
+#[derive(Default)]
+pub struct InMemoryStorage {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    profiles: Vec<StoredProfile>,
+    posts: Vec<StoredPost>,
+    pending_posts: Vec<StoredPendingPost>,
+    reposts: Vec<StoredRepost>,
+    likes: Vec<StoredLike>,
+    follows: Vec<StoredFollow>,
+    labels: Vec<StoredLabel>,
+    bans: Vec<Ban>,
+    moderation_log: Vec<ModerationLogEntry>,
+    batches: Vec<StoredBatch>,
+    subscriptions: HashMap<(String, String), i64>,
+}
+
+struct StoredProfile {
+    did: String,
+    first_seen_at: DateTime<Utc>,
+    has_been_processed: bool,
+    likely_country_of_living: Option<String>,
+    residency_probability: Option<f64>,
+    classification_attempts: i32,
+    next_classification_attempt_at: Option<DateTime<Utc>>,
+    has_failed_classification: bool,
+    last_classification_error: Option<String>,
+    display_name: Option<String>,
+    description: Option<String>,
+    classified_at: Option<DateTime<Utc>>,
+}
+
+struct StoredPost {
+    indexed_at: DateTime<Utc>,
+    author_did: String,
+    cid: String,
+    uri: String,
+    created_at: DateTime<Utc>,
+    text: String,
+    language: Option<String>,
+    reply_parent_uri: Option<String>,
+    reply_root_uri: Option<String>,
+    embed_kind: Option<EmbedKind>,
+    quoted_uri: Option<String>,
+    like_count: i32,
+    /// Country of the author, kept in line with their profile the same way
+    /// Postgres keeps its `author_country` column
+    author_country: Option<String>,
+}
+
+impl From<&NewPost<'_>> for StoredPost {
+    fn from(post: &NewPost<'_>) -> Self {
+        Self {
+            indexed_at: Utc::now(),
+            author_did: post.author_did.to_owned(),
+            cid: post.cid.to_owned(),
+            uri: post.uri.to_owned(),
+            created_at: post.created_at,
+            text: post.text.to_owned(),
+            language: post.language.map(str::to_owned),
+            reply_parent_uri: post.reply_parent_uri.map(str::to_owned),
+            reply_root_uri: post.reply_root_uri.map(str::to_owned),
+            embed_kind: post.embed_kind,
+            quoted_uri: post.quoted_uri.map(str::to_owned),
+            like_count: 0,
+            author_country: None,
+        }
+    }
+}
+
+struct StoredPendingPost {
+    post: StoredPost,
+    self_labels: Vec<String>,
+    awaited_country: String,
+}
+
+struct StoredRepost {
+    author_did: String,
+    cid: String,
+    uri: String,
+    post_uri: String,
+    created_at: DateTime<Utc>,
+}
+
+struct StoredLike {
+    author_did: String,
+    uri: String,
+    post_uri: String,
+}
+
+struct StoredFollow {
+    author_did: String,
+    subject_did: String,
+    uri: String,
+}
+
+struct StoredLabel {
+    uri: String,
+    src: String,
+    value: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+struct StoredBatch {
+    batch_id: String,
+    submitted_at: DateTime<Utc>,
+    ended_at: Option<DateTime<Utc>>,
+    profiles: Vec<(String, String)>,
+}
+
+impl InMemoryStorage {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("in-memory storage lock is poisoned")
+    }
+}
+
+impl State {
+    fn profile(&self, did: &str) -> Option<&StoredProfile> {
+        self.profiles.iter().find(|p| p.did == did)
+    }
+
+    fn profile_mut(&mut self, did: &str) -> Option<&mut StoredProfile> {
+        self.profiles.iter_mut().find(|p| p.did == did)
+    }
+
+    fn post(&self, uri: &str) -> Option<&StoredPost> {
+        self.posts.iter().find(|p| p.uri == uri)
+    }
+
+    fn insert_profile(&mut self, did: &str) -> bool {
+        if self.profile(did).is_some() {
+            return false;
+        }
+
+        self.profiles.push(StoredProfile {
+            did: did.to_owned(),
+            first_seen_at: Utc::now(),
+            has_been_processed: false,
+            likely_country_of_living: None,
+            residency_probability: None,
+            classification_attempts: 0,
+            next_classification_attempt_at: None,
+            has_failed_classification: false,
+            last_classification_error: None,
+            display_name: None,
+            description: None,
+            classified_at: None,
+        });
+
+        true
+    }
+
+    /// URIs of posts in the scope that the policy says should be deleted,
+    /// oldest first
+    fn posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let timestamp = |p: &StoredPost| match policy.age_of {
+            PostTimestamp::CreatedAt => p.created_at,
+            PostTimestamp::IndexedAt => p.indexed_at,
+        };
+
+        let mut in_scope = self
+            .posts
+            .iter()
+            .filter(|p| {
+                let country = p.author_country.as_deref();
+
+                match scope {
+                    RetentionScope::Country(c) => country == Some(c),
+                    RetentionScope::OtherCountries(countries) => {
+                        country.is_none_or(|c| !countries.iter().any(|o| o == c))
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        in_scope.sort_by_key(|p| timestamp(p));
+
+        // Posts as old as the last one to keep are kept too
+        let oldest_kept = policy
+            .max_posts
+            .and_then(|max_posts| in_scope.iter().rev().nth(max_posts - 1))
+            .map(|p| timestamp(p));
+
+        in_scope
+            .into_iter()
+            .filter(|p| {
+                timestamp(p) < now - policy.max_age
+                    || oldest_kept.is_some_and(|oldest| timestamp(p) < oldest)
+            })
+            .filter(|p| policy.keep_liked.is_none_or(|min| p.like_count < min))
+            .map(|p| p.uri.clone())
+            .collect()
+    }
+
+    fn is_in_country(&self, did: &str, country: &str) -> bool {
+        self.profile(did)
+            .is_some_and(|p| p.likely_country_of_living.as_deref() == Some(country))
+    }
+
+    /// Whether the record behind the URI was made by someone from the country
+    fn is_by_author_from(&self, uri: &str, country: &str) -> bool {
+        self.is_in_country(author_of(uri), country)
+    }
+
+    fn is_banned(&self, did: &str, uri: &str) -> bool {
+        self.bans.iter().any(|b| match b.kind {
+            BanKind::Profile => b.subject == did,
+            BanKind::Post => b.subject == uri,
+        })
+    }
+
+    fn active_labels(&self, uri: &str, now: DateTime<Utc>) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(move |l| l.uri == uri && l.expires_at.is_none_or(|e| e > now))
+            .map(|l| l.value.as_str())
+    }
+
+    fn is_unprocessed(&self, profile: &StoredProfile, now: DateTime<Utc>) -> bool {
+        !profile.has_been_processed
+            && !profile.has_failed_classification
+            && profile
+                .next_classification_attempt_at
+                .is_none_or(|at| at <= now)
+            && !self
+                .batches
+                .iter()
+                .filter(|b| b.ended_at.is_none())
+                .any(|b| b.profiles.iter().any(|(_, did)| *did == profile.did))
+    }
+
+    fn count_posts_by(&self, did: &str) -> i64 {
+        self.posts.iter().filter(|p| p.author_did == did).count() as i64
+    }
+
+    /// Posts by the author, including the ones held back until the author is
+    /// classified
+    fn all_posts_by<'a>(&'a self, did: &'a str) -> impl Iterator<Item = &'a StoredPost> {
+        self.posts
+            .iter()
+            .chain(self.pending_posts.iter().map(|p| &p.post))
+            .filter(move |p| p.author_did == did)
+    }
+
+    fn count_pending_posts_by(&self, did: &str) -> i64 {
+        self.pending_posts
+            .iter()
+            .filter(|p| p.post.author_did == did)
+            .count() as i64
+    }
+
+    /// Posts by authors from the given country that pass the filters, the
+    /// same way `posts_by_authors_country_query` selects them
+    fn posts_by_authors_country<'a>(
+        &'a self,
+        country: &'a str,
+        filters: &'a PostFilters,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = &'a StoredPost> {
+        self.posts.iter().filter(move |p| {
+            p.author_country.as_deref() == Some(country)
+                && !self.is_banned(&p.author_did, &p.uri)
+                && self.passes_reply_policy(p, country, filters.replies)
+                && (filters.embed_kinds.is_empty()
+                    || p.embed_kind
+                        .is_some_and(|k| filters.embed_kinds.contains(&k)))
+                && !self
+                    .active_labels(&p.uri, now)
+                    .any(|l| filters.hidden_labels.iter().any(|h| h == l))
+                && (!filters.hide_quotes_from_outside
+                    || p.quoted_uri
+                        .as_deref()
+                        .is_none_or(|q| self.is_by_author_from(q, country)))
+        })
+    }
+
+    fn passes_reply_policy(&self, post: &StoredPost, country: &str, policy: ReplyPolicy) -> bool {
+        match policy {
+            ReplyPolicy::Show => true,
+            ReplyPolicy::Hide => post.reply_parent_uri.is_none(),
+            ReplyPolicy::OnlyToFeedAuthors => post
+                .reply_parent_uri
+                .as_deref()
+                .is_none_or(|parent| self.is_by_author_from(parent, country)),
+            ReplyPolicy::CollapseThreads => {
+                let thread = thread_of(post);
+                !self.posts.iter().any(|other| {
+                    thread_of(other) == thread
+                        && other.author_country.as_deref() == Some(country)
+                        && (other.created_at, &other.cid) > (post.created_at, &post.cid)
+                })
+            }
+        }
+    }
+
+    fn to_post(&self, post: &StoredPost, filters: &PostFilters, now: DateTime<Utc>) -> Post {
+        let mut labels = if filters.mark_labeled {
+            self.active_labels(&post.uri, now)
+                .map(str::to_owned)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        labels.sort();
+        labels.dedup();
+
+        Post {
+            created_at: post.created_at,
+            author_did: post.author_did.clone(),
+            cid: post.cid.clone(),
+            uri: post.uri.clone(),
+            labels,
+        }
+    }
+
+    fn update_author_country_of_posts(&mut self, did: &str, country: Option<&str>) {
+        for post in self.posts.iter_mut().filter(|p| p.author_did == did) {
+            post.author_country = country.map(str::to_owned);
+        }
+    }
+
+    fn settle_pending_posts(&mut self, did: &str, country: &str) {
+        let (settled, pending) = std::mem::take(&mut self.pending_posts)
+            .into_iter()
+            .partition::<Vec<_>, _>(|p| p.post.author_did == did);
+
+        self.pending_posts = pending;
+
+        for pending in settled {
+            if pending.awaited_country != country || self.post(&pending.post.uri).is_some() {
+                continue;
+            }
+
+            for value in pending.self_labels {
+                self.labels.push(StoredLabel {
+                    uri: pending.post.uri.clone(),
+                    src: did.to_owned(),
+                    value,
+                    created_at: pending.post.created_at,
+                    expires_at: None,
+                });
+            }
+
+            self.posts.push(StoredPost {
+                author_country: Some(country.to_owned()),
+                ..pending.post
+            });
+        }
+    }
+
+    fn force_country(&mut self, did: &str, likely_country_of_living: &str) {
+        self.insert_profile(did);
+
+        let profile = self.profile_mut(did).expect("profile was just inserted");
+        profile.has_been_processed = true;
+        profile.classified_at = Some(Utc::now());
+        profile.has_failed_classification = false;
+        profile.likely_country_of_living = Some(likely_country_of_living.to_owned());
+
+        self.update_author_country_of_posts(did, Some(likely_country_of_living));
+        self.settle_pending_posts(did, likely_country_of_living);
+    }
+
+    fn log(&mut self, action: &str, subject: &str, reason: Option<&str>, moderator: Option<&str>) {
+        self.moderation_log.push(ModerationLogEntry {
+            performed_at: Utc::now(),
+            action: action.to_owned(),
+            subject: subject.to_owned(),
+            reason: reason.map(str::to_owned),
+            moderator: moderator.map(str::to_owned),
+        });
+    }
+}
+
+#[async_trait]
+impl Storage for InMemoryStorage {
+    async fn migrate(&self) -> Result<SchemaStatus> {
+        self.schema_status().await
+    }
+
+    async fn schema_status(&self) -> Result<SchemaStatus> {
+        // There's no schema to speak of, so it's always up to date
+        Ok(SchemaStatus {
+            current_version: None,
+            expected_version: 0,
+            pending: 0,
+        })
+    }
+
+    async fn baseline_schema(&self, _up_to: i64) -> Result<usize> {
+        Ok(0)
+    }
+
+    async fn insert_post(&self, post: &NewPost<'_>) -> Result<bool> {
+        let mut state = self.state();
+
+        if state.profile(post.author_did).is_none() {
+            bail!(
+                "No profile with did {} to attribute the post to",
+                post.author_did
+            );
+        }
+
+        if state
+            .posts
+            .iter()
+            .any(|p| p.uri == post.uri || p.cid == post.cid)
+        {
+            return Ok(false);
+        }
+
+        let author_country = state
+            .profile(post.author_did)
+            .and_then(|p| p.likely_country_of_living.clone());
+
+        state.posts.push(StoredPost {
+            author_country,
+            ..post.into()
+        });
+
+        Ok(true)
+    }
+
+    async fn fetch_recent_post_texts(&self, author_did: &str, limit: usize) -> Result<Vec<String>> {
+        let state = self.state();
+
+        let mut posts = state.all_posts_by(author_did).collect::<Vec<_>>();
+
+        posts.sort_by_key(|p| std::cmp::Reverse(p.created_at));
+
+        Ok(posts
+            .into_iter()
+            .take(limit)
+            .map(|p| p.text.clone())
+            .collect())
+    }
+
+    async fn fetch_post_language_counts(&self, author_did: &str) -> Result<Vec<(String, i64)>> {
+        let state = self.state();
+
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for post in state.all_posts_by(author_did) {
+            if let Some(language) = &post.language {
+                *counts.entry(language).or_default() += 1;
+            }
+        }
+
+        let mut counts = counts
+            .into_iter()
+            .map(|(language, count)| (language.to_owned(), count))
+            .collect::<Vec<_>>();
+
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        Ok(counts)
+    }
+
+    async fn fetch_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        let state = self.state();
+        let now = Utc::now();
+
+        let mut posts = state
+            .posts_by_authors_country(author_country, filters, now)
+            .filter(|p| {
+                earlier_than.is_none_or(|(last_created_at, last_cid)| {
+                    (p.created_at, p.cid.as_str()) < (last_created_at, last_cid)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        posts.sort_by(|a, b| (b.created_at, &b.cid).cmp(&(a.created_at, &a.cid)));
+
+        Ok(posts
+            .into_iter()
+            .take(limit)
+            .map(|p| state.to_post(p, filters, now))
+            .collect())
+    }
+
+    async fn fetch_top_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        offset: usize,
+        as_of: DateTime<Utc>,
+        max_age: TimeDelta,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        let state = self.state();
+        let now = Utc::now();
+
+        let score = |p: &StoredPost| {
+            let hours = (as_of - p.created_at).num_seconds().max(0) as f64 / 3600.0;
+            p.like_count as f64 / (hours + 2.0).powf(1.8)
+        };
+
+        let mut posts = state
+            .posts_by_authors_country(author_country, filters, now)
+            .filter(|p| p.created_at <= as_of && p.created_at > as_of - max_age)
+            .collect::<Vec<_>>();
+
+        posts.sort_by(|a, b| {
+            score(b)
+                .total_cmp(&score(a))
+                .then_with(|| (b.created_at, &b.cid).cmp(&(a.created_at, &a.cid)))
+        });
+
+        Ok(posts
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|p| state.to_post(p, filters, now))
+            .collect())
+    }
+
+    async fn delete_post(&self, uri: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let pending_before = state.pending_posts.len();
+        state.pending_posts.retain(|p| p.post.uri != uri);
+        let deleted_pending = state.pending_posts.len() < pending_before;
+
+        let before = state.posts.len();
+        state.posts.retain(|p| p.uri != uri);
+        let deleted = state.posts.len() < before;
+
+        if deleted {
+            state.likes.retain(|l| l.post_uri != uri);
+            state.labels.retain(|l| l.uri != uri);
+        }
+
+        Ok(deleted || deleted_pending)
+    }
+
+    async fn count_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<u64> {
+        Ok(self.state().posts_past_retention(scope, policy, now).len() as u64)
+    }
+
+    async fn delete_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<u64> {
+        let mut state = self.state();
+
+        let mut expired = state.posts_past_retention(scope, policy, now);
+        expired.truncate(limit);
+
+        state.posts.retain(|p| !expired.contains(&p.uri));
+        state.likes.retain(|l| !expired.contains(&l.post_uri));
+        state.labels.retain(|l| !expired.contains(&l.uri));
+
+        Ok(expired.len() as u64)
+    }
+
+    async fn insert_pending_post(
+        &self,
+        post: &NewPost<'_>,
+        self_labels: &[&str],
+        awaited_country: &str,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        let author_is_unprocessed = state
+            .profile(post.author_did)
+            .is_some_and(|p| !p.has_been_processed);
+
+        if !author_is_unprocessed
+            || state
+                .pending_posts
+                .iter()
+                .any(|p| p.post.uri == post.uri || p.post.cid == post.cid)
+        {
+            return Ok(false);
+        }
+
+        state.pending_posts.push(StoredPendingPost {
+            post: post.into(),
+            self_labels: self_labels.iter().map(|&l| l.to_owned()).collect(),
+            awaited_country: awaited_country.to_owned(),
+        });
+
+        Ok(true)
+    }
+
+    async fn delete_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
+        let mut state = self.state();
+
+        let before = state.pending_posts.len();
+        state
+            .pending_posts
+            .retain(|p| p.post.indexed_at >= *earlier_than);
+
+        Ok((before - state.pending_posts.len()) as u64)
+    }
+
+    async fn count_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
+        Ok(self
+            .state()
+            .pending_posts
+            .iter()
+            .filter(|p| p.post.indexed_at < *earlier_than)
+            .count() as u64)
+    }
+
+    async fn insert_repost_if_relevant(
+        &self,
+        author_did: &str,
+        cid: &str,
+        uri: &str,
+        post_uri: &str,
+        created_at: DateTime<Utc>,
+        reposter_country: &str,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        if !state.is_in_country(author_did, reposter_country)
+            || state.reposts.iter().any(|r| r.uri == uri || r.cid == cid)
+        {
+            return Ok(false);
+        }
+
+        state.reposts.push(StoredRepost {
+            author_did: author_did.to_owned(),
+            cid: cid.to_owned(),
+            uri: uri.to_owned(),
+            post_uri: post_uri.to_owned(),
+            created_at,
+        });
+
+        Ok(true)
+    }
+
+    async fn fetch_reposts_by_reposters_country(
+        &self,
+        reposter_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+    ) -> Result<Vec<Repost>> {
+        let state = self.state();
+
+        let mut reposts = state
+            .reposts
+            .iter()
+            .filter(|r| {
+                state.is_in_country(&r.author_did, reposter_country)
+                    && !state.is_banned(&r.author_did, &r.uri)
+                    && !state.is_banned(author_of(&r.post_uri), &r.post_uri)
+                    && earlier_than.is_none_or(|(last_created_at, last_cid)| {
+                        (r.created_at, r.cid.as_str()) < (last_created_at, last_cid)
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        reposts.sort_by(|a, b| (b.created_at, &b.cid).cmp(&(a.created_at, &a.cid)));
+
+        Ok(reposts
+            .into_iter()
+            .take(limit)
+            .map(|r| Repost {
+                created_at: r.created_at,
+                author_did: r.author_did.clone(),
+                cid: r.cid.clone(),
+                uri: r.uri.clone(),
+                post_uri: r.post_uri.clone(),
+            })
+            .collect())
+    }
+
+    async fn delete_repost(&self, uri: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let before = state.reposts.len();
+        state.reposts.retain(|r| r.uri != uri);
+
+        Ok(state.reposts.len() < before)
+    }
+
+    async fn insert_label_if_relevant(
+        &self,
+        uri: &str,
+        src: &str,
+        value: &str,
+        created_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        if state.post(uri).is_none() {
+            return Ok(false);
+        }
+
+        match state
+            .labels
+            .iter_mut()
+            .find(|l| l.uri == uri && l.src == src && l.value == value)
+        {
+            Some(label) => {
+                label.created_at = created_at;
+                label.expires_at = expires_at;
+            }
+            None => state.labels.push(StoredLabel {
+                uri: uri.to_owned(),
+                src: src.to_owned(),
+                value: value.to_owned(),
+                created_at,
+                expires_at,
+            }),
+        }
+
+        Ok(true)
+    }
+
+    async fn delete_label(&self, uri: &str, src: &str, value: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let before = state.labels.len();
+        state
+            .labels
+            .retain(|l| !(l.uri == uri && l.src == src && l.value == value));
+
+        Ok(state.labels.len() < before)
+    }
+
+    async fn insert_like_if_relevant(
+        &self,
+        author_did: &str,
+        post_uri: &str,
+        uri: &str,
+        _created_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        if state.likes.iter().any(|l| l.uri == uri) {
+            return Ok(false);
+        }
+
+        let Some(post) = state.posts.iter_mut().find(|p| p.uri == post_uri) else {
+            return Ok(false);
+        };
+
+        post.like_count += 1;
+        state.likes.push(StoredLike {
+            author_did: author_did.to_owned(),
+            uri: uri.to_owned(),
+            post_uri: post_uri.to_owned(),
+        });
+
+        Ok(true)
+    }
+
+    async fn delete_like(&self, uri: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(index) = state.likes.iter().position(|l| l.uri == uri) else {
+            return Ok(false);
+        };
+
+        let like = state.likes.remove(index);
+
+        if let Some(post) = state.posts.iter_mut().find(|p| p.uri == like.post_uri) {
+            post.like_count = (post.like_count - 1).max(0);
+        }
+
+        Ok(true)
+    }
+
+    async fn fetch_post_author_dids(&self) -> Result<Vec<String>> {
+        let mut dids: Vec<_> = self
+            .state()
+            .posts
+            .iter()
+            .map(|p| p.author_did.clone())
+            .collect();
+
+        dids.sort();
+        dids.dedup();
+
+        Ok(dids)
+    }
+
+    async fn fetch_liker_dids(&self) -> Result<Vec<String>> {
+        let mut dids: Vec<_> = self
+            .state()
+            .likes
+            .iter()
+            .map(|l| l.author_did.clone())
+            .collect();
+
+        dids.sort();
+        dids.dedup();
+
+        Ok(dids)
+    }
+
+    async fn insert_follow_if_relevant(
+        &self,
+        author_did: &str,
+        subject_did: &str,
+        uri: &str,
+        _created_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        if (state.profile(author_did).is_none() && state.profile(subject_did).is_none())
+            || state.follows.iter().any(|f| f.uri == uri)
+        {
+            return Ok(false);
+        }
+
+        state.follows.push(StoredFollow {
+            author_did: author_did.to_owned(),
+            subject_did: subject_did.to_owned(),
+            uri: uri.to_owned(),
+        });
+
+        Ok(true)
+    }
+
+    async fn delete_follow(&self, uri: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let before = state.follows.len();
+        state.follows.retain(|f| f.uri != uri);
+
+        Ok(state.follows.len() < before)
+    }
+
+    async fn fetch_followed_dids(&self, did: &str) -> Result<Vec<String>> {
+        Ok(self
+            .state()
+            .follows
+            .iter()
+            .filter(|f| f.author_did == did)
+            .map(|f| f.subject_did.clone())
+            .collect())
+    }
+
+    async fn count_classified_followers(&self, did: &str, country: &str) -> Result<(i64, i64)> {
+        let state = self.state();
+
+        let followers = state
+            .follows
+            .iter()
+            .filter(|f| f.subject_did == did)
+            .filter_map(|f| state.profile(&f.author_did))
+            .filter(|p| p.has_been_processed)
+            .collect::<Vec<_>>();
+
+        let in_country = followers
+            .iter()
+            .filter(|p| p.likely_country_of_living.as_deref() == Some(country))
+            .count();
+
+        Ok((followers.len() as i64, in_country as i64))
+    }
+
+    async fn insert_profile_if_it_doesnt_exist(&self, did: &str) -> Result<bool> {
+        Ok(self.state().insert_profile(did))
+    }
+
+    async fn claim_unprocessed_profile_dids(
+        &self,
+        limit: usize,
+        lease: TimeDelta,
+        order: ClassificationOrder,
+    ) -> Result<Vec<String>> {
+        let mut state = self.state();
+        let now = Utc::now();
+
+        let mut claimable = state
+            .profiles
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| state.is_unprocessed(p, now))
+            .map(|(index, p)| {
+                let activity = match order {
+                    ClassificationOrder::FirstSeen => 0,
+                    ClassificationOrder::PostActivity => state.count_pending_posts_by(&p.did),
+                };
+                (activity, p.first_seen_at, index)
+            })
+            .collect::<Vec<_>>();
+
+        claimable.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| (a.1, a.2).cmp(&(b.1, b.2))));
+
+        let mut claimed = Vec::new();
+        for (_, _, index) in claimable.into_iter().take(limit) {
+            let profile = &mut state.profiles[index];
+            profile.classification_attempts += 1;
+            profile.next_classification_attempt_at = Some(now + lease);
+            claimed.push(profile.did.clone());
+        }
+
+        Ok(claimed)
+    }
+
+    async fn count_unprocessed_profiles(&self) -> Result<i64> {
+        let state = self.state();
+        let now = Utc::now();
+
+        Ok(state
+            .profiles
+            .iter()
+            .filter(|p| state.is_unprocessed(p, now))
+            .count() as i64)
+    }
+
+    async fn record_profile_classification_failure(
+        &self,
+        did: &str,
+        error: &str,
+        max_attempts: i32,
+        backoff: TimeDelta,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(profile) = state.profile_mut(did) else {
+            return Ok(false);
+        };
+
+        let multiplier = 2f64.powi((profile.classification_attempts - 1).min(16));
+        let delay =
+            TimeDelta::milliseconds((backoff.num_milliseconds() as f64 * multiplier) as i64);
+
+        profile.last_classification_error = Some(error.to_owned());
+        profile.has_failed_classification = profile.classification_attempts >= max_attempts;
+        profile.next_classification_attempt_at = Some(Utc::now() + delay);
+
+        Ok(profile.has_failed_classification)
+    }
+
+    async fn create_classification_batch(
+        &self,
+        batch_id: &str,
+        profiles: &[(String, String)],
+    ) -> Result<()> {
+        let mut state = self.state();
+
+        if state.batches.iter().any(|b| b.batch_id == batch_id) {
+            bail!("Classification batch {} already exists", batch_id);
+        }
+
+        state.batches.push(StoredBatch {
+            batch_id: batch_id.to_owned(),
+            submitted_at: Utc::now(),
+            ended_at: None,
+            profiles: profiles.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    async fn fetch_unfinished_classification_batches(
+        &self,
+    ) -> Result<Vec<(String, DateTime<Utc>)>> {
+        let state = self.state();
+
+        let mut batches = state
+            .batches
+            .iter()
+            .filter(|b| b.ended_at.is_none())
+            .collect::<Vec<_>>();
+
+        batches.sort_by_key(|b| b.submitted_at);
+
+        Ok(batches
+            .into_iter()
+            .map(|b| (b.batch_id.clone(), b.submitted_at))
+            .collect())
+    }
+
+    async fn fetch_classification_batch_profiles(
+        &self,
+        batch_id: &str,
+    ) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .state()
+            .batches
+            .iter()
+            .find(|b| b.batch_id == batch_id)
+            .map(|b| b.profiles.clone())
+            .unwrap_or_default())
+    }
+
+    async fn finish_classification_batch(&self, batch_id: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(batch) = state.batches.iter_mut().find(|b| b.batch_id == batch_id) else {
+            return Ok(false);
+        };
+
+        batch.ended_at = Some(Utc::now());
+
+        Ok(true)
+    }
+
+    async fn store_profile_details(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        residency_probability: Option<f64>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(profile) = state.profile_mut(did) else {
+            return Ok(false);
+        };
+
+        profile.has_been_processed = true;
+        profile.classified_at = Some(Utc::now());
+        profile.likely_country_of_living = Some(likely_country_of_living.to_owned());
+        profile.residency_probability = residency_probability;
+        profile.next_classification_attempt_at = None;
+        profile.last_classification_error = None;
+
+        state.update_author_country_of_posts(did, Some(likely_country_of_living));
+        state.settle_pending_posts(did, likely_country_of_living);
+
+        Ok(true)
+    }
+
+    async fn force_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+    ) -> Result<bool> {
+        self.state().force_country(did, likely_country_of_living);
+        Ok(true)
+    }
+
+    async fn override_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        moderator: Option<&str>,
+    ) -> Result<()> {
+        let mut state = self.state();
+
+        state.force_country(did, likely_country_of_living);
+        state.log(
+            "force_country",
+            did,
+            Some(likely_country_of_living),
+            moderator,
+        );
+
+        Ok(())
+    }
+
+    async fn store_profile_description(
+        &self,
+        did: &str,
+        display_name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(profile) = state.profile_mut(did) else {
+            return Ok(false);
+        };
+
+        profile.display_name = display_name.map(str::to_owned);
+        profile.description = description.map(str::to_owned);
+
+        Ok(true)
+    }
+
+    async fn fetch_recently_classified_profiles(
+        &self,
+        country: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ClassifiedProfile>> {
+        let state = self.state();
+
+        let mut profiles = state
+            .profiles
+            .iter()
+            .filter(|p| country.is_none() || p.likely_country_of_living.as_deref() == country)
+            .filter_map(|p| Some((p.classified_at?, p)))
+            .collect::<Vec<_>>();
+
+        profiles.sort_by_key(|(classified_at, _)| std::cmp::Reverse(*classified_at));
+
+        Ok(profiles
+            .into_iter()
+            .take(limit)
+            .map(|(classified_at, p)| ClassifiedProfile {
+                did: p.did.clone(),
+                display_name: p.display_name.clone(),
+                description: p.description.clone(),
+                likely_country_of_living: p.likely_country_of_living.clone(),
+                residency_probability: p.residency_probability,
+                classified_at,
+            })
+            .collect())
+    }
+
+    async fn fetch_recent_post_previews(
+        &self,
+        author_country: &str,
+        limit: usize,
+    ) -> Result<Vec<PostPreview>> {
+        let state = self.state();
+
+        let mut posts = state
+            .posts
+            .iter()
+            .filter(|p| state.is_in_country(&p.author_did, author_country))
+            .collect::<Vec<_>>();
+
+        posts.sort_by(|a, b| (b.created_at, &b.cid).cmp(&(a.created_at, &a.cid)));
+
+        Ok(posts
+            .into_iter()
+            .take(limit)
+            .map(|p| PostPreview {
+                uri: p.uri.clone(),
+                author_did: p.author_did.clone(),
+                author_display_name: state
+                    .profile(&p.author_did)
+                    .and_then(|pr| pr.display_name.clone()),
+                created_at: p.created_at,
+                text: Some(p.text.clone()),
+                language: p.language.clone(),
+                like_count: p.like_count,
+            })
+            .collect())
+    }
+
+    async fn fetch_profile_details(&self, did: &str) -> Result<Option<ProfileDetails>> {
+        let state = self.state();
+
+        Ok(state.profile(did).map(|p| ProfileDetails {
+            did: p.did.clone(),
+            first_seen_at: p.first_seen_at,
+            has_been_processed: p.has_been_processed,
+            likely_country_of_living: p.likely_country_of_living.clone(),
+            residency_probability: p.residency_probability,
+            classification_attempts: p.classification_attempts,
+            next_classification_attempt_at: p.next_classification_attempt_at,
+            has_failed_classification: p.has_failed_classification,
+            last_classification_error: p.last_classification_error.clone(),
+            post_count: state.count_posts_by(&p.did),
+            is_banned: state
+                .bans
+                .iter()
+                .any(|b| b.kind == BanKind::Profile && b.subject == p.did),
+        }))
+    }
+
+    async fn reset_profile_classification(&self, did: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let Some(profile) = state.profile_mut(did) else {
+            return Ok(false);
+        };
+
+        profile.has_been_processed = false;
+        profile.classified_at = None;
+        profile.likely_country_of_living = None;
+        profile.residency_probability = None;
+        profile.classification_attempts = 0;
+        profile.next_classification_attempt_at = None;
+        profile.has_failed_classification = false;
+        profile.last_classification_error = None;
+
+        state.update_author_country_of_posts(did, None);
+
+        Ok(true)
+    }
+
+    async fn fetch_profile_residency(&self, did: &str, country: &str) -> Result<Option<Residency>> {
+        Ok(self.state().profile(did).and_then(|p| {
+            Residency::of(
+                p.has_been_processed,
+                p.has_failed_classification,
+                p.likely_country_of_living.as_deref(),
+                country,
+            )
+        }))
+    }
+
+    async fn count_posts(&self) -> Result<i64> {
+        Ok(self.state().posts.len() as i64)
+    }
+
+    async fn count_classified_profiles_among(
+        &self,
+        dids: &[String],
+        country: &str,
+    ) -> Result<(i64, i64)> {
+        let state = self.state();
+
+        let classified = state
+            .profiles
+            .iter()
+            .filter(|p| p.has_been_processed && dids.contains(&p.did))
+            .collect::<Vec<_>>();
+
+        let in_country = classified
+            .iter()
+            .filter(|p| p.likely_country_of_living.as_deref() == Some(country))
+            .count();
+
+        Ok((classified.len() as i64, in_country as i64))
+    }
+
+    async fn count_failed_profiles(&self) -> Result<i64> {
+        Ok(self
+            .state()
+            .profiles
+            .iter()
+            .filter(|p| p.has_failed_classification)
+            .count() as i64)
+    }
+
+    async fn count_profiles_in_country(&self, country: &str) -> Result<i64> {
+        Ok(self
+            .state()
+            .profiles
+            .iter()
+            .filter(|p| p.likely_country_of_living.as_deref() == Some(country))
+            .count() as i64)
+    }
+
+    async fn fetch_profile_dids_in_country(&self, country: &str) -> Result<Vec<String>> {
+        let mut dids: Vec<_> = self
+            .state()
+            .profiles
+            .iter()
+            .filter(|p| p.has_been_processed)
+            .filter(|p| p.likely_country_of_living.as_deref() == Some(country))
+            .map(|p| p.did.clone())
+            .collect();
+
+        dids.sort();
+
+        Ok(dids)
+    }
+
+    async fn ban(
+        &self,
+        kind: BanKind,
+        subject: &str,
+        reason: &str,
+        moderator: Option<&str>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+
+        if state
+            .bans
+            .iter()
+            .any(|b| b.kind == kind && b.subject == subject)
+        {
+            return Ok(false);
+        }
+
+        state.bans.push(Ban {
+            kind,
+            subject: subject.to_owned(),
+            reason: reason.to_owned(),
+            banned_at: Utc::now(),
+        });
+        state.log(kind.action(true), subject, Some(reason), moderator);
+
+        Ok(true)
+    }
+
+    async fn unban(&self, kind: BanKind, subject: &str, moderator: Option<&str>) -> Result<bool> {
+        let mut state = self.state();
+
+        let before = state.bans.len();
+        state
+            .bans
+            .retain(|b| !(b.kind == kind && b.subject == subject));
+
+        if state.bans.len() == before {
+            return Ok(false);
+        }
+
+        state.log(kind.action(false), subject, None, moderator);
+
+        Ok(true)
+    }
+
+    async fn fetch_bans(&self, kind: BanKind) -> Result<Vec<Ban>> {
+        Ok(self
+            .state()
+            .bans
+            .iter()
+            .rev()
+            .filter(|b| b.kind == kind)
+            .map(|b| Ban {
+                kind: b.kind,
+                subject: b.subject.clone(),
+                reason: b.reason.clone(),
+                banned_at: b.banned_at,
+            })
+            .collect())
+    }
+
+    async fn is_post_banned(&self, author_did: &str, uri: &str) -> Result<bool> {
+        Ok(self.state().is_banned(author_did, uri))
+    }
+
+    async fn record_moderation_action(
+        &self,
+        action: &str,
+        subject: &str,
+        reason: Option<&str>,
+        moderator: Option<&str>,
+    ) -> Result<()> {
+        self.state().log(action, subject, reason, moderator);
+        Ok(())
+    }
+
+    async fn fetch_moderation_log(&self, limit: usize) -> Result<Vec<ModerationLogEntry>> {
+        Ok(self
+            .state()
+            .moderation_log
+            .iter()
+            .rev()
+            .take(limit)
+            .map(|e| ModerationLogEntry {
+                performed_at: e.performed_at,
+                action: e.action.clone(),
+                subject: e.subject.clone(),
+                reason: e.reason.clone(),
+                moderator: e.moderator.clone(),
+            })
+            .collect())
+    }
+
+    async fn fetch_subscription_cursor(&self, host: &str, did: &str) -> Result<Option<i64>> {
+        Ok(self
+            .state()
+            .subscriptions
+            .get(&(host.to_owned(), did.to_owned()))
+            .copied())
+    }
+
+    async fn create_subscription_state(&self, host: &str, did: &str) -> Result<bool> {
+        let mut state = self.state();
+
+        let key = (host.to_owned(), did.to_owned());
+        if state.subscriptions.contains_key(&key) {
+            bail!("Subscription state for {} at {} already exists", did, host);
+        }
+
+        state.subscriptions.insert(key, 0);
+
+        Ok(true)
+    }
+
+    async fn update_subscription_cursor(&self, host: &str, did: &str, cursor: i64) -> Result<bool> {
+        let mut state = self.state();
+
+        match state
+            .subscriptions
+            .get_mut(&(host.to_owned(), did.to_owned()))
+        {
+            Some(stored) => {
+                *stored = cursor;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// DID of whoever made the record behind an `at://` URI
+fn author_of(uri: &str) -> &str {
+    uri.split('/').nth(2).unwrap_or_default()
+}
+
+/// URI of the post that starts the thread the post belongs to
+fn thread_of(post: &StoredPost) -> &str {
+    post.reply_root_uri.as_deref().unwrap_or(&post.uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::database::Database;
+    use crate::services::database::tests::{
+        moves_posts_between_feeds, new_post, overrides_profile_country,
+        retries_classification_with_backoff, stores_and_counts_follows,
+    };
+
+    async fn database_with_profiles() -> Database {
+        let database = Database::in_memory();
+
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+        database
+            .force_profile_country("did:de", "de")
+            .await
+            .unwrap();
+
+        database
+    }
+
+    #[tokio::test]
+    async fn pages_through_posts_by_authors_country() {
+        let database = database_with_profiles().await;
+
+        for (uri, minutes_ago) in [
+            ("at://did:nl/app.bsky.feed.post/c", 1),
+            ("at://did:nl/app.bsky.feed.post/b", 2),
+            ("at://did:nl/app.bsky.feed.post/a", 3),
+        ] {
+            database
+                .insert_post(&new_post("did:nl", uri, minutes_ago))
+                .await
+                .unwrap();
+        }
+        database
+            .insert_post(&new_post("did:de", "at://did:de/app.bsky.feed.post/d", 0))
+            .await
+            .unwrap();
+
+        let filters = PostFilters::default();
+
+        let first_page = database
+            .fetch_posts_by_authors_country("nl", 2, None, &filters)
+            .await
+            .unwrap();
+        assert_eq!(
+            first_page
+                .iter()
+                .map(|p| p.cid.as_str())
+                .collect::<Vec<_>>(),
+            ["c", "b"]
+        );
+
+        let last = first_page.last().unwrap();
+        let second_page = database
+            .fetch_posts_by_authors_country("nl", 2, Some((last.created_at, &last.cid)), &filters)
+            .await
+            .unwrap();
+        assert_eq!(
+            second_page
+                .iter()
+                .map(|p| p.cid.as_str())
+                .collect::<Vec<_>>(),
+            ["a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_out_banned_and_hidden_posts() {
+        let database = database_with_profiles().await;
+
+        let banned = "at://did:nl/app.bsky.feed.post/a";
+        let labeled = "at://did:nl/app.bsky.feed.post/b";
+        let reply = "at://did:nl/app.bsky.feed.post/c";
+
+        database
+            .insert_post(&new_post("did:nl", banned, 3))
+            .await
+            .unwrap();
+        database
+            .insert_post(&new_post("did:nl", labeled, 2))
+            .await
+            .unwrap();
+        database
+            .insert_post(&NewPost {
+                reply_parent_uri: Some("at://did:de/app.bsky.feed.post/x"),
+                reply_root_uri: Some("at://did:de/app.bsky.feed.post/x"),
+                ..new_post("did:nl", reply, 1)
+            })
+            .await
+            .unwrap();
+
+        database
+            .ban(BanKind::Post, banned, "spam", None)
+            .await
+            .unwrap();
+        database
+            .insert_label_if_relevant(labeled, "did:labeler", "porn", Utc::now(), None)
+            .await
+            .unwrap();
+
+        let filters = PostFilters {
+            replies: ReplyPolicy::OnlyToFeedAuthors,
+            hidden_labels: vec!["porn".to_owned()],
+            ..Default::default()
+        };
+
+        let posts = database
+            .fetch_posts_by_authors_country("nl", 10, None, &filters)
+            .await
+            .unwrap();
+        assert!(posts.is_empty());
+
+        let posts = database
+            .fetch_posts_by_authors_country("nl", 10, None, &PostFilters::default())
+            .await
+            .unwrap();
+        assert_eq!(posts.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn moves_posts_between_feeds_when_authors_are_reclassified() {
+        moves_posts_between_feeds(&Database::in_memory()).await;
+    }
+
+    #[tokio::test]
+    async fn stores_deletes_and_counts_follows() {
+        stores_and_counts_follows(&Database::in_memory()).await;
+    }
+
+    #[tokio::test]
+    async fn overrides_profile_country_with_a_trace() {
+        overrides_profile_country(&Database::in_memory()).await;
+    }
+
+    #[tokio::test]
+    async fn retries_classification_with_backoff_until_attempts_run_out() {
+        retries_classification_with_backoff(&Database::in_memory()).await;
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::{Result, bail};
+use sqlx::migrate::{AppliedMigration, Migration};
+
+/// Makes sure every applied migration is one we know about, unchanged, so
+/// that an older build never runs against a newer schema
+pub(super) fn check_applied_migrations(
+    known: &[Migration],
+    applied: &[AppliedMigration],
+) -> Result<()> {
+    for applied in applied {
+        let Some(migration) = known.iter().find(|m| m.version == applied.version) else {
+            bail!(
+                "Database schema is at version {}, which this build doesn't know about \
+                 (latest known is {}). Is an older build running against a newer database?",
+                applied.version,
+                known.iter().map(|m| m.version).max().unwrap_or(0)
+            );
+        };
+
+        if migration.checksum != applied.checksum {
+            bail!(
+                "Migration {} ({}) was changed after being applied to the database",
+                migration.version,
+                migration.description
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::migrate::MigrationType;
+
+    use super::*;
+
+    fn migration(version: i64, sql: &'static str) -> Migration {
+        Migration::new(
+            version,
+            "test".into(),
+            MigrationType::Simple,
+            sql.into(),
+            false,
+        )
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            checksum: migration.checksum.clone(),
+        }
+    }
+
+    #[test]
+    fn accepts_known_migrations_and_rejects_unexpected_ones() {
+        let first = migration(1, "CREATE TABLE a ()");
+        let second = migration(2, "CREATE TABLE b ()");
+        let known = [first.clone(), second.clone()];
+
+        assert!(check_applied_migrations(&known, &[]).is_ok());
+        assert!(check_applied_migrations(&known, &[applied(&first)]).is_ok());
+
+        let newer = migration(3, "CREATE TABLE c ()");
+        assert!(check_applied_migrations(&known, &[applied(&first), applied(&newer)]).is_err());
+
+        let changed = migration(2, "CREATE TABLE changed ()");
+        assert!(check_applied_migrations(&known, &[applied(&first), applied(&changed)]).is_err());
+    }
+}
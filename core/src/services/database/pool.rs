@@ -0,0 +1,25 @@
+use sqlx::Pool;
+use sqlx::pool::PoolOptions;
+
+use super::{PoolConfig, PoolStatus};
+
+/// Pool options shared by every storage with an actual connection pool
+pub(super) fn pool_options<DB: sqlx::Database>(config: &PoolConfig) -> PoolOptions<DB> {
+    PoolOptions::new()
+        .max_connections(config.max_connections)
+        .acquire_timeout(config.acquire_timeout)
+        .idle_timeout(config.idle_timeout)
+}
+
+/// Reads the pool's own counters, without taking a connection that queries
+/// might be waiting for
+pub(super) fn pool_status<DB: sqlx::Database>(pool: &Pool<DB>) -> PoolStatus {
+    let size = pool.size();
+    let idle = pool.num_idle() as u32;
+
+    PoolStatus {
+        max_connections: pool.options().get_max_connections(),
+        in_use: size.saturating_sub(idle),
+        idle,
+    }
+}
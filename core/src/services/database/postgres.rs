@@ -0,0 +1,1687 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use scooby::postgres::{
+    Aliasable, Joinable, Orderable, Parameters, Select, delete_from, insert_into, select, update,
+};
+use sqlx::postgres::{PgConnectOptions, PgConnection, PgPool, PgRow};
+use sqlx::query;
+use sqlx::{Executor, Postgres, Row};
+
+use super::{
+    Ban, BanKind, ClassificationOrder, ClassifiedProfile, ModerationLogEntry, NewPost, PoolConfig,
+    PoolStatus, Post, PostFilters, PostPreview, ProfileDetails, ReplyPolicy, Repost, Residency,
+    RetentionPolicy, RetentionScope, SchemaStatus, Storage, pool,
+};
+
+mod migrations;
+mod statements;
+
+use self::statements::{FeedPage, StatementCache};
+
+pub struct PostgresStorage {
+    connection_pool: PgPool,
+    feed_statements: StatementCache<(FeedPage, PostFilters, usize)>,
+    repost_statements: StatementCache<(bool, usize)>,
+    cache_statements: bool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str, config: &PoolConfig) -> Result<Self> {
+        let options = connect_options(url, config)?;
+
+        Ok(Self::with_pool(
+            pool::pool_options(config).connect_with(options).await?,
+        ))
+    }
+
+    /// Sets up the storage without connecting until it's first queried, so
+    /// that a database being down doesn't keep the process from starting
+    pub fn connect_lazy(url: &str, config: &PoolConfig) -> Result<Self> {
+        let options = connect_options(url, config)?;
+
+        Ok(Self::with_pool(
+            pool::pool_options(config).connect_lazy_with(options),
+        ))
+    }
+
+    fn with_pool(connection_pool: PgPool) -> Self {
+        Self {
+            connection_pool,
+            feed_statements: StatementCache::default(),
+            repost_statements: StatementCache::default(),
+            cache_statements: true,
+        }
+    }
+
+    /// Builds statements on the hot paths anew on every call, the way it
+    /// used to be done, so that benchmarks can compare it with building
+    /// them once
+    pub fn without_statement_cache(self) -> Self {
+        Self {
+            feed_statements: StatementCache::disabled(),
+            repost_statements: StatementCache::disabled(),
+            cache_statements: false,
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn migrate(&self) -> Result<SchemaStatus> {
+        self.run_migrations().await
+    }
+
+    async fn schema_status(&self) -> Result<SchemaStatus> {
+        self.check_schema().await
+    }
+
+    async fn baseline_schema(&self, up_to: i64) -> Result<usize> {
+        self.record_migrations_up_to(up_to).await
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(pool::pool_status(&self.connection_pool))
+    }
+
+    async fn insert_post(&self, post: &NewPost<'_>) -> Result<bool> {
+        Ok(query(&statements::INSERT_POST)
+            .bind(post.author_did)
+            .bind(post.cid)
+            .bind(post.uri)
+            .bind(post.created_at)
+            .bind(post.text)
+            .bind(post.language)
+            .bind(post.reply_parent_uri)
+            .bind(post.reply_root_uri)
+            .bind(post.embed_kind.map(|k| k.as_str()))
+            .bind(post.quoted_uri)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_recent_post_texts(&self, author_did: &str, limit: usize) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("text")
+                .from(all_posts_by_author(&params.next()))
+                .where_("text IS NOT NULL")
+                .order_by("created_at".desc())
+                .limit(limit)
+                .to_string(),
+        )
+        .bind(author_did)
+        .map(|r: PgRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_post_language_counts(&self, author_did: &str) -> Result<Vec<(String, i64)>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select(("language", "COUNT(*)"))
+                .from(all_posts_by_author(&params.next()))
+                .where_("language IS NOT NULL")
+                .group_by("language")
+                .order_by("COUNT(*)".desc())
+                .to_string(),
+        )
+        .bind(author_did)
+        .map(|r: PgRow| (r.get(0), r.get(1)))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        let page = match earlier_than {
+            Some(_) => FeedPage::LatestAfterCursor,
+            None => FeedPage::Latest,
+        };
+
+        let statement = self
+            .feed_statements
+            .get((page, filters.clone(), limit), || {
+                statements::posts_by_authors_country(page, filters, limit)
+            });
+
+        let mut query_object = query(&statement).bind(author_country);
+
+        if let Some((last_created_at, last_cid)) = earlier_than {
+            query_object = query_object.bind(last_created_at).bind(last_cid);
+        }
+
+        Ok(query_object
+            .map(post_from_row)
+            .fetch_all(&self.connection_pool)
+            .await?)
+    }
+
+    async fn fetch_top_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        offset: usize,
+        as_of: DateTime<Utc>,
+        max_age: TimeDelta,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        let statement = self
+            .feed_statements
+            .get((FeedPage::Top, filters.clone(), limit), || {
+                statements::posts_by_authors_country(FeedPage::Top, filters, limit)
+            });
+
+        Ok(query(&statement)
+            .bind(author_country)
+            .bind(as_of)
+            .bind(as_of - max_age)
+            .bind(offset as i64)
+            .map(post_from_row)
+            .fetch_all(&self.connection_pool)
+            .await?)
+    }
+
+    async fn delete_post(&self, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+        let uri_param = params.next();
+
+        // Only the rows returned by the outer statement count as affected, so
+        // count what both deletes returned instead
+        Ok(query(&format!(
+            "WITH deleted_pending AS ({} RETURNING uri), deleted AS ({} RETURNING uri) {}",
+            delete_from("PendingPost").where_(format!("uri = {uri_param}")),
+            delete_from("Post").where_(format!("uri = {uri_param}")),
+            select(format!(
+                "({}) + ({})",
+                select("COUNT(*)").from("deleted_pending"),
+                select("COUNT(*)").from("deleted")
+            ))
+        ))
+        .bind(uri)
+        .map(|r: PgRow| r.get::<i64, _>(0) > 0)
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<u64> {
+        let mut params = Parameters::new();
+        let expired = posts_past_retention_query(scope, policy, &mut params);
+
+        let sql_string = select("COUNT(*)")
+            .from(format!("({expired})").as_("p"))
+            .to_string();
+        let mut query_object = query(&sql_string);
+
+        if let RetentionScope::Country(country) = scope {
+            query_object = query_object.bind(country);
+        }
+
+        Ok(query_object
+            .bind(now - policy.max_age)
+            .map(|r: PgRow| r.get::<i64, _>(0) as u64)
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn delete_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<u64> {
+        let mut params = Parameters::new();
+        let expired = posts_past_retention_query(scope, policy, &mut params)
+            .order_by(format!("p.{}", policy.age_of.column()))
+            .limit(limit);
+
+        let sql_string = delete_from("Post")
+            .where_(format!("uri IN ({expired})"))
+            .to_string();
+        let mut query_object = query(&sql_string);
+
+        if let RetentionScope::Country(country) = scope {
+            query_object = query_object.bind(country);
+        }
+
+        Ok(query_object
+            .bind(now - policy.max_age)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected())?)
+    }
+
+    async fn insert_pending_post(
+        &self,
+        post: &NewPost<'_>,
+        self_labels: &[&str],
+        awaited_country: &str,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+        let values = params.next_array::<12>();
+
+        // Locking the profile makes classifying it wait for the post to be
+        // inserted, so that it gets settled along with the others, or makes
+        // this wait for the classification and then insert nothing
+        let unprocessed_author = format!(
+            "EXISTS ({} FOR SHARE)",
+            select("1")
+                .from("Profile")
+                .where_(format!("did = {}", values[0]))
+                .where_("has_been_processed = FALSE")
+        );
+
+        Ok(query(&format!(
+            "INSERT INTO PendingPost (author_did, cid, uri, created_at, text, language, reply_parent_uri, reply_root_uri, embed_kind, quoted_uri, self_labels, awaited_country) {} ON CONFLICT DO NOTHING",
+            select(values).where_(unprocessed_author)
+        ))
+        .bind(post.author_did)
+        .bind(post.cid)
+        .bind(post.uri)
+        .bind(post.created_at)
+        .bind(post.text)
+        .bind(post.language)
+        .bind(post.reply_parent_uri)
+        .bind(post.reply_root_uri)
+        .bind(post.embed_kind.map(|k| k.as_str()))
+        .bind(post.quoted_uri)
+        .bind(self_labels)
+        .bind(awaited_country)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn delete_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("PendingPost")
+                .where_(format!("indexed_at < {}", params.next()))
+                .to_string(),
+        )
+        .bind(earlier_than)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected())?)
+    }
+
+    async fn count_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("COUNT(*)")
+                .from("PendingPost")
+                .where_(format!("indexed_at < {}", params.next()))
+                .to_string(),
+        )
+        .bind(earlier_than)
+        .map(|r: PgRow| r.get::<i64, _>(0) as u64)
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn insert_repost_if_relevant(
+        &self,
+        author_did: &str,
+        cid: &str,
+        uri: &str,
+        post_uri: &str,
+        created_at: DateTime<Utc>,
+        reposter_country: &str,
+    ) -> Result<bool> {
+        Ok(query(&statements::INSERT_REPOST_IF_RELEVANT)
+            .bind(author_did)
+            .bind(cid)
+            .bind(uri)
+            .bind(post_uri)
+            .bind(created_at)
+            .bind(reposter_country)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_reposts_by_reposters_country(
+        &self,
+        reposter_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+    ) -> Result<Vec<Repost>> {
+        let after_cursor = earlier_than.is_some();
+
+        let statement = self.repost_statements.get((after_cursor, limit), || {
+            statements::reposts_by_reposters_country(after_cursor, limit)
+        });
+
+        let mut query_object = query(&statement).bind(reposter_country);
+
+        if let Some((last_created_at, last_cid)) = earlier_than {
+            query_object = query_object.bind(last_created_at).bind(last_cid);
+        }
+
+        Ok(query_object
+            .map(|r: PgRow| Repost {
+                created_at: r.get("created_at"),
+                author_did: r.get("author_did"),
+                cid: r.get("cid"),
+                uri: r.get("uri"),
+                post_uri: r.get("post_uri"),
+            })
+            .fetch_all(&self.connection_pool)
+            .await?)
+    }
+
+    async fn delete_repost(&self, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("Repost")
+                .where_(format!("uri = {}", params.next()))
+                .to_string(),
+        )
+        .bind(uri)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn insert_label_if_relevant(
+        &self,
+        uri: &str,
+        src: &str,
+        value: &str,
+        created_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [
+            uri_param,
+            src_param,
+            value_param,
+            created_at_param,
+            expires_at_param,
+        ] = params.next_array();
+
+        let known_post = select("1")
+            .from("Post")
+            .where_(format!("uri = {uri_param}"));
+
+        Ok(query(&format!(
+            "INSERT INTO PostLabel (uri, src, value, created_at, expires_at) {} ON CONFLICT (uri, src, value) DO UPDATE SET created_at = EXCLUDED.created_at, expires_at = EXCLUDED.expires_at",
+            select((
+                uri_param,
+                src_param,
+                value_param,
+                created_at_param,
+                expires_at_param
+            ))
+            .where_(format!("EXISTS ({known_post})"))
+        ))
+        .bind(uri)
+        .bind(src)
+        .bind(value)
+        .bind(created_at)
+        .bind(expires_at)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn delete_label(&self, uri: &str, src: &str, value: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("PostLabel")
+                .where_(format!("uri = {}", params.next()))
+                .where_(format!("src = {}", params.next()))
+                .where_(format!("value = {}", params.next()))
+                .to_string(),
+        )
+        .bind(uri)
+        .bind(src)
+        .bind(value)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn insert_like_if_relevant(
+        &self,
+        author_did: &str,
+        post_uri: &str,
+        uri: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        Ok(query(&statements::INSERT_LIKE_IF_RELEVANT)
+            .bind(author_did)
+            .bind(post_uri)
+            .bind(uri)
+            .bind(created_at)
+            .map(|r: PgRow| r.get(0))
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn delete_like(&self, uri: &str) -> Result<bool> {
+        Ok(query(&statements::DELETE_LIKE)
+            .bind(uri)
+            .map(|r: PgRow| r.get(0))
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn fetch_post_author_dids(&self) -> Result<Vec<String>> {
+        Ok(
+            query(&select("author_did").distinct().from("Post").to_string())
+                .map(|r: PgRow| r.get(0))
+                .fetch_all(&self.connection_pool)
+                .await?,
+        )
+    }
+
+    async fn fetch_liker_dids(&self) -> Result<Vec<String>> {
+        Ok(
+            query(&select("author_did").distinct().from("PostLike").to_string())
+                .map(|r: PgRow| r.get(0))
+                .fetch_all(&self.connection_pool)
+                .await?,
+        )
+    }
+
+    async fn insert_follow_if_relevant(
+        &self,
+        author_did: &str,
+        subject_did: &str,
+        uri: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        Ok(query(&statements::INSERT_FOLLOW_IF_RELEVANT)
+            .bind(author_did)
+            .bind(subject_did)
+            .bind(uri)
+            .bind(created_at)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn delete_follow(&self, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("Follow")
+                .where_(format!("uri = {}", params.next()))
+                .to_string(),
+        )
+        .bind(uri)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_followed_dids(&self, did: &str) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("subject_did")
+                .from("Follow")
+                .where_(format!("author_did = {}", params.next()))
+                .to_string(),
+        )
+        .bind(did)
+        .map(|r: PgRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_classified_followers(&self, did: &str, country: &str) -> Result<(i64, i64)> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "COUNT(*)",
+                format!(
+                    "COUNT(*) FILTER (WHERE pr.likely_country_of_living = {})",
+                    params.next()
+                ),
+            ))
+            .from(
+                "Follow"
+                    .as_("f")
+                    .inner_join("Profile".as_("pr"))
+                    .on("pr.did = f.author_did"),
+            )
+            .where_(format!("f.subject_did = {}", params.next()))
+            .where_("pr.has_been_processed = TRUE")
+            .to_string(),
+        )
+        .bind(country)
+        .bind(did)
+        .map(|r: PgRow| (r.get(0), r.get(1)))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn insert_profile_if_it_doesnt_exist(&self, did: &str) -> Result<bool> {
+        Ok(query(&statements::INSERT_PROFILE_IF_IT_DOESNT_EXIST)
+            .bind(did)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn claim_unprocessed_profile_dids(
+        &self,
+        limit: usize,
+        lease: TimeDelta,
+        order: ClassificationOrder,
+    ) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        let mut claimable = unprocessed_profiles_query(select("did")).limit(limit);
+
+        if order == ClassificationOrder::PostActivity {
+            claimable = claimable.order_by(
+                "(SELECT COUNT(*) FROM PendingPost WHERE PendingPost.author_did = Profile.did)"
+                    .desc(),
+            );
+        }
+
+        let claimable = format!(
+            "{} FOR UPDATE SKIP LOCKED",
+            claimable.order_by(("first_seen_at", "id"))
+        );
+
+        Ok(query(
+            &update("Profile")
+                .set("classification_attempts", "classification_attempts + 1")
+                .set(
+                    "next_classification_attempt_at",
+                    format!("NOW() + {}", params.next()),
+                )
+                .where_(format!("did IN ({claimable})"))
+                .returning("did")
+                .to_string(),
+        )
+        .bind(lease)
+        .map(|r: PgRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_unprocessed_profiles(&self) -> Result<i64> {
+        Ok(
+            query(&unprocessed_profiles_query(select("COUNT(*)")).to_string())
+                .map(|r: PgRow| r.get(0))
+                .fetch_one(&self.connection_pool)
+                .await?,
+        )
+    }
+
+    async fn record_profile_classification_failure(
+        &self,
+        did: &str,
+        error: &str,
+        max_attempts: i32,
+        backoff: TimeDelta,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &update("Profile")
+                .set("last_classification_error", params.next())
+                .set(
+                    "has_failed_classification",
+                    format!("classification_attempts >= {}", params.next()),
+                )
+                .set(
+                    "next_classification_attempt_at",
+                    format!(
+                        "NOW() + {} * POWER(2, LEAST(classification_attempts - 1, 16))",
+                        params.next()
+                    ),
+                )
+                .where_(format!("did = {}", params.next()))
+                .returning("has_failed_classification")
+                .to_string(),
+        )
+        .bind(error)
+        .bind(max_attempts)
+        .bind(backoff)
+        .bind(did)
+        .map(|r: PgRow| r.get(0))
+        .fetch_optional(&self.connection_pool)
+        .await?
+        .unwrap_or(false))
+    }
+
+    async fn create_classification_batch(
+        &self,
+        batch_id: &str,
+        profiles: &[(String, String)],
+    ) -> Result<()> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        {
+            let mut params = Parameters::new();
+
+            query(
+                &insert_into("ClassificationBatch")
+                    .columns(("batch_id",))
+                    .values([params.next()])
+                    .to_string(),
+            )
+            .bind(batch_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for (custom_id, did) in profiles {
+            let mut params = Parameters::new();
+
+            query(
+                &insert_into("ClassificationBatchProfile")
+                    .columns(("batch_id", "custom_id", "did"))
+                    .values([params.next_array()])
+                    .to_string(),
+            )
+            .bind(batch_id)
+            .bind(custom_id)
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn fetch_unfinished_classification_batches(
+        &self,
+    ) -> Result<Vec<(String, DateTime<Utc>)>> {
+        Ok(query(
+            &select(("batch_id", "COALESCE(submitted_at, NOW())"))
+                .from("ClassificationBatch")
+                .where_("ended_at IS NULL")
+                .order_by("submitted_at")
+                .to_string(),
+        )
+        .map(|r: PgRow| (r.get(0), r.get(1)))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_classification_batch_profiles(
+        &self,
+        batch_id: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select(("custom_id", "did"))
+                .from("ClassificationBatchProfile")
+                .where_(format!("batch_id = {}", params.next()))
+                .to_string(),
+        )
+        .bind(batch_id)
+        .map(|r: PgRow| (r.get("custom_id"), r.get("did")))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn finish_classification_batch(&self, batch_id: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &update("ClassificationBatch")
+                .set("ended_at", "NOW()")
+                .where_(format!("batch_id = {}", params.next()))
+                .to_string(),
+        )
+        .bind(batch_id)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn store_profile_details(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        residency_probability: Option<f64>,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let stored = {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Profile")
+                    .set("has_been_processed", "TRUE")
+                    .set("classified_at", "NOW()")
+                    .set("likely_country_of_living", params.next())
+                    .set("residency_probability", params.next())
+                    .set("next_classification_attempt_at", "NULL")
+                    .set("last_classification_error", "NULL")
+                    .where_(format!("did = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(likely_country_of_living)
+            .bind(residency_probability)
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        update_author_country_of_posts(&mut *transaction, did, Some(likely_country_of_living))
+            .await?;
+        settle_pending_posts(&mut transaction, did, likely_country_of_living).await?;
+
+        transaction.commit().await?;
+
+        Ok(stored)
+    }
+
+    async fn force_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        force_country(&mut transaction, did, likely_country_of_living).await?;
+
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn override_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        moderator: Option<&str>,
+    ) -> Result<()> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        force_country(&mut transaction, did, likely_country_of_living).await?;
+        log_moderation_action(
+            &mut *transaction,
+            "force_country",
+            did,
+            Some(likely_country_of_living),
+            moderator,
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn store_profile_description(
+        &self,
+        did: &str,
+        display_name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &update("Profile")
+                .set("display_name", params.next())
+                .set("description", params.next())
+                .where_(format!("did = {}", params.next()))
+                .to_string(),
+        )
+        .bind(display_name)
+        .bind(description)
+        .bind(did)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_recently_classified_profiles(
+        &self,
+        country: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ClassifiedProfile>> {
+        let mut params = Parameters::new();
+        let mut sql_builder = select((
+            "did",
+            "display_name",
+            "description",
+            "likely_country_of_living",
+            "residency_probability",
+            "classified_at",
+        ))
+        .from("Profile")
+        .where_("classified_at IS NOT NULL")
+        .order_by("classified_at".desc())
+        .limit(limit);
+
+        if country.is_some() {
+            sql_builder =
+                sql_builder.where_(format!("likely_country_of_living = {}", params.next()));
+        }
+
+        let sql_string = sql_builder.to_string();
+
+        let mut query_object = query(&sql_string);
+
+        if let Some(country) = country {
+            query_object = query_object.bind(country);
+        }
+
+        Ok(query_object
+            .map(|r: PgRow| ClassifiedProfile {
+                did: r.get("did"),
+                display_name: r.get("display_name"),
+                description: r.get("description"),
+                likely_country_of_living: r.get("likely_country_of_living"),
+                residency_probability: r.get("residency_probability"),
+                classified_at: r.get("classified_at"),
+            })
+            .fetch_all(&self.connection_pool)
+            .await?)
+    }
+
+    async fn fetch_recent_post_previews(
+        &self,
+        author_country: &str,
+        limit: usize,
+    ) -> Result<Vec<PostPreview>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "p.uri",
+                "p.author_did",
+                "pr.display_name",
+                "p.created_at",
+                "p.text",
+                "p.language",
+                "p.like_count",
+            ))
+            .from(
+                "Post"
+                    .as_("p")
+                    .inner_join("Profile".as_("pr"))
+                    .on("pr.did = p.author_did"),
+            )
+            .where_(format!("pr.likely_country_of_living = {}", params.next()))
+            .order_by(("p.created_at".desc(), "p.cid".desc()))
+            .limit(limit)
+            .to_string(),
+        )
+        .bind(author_country)
+        .map(|r: PgRow| PostPreview {
+            uri: r.get("uri"),
+            author_did: r.get("author_did"),
+            author_display_name: r.get("display_name"),
+            created_at: r.get("created_at"),
+            text: r.get("text"),
+            language: r.get("language"),
+            like_count: r.get("like_count"),
+        })
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_profile_details(&self, did: &str) -> Result<Option<ProfileDetails>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select([
+                "did",
+                "first_seen_at",
+                "has_been_processed",
+                "likely_country_of_living",
+                "residency_probability",
+                "classification_attempts",
+                "next_classification_attempt_at",
+                "has_failed_classification",
+                "last_classification_error",
+                "(SELECT COUNT(*) FROM Post WHERE Post.author_did = Profile.did) AS post_count",
+                "EXISTS (SELECT 1 FROM BannedProfile WHERE BannedProfile.did = Profile.did) AS is_banned",
+            ])
+            .from("Profile")
+            .where_(format!("did = {}", params.next()))
+            .to_string(),
+        )
+        .bind(did)
+        .map(|r: PgRow| ProfileDetails {
+            did: r.get("did"),
+            first_seen_at: r.get("first_seen_at"),
+            has_been_processed: r.get("has_been_processed"),
+            likely_country_of_living: r.get("likely_country_of_living"),
+            residency_probability: r.get("residency_probability"),
+            classification_attempts: r.get("classification_attempts"),
+            next_classification_attempt_at: r.get("next_classification_attempt_at"),
+            has_failed_classification: r.get("has_failed_classification"),
+            last_classification_error: r.get("last_classification_error"),
+            post_count: r.get("post_count"),
+            is_banned: r.get("is_banned"),
+        })
+        .fetch_optional(&self.connection_pool)
+        .await?)
+    }
+
+    async fn reset_profile_classification(&self, did: &str) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let reset = {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Profile")
+                    .set("has_been_processed", "FALSE")
+                    .set("classified_at", "NULL")
+                    .set("likely_country_of_living", "NULL")
+                    .set("residency_probability", "NULL")
+                    .set("classification_attempts", "0")
+                    .set("next_classification_attempt_at", "NULL")
+                    .set("has_failed_classification", "FALSE")
+                    .set("last_classification_error", "NULL")
+                    .where_(format!("did = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        update_author_country_of_posts(&mut *transaction, did, None).await?;
+
+        transaction.commit().await?;
+
+        Ok(reset)
+    }
+
+    async fn fetch_profile_residency(&self, did: &str, country: &str) -> Result<Option<Residency>> {
+        let statement = match self.cache_statements {
+            true => Cow::Borrowed(statements::FETCH_PROFILE_RESIDENCY.as_str()),
+            false => Cow::Owned(statements::fetch_profile_residency()),
+        };
+
+        Ok(query(&statement)
+            .bind(did)
+            .map(|r: PgRow| {
+                Residency::of(
+                    r.get::<Option<bool>, _>("has_been_processed")
+                        .unwrap_or_default(),
+                    r.get("has_failed_classification"),
+                    r.get("likely_country_of_living"),
+                    country,
+                )
+            })
+            .fetch_optional(&self.connection_pool)
+            .await?
+            .flatten())
+    }
+
+    async fn count_posts(&self) -> Result<i64> {
+        Ok(query(&statements::COUNT_POSTS)
+            .map(|r: PgRow| r.get(0))
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn count_classified_profiles_among(
+        &self,
+        dids: &[String],
+        country: &str,
+    ) -> Result<(i64, i64)> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "COUNT(*)",
+                format!(
+                    "COUNT(*) FILTER (WHERE likely_country_of_living = {})",
+                    params.next()
+                ),
+            ))
+            .from("Profile")
+            .where_(format!("did = ANY({})", params.next()))
+            .where_("has_been_processed = TRUE")
+            .to_string(),
+        )
+        .bind(country)
+        .bind(dids)
+        .map(|r: PgRow| (r.get(0), r.get(1)))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_failed_profiles(&self) -> Result<i64> {
+        Ok(query(
+            &select("COUNT(*)")
+                .from("Profile")
+                .where_("has_failed_classification = TRUE")
+                .to_string(),
+        )
+        .map(|r: PgRow| r.get(0))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_profiles_in_country(&self, country: &str) -> Result<i64> {
+        Ok(query(&statements::COUNT_PROFILES_IN_COUNTRY)
+            .bind(country)
+            .map(|r: PgRow| r.get(0))
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn fetch_profile_dids_in_country(&self, country: &str) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("did")
+                .from("Profile")
+                .where_(format!("likely_country_of_living = {}", params.next()))
+                .where_("has_been_processed = TRUE")
+                .order_by("did")
+                .to_string(),
+        )
+        .bind(country)
+        .map(|r: PgRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn ban(
+        &self,
+        kind: BanKind,
+        subject: &str,
+        reason: &str,
+        moderator: Option<&str>,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let banned = {
+            let mut params = Parameters::new();
+
+            query(
+                &insert_into(kind.table())
+                    .columns((kind.subject_column(), "reason"))
+                    .values([params.next_array()])
+                    .on_conflict()
+                    .do_nothing()
+                    .to_string(),
+            )
+            .bind(subject)
+            .bind(reason)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        if banned {
+            log_moderation_action(
+                &mut *transaction,
+                kind.action(true),
+                subject,
+                Some(reason),
+                moderator,
+            )
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(banned)
+    }
+
+    async fn unban(&self, kind: BanKind, subject: &str, moderator: Option<&str>) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let unbanned = {
+            let mut params = Parameters::new();
+
+            query(
+                &delete_from(kind.table())
+                    .where_(format!("{} = {}", kind.subject_column(), params.next()))
+                    .to_string(),
+            )
+            .bind(subject)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        if unbanned {
+            log_moderation_action(
+                &mut *transaction,
+                kind.action(false),
+                subject,
+                None,
+                moderator,
+            )
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(unbanned)
+    }
+
+    async fn fetch_bans(&self, kind: BanKind) -> Result<Vec<Ban>> {
+        Ok(query(
+            &select((kind.subject_column().as_("subject"), "reason", "banned_at"))
+                .from(kind.table())
+                .order_by("banned_at".desc())
+                .to_string(),
+        )
+        .map(|r: PgRow| Ban {
+            kind,
+            subject: r.get("subject"),
+            reason: r.get("reason"),
+            banned_at: r.get("banned_at"),
+        })
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn is_post_banned(&self, author_did: &str, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [author, uri_param] = params.next_array();
+
+        Ok(query(&format!(
+            "SELECT EXISTS ({}) OR EXISTS ({})",
+            select("1")
+                .from("BannedProfile")
+                .where_(format!("did = {author}")),
+            select("1")
+                .from("BannedPost")
+                .where_(format!("uri = {uri_param}")),
+        ))
+        .bind(author_did)
+        .bind(uri)
+        .map(|r: PgRow| r.get(0))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn record_moderation_action(
+        &self,
+        action: &str,
+        subject: &str,
+        reason: Option<&str>,
+        moderator: Option<&str>,
+    ) -> Result<()> {
+        log_moderation_action(&self.connection_pool, action, subject, reason, moderator).await
+    }
+
+    async fn fetch_moderation_log(&self, limit: usize) -> Result<Vec<ModerationLogEntry>> {
+        Ok(query(
+            &select(("performed_at", "action", "subject", "reason", "moderator"))
+                .from("ModerationLog")
+                .order_by("performed_at".desc())
+                .limit(limit)
+                .to_string(),
+        )
+        .map(|r: PgRow| ModerationLogEntry {
+            performed_at: r.get("performed_at"),
+            action: r.get("action"),
+            subject: r.get("subject"),
+            reason: r.get("reason"),
+            moderator: r.get("moderator"),
+        })
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_subscription_cursor(&self, host: &str, did: &str) -> Result<Option<i64>> {
+        Ok(query(&statements::FETCH_SUBSCRIPTION_CURSOR)
+            .bind(did)
+            .bind(host)
+            .map(|r: PgRow| r.get("cursor"))
+            .fetch_optional(&self.connection_pool)
+            .await?)
+    }
+
+    async fn create_subscription_state(&self, host: &str, did: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &insert_into("SubscriptionState")
+                .columns(("service", "cursor", "host"))
+                .values([params.next_array()])
+                .to_string(),
+        )
+        .bind(did)
+        .bind(0)
+        .bind(host)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn update_subscription_cursor(&self, host: &str, did: &str, cursor: i64) -> Result<bool> {
+        Ok(query(&statements::UPDATE_SUBSCRIPTION_CURSOR)
+            .bind(cursor)
+            .bind(did)
+            .bind(host)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)?)
+    }
+}
+
+fn connect_options(url: &str, config: &PoolConfig) -> Result<PgConnectOptions> {
+    let mut options = PgConnectOptions::from_str(url)?;
+
+    if let Some(timeout) = config.statement_timeout {
+        options = options.options([("statement_timeout", timeout.as_millis())]);
+    }
+
+    Ok(options)
+}
+
+/// Selects (as `ap`) texts and languages of posts by the author, including
+/// the ones held back until the author is classified
+fn all_posts_by_author(author: &str) -> String {
+    let posts_in = |table: &str| {
+        select(("text", "language", "created_at"))
+            .from(table)
+            .where_(format!("author_did = {author}"))
+    };
+
+    format!(
+        "({} UNION ALL {}) AS ap",
+        posts_in("Post"),
+        posts_in("PendingPost")
+    )
+}
+
+fn unprocessed_profiles_query(statement: Select) -> Select {
+    statement
+        .from("Profile")
+        .where_("has_been_processed = FALSE")
+        .where_("has_failed_classification = FALSE")
+        .where_(
+            "(next_classification_attempt_at IS NULL OR next_classification_attempt_at <= NOW())",
+        )
+        .where_(format!(
+            "did NOT IN ({})",
+            select("bp.did")
+                .from(
+                    "ClassificationBatchProfile"
+                        .as_("bp")
+                        .inner_join("ClassificationBatch".as_("b"))
+                        .on("b.batch_id = bp.batch_id")
+                )
+                .where_("b.ended_at IS NULL")
+        ))
+}
+
+/// Selects posts (as `p`) by authors from the given country that pass the
+/// filters, in the shape expected by `post_from_row`
+fn posts_by_authors_country_query(country: &str, filters: &PostFilters) -> Select {
+    let labels = if filters.mark_labeled {
+        format!(
+            "ARRAY({})",
+            select("DISTINCT l.value")
+                .from("PostLabel".as_("l"))
+                .where_("l.uri = p.uri")
+                .where_(LABEL_IS_ACTIVE)
+        )
+    } else {
+        "ARRAY[]::TEXT[]".to_owned()
+    };
+
+    let mut statement = select((
+        "p.created_at",
+        "p.author_did",
+        "p.cid",
+        "p.uri",
+        labels.as_("labels"),
+    ))
+    .from("Post".as_("p"))
+    .where_(format!("p.author_country = {country}"))
+    .where_(is_not_banned("p.author_did", "p.uri"));
+
+    statement = match filters.replies {
+        ReplyPolicy::Show => statement,
+        ReplyPolicy::Hide => statement.where_("p.reply_parent_uri IS NULL"),
+        ReplyPolicy::OnlyToFeedAuthors => statement.where_(format!(
+            "(p.reply_parent_uri IS NULL OR {})",
+            is_by_author_from("p.reply_parent_uri", country)
+        )),
+        ReplyPolicy::CollapseThreads => statement.where_(format!(
+            "NOT EXISTS ({})",
+            select("1")
+                .from("Post".as_("tp"))
+                .where_("COALESCE(tp.reply_root_uri, tp.uri) = COALESCE(p.reply_root_uri, p.uri)")
+                .where_(format!("tp.author_country = {country}"))
+                .where_("(tp.created_at, tp.cid) > (p.created_at, p.cid)")
+        )),
+    };
+
+    if !filters.embed_kinds.is_empty() {
+        let kinds = filters
+            .embed_kinds
+            .iter()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>();
+
+        statement = statement.where_(format!("p.embed_kind IN ({})", string_literals(&kinds)));
+    }
+
+    if !filters.hidden_labels.is_empty() {
+        statement = statement.where_(format!(
+            "NOT EXISTS ({})",
+            select("1")
+                .from("PostLabel".as_("l"))
+                .where_("l.uri = p.uri")
+                .where_(LABEL_IS_ACTIVE)
+                .where_(format!(
+                    "l.value IN ({})",
+                    string_literals(&filters.hidden_labels)
+                ))
+        ));
+    }
+
+    if filters.hide_quotes_from_outside {
+        statement = statement.where_(format!(
+            "(p.quoted_uri IS NULL OR {})",
+            is_by_author_from("p.quoted_uri", country)
+        ));
+    }
+
+    statement
+}
+
+/// Selects URIs of posts (as `p`) in the scope that the policy says should be
+/// deleted. Binds the country of the scope if there is one, then the moment
+/// posts older than are deleted.
+fn posts_past_retention_query(
+    scope: RetentionScope<'_>,
+    policy: &RetentionPolicy,
+    params: &mut Parameters,
+) -> Select {
+    let country = match scope {
+        RetentionScope::Country(_) => Some(params.next()),
+        RetentionScope::OtherCountries(_) => None,
+    };
+    let in_scope = |alias: &str| match (scope, &country) {
+        (RetentionScope::Country(_), Some(country)) => {
+            format!("{alias}.author_country = {country}")
+        }
+        (RetentionScope::OtherCountries(countries), _) if !countries.is_empty() => format!(
+            "({alias}.author_country IS NULL OR {alias}.author_country NOT IN ({}))",
+            string_literals(countries)
+        ),
+        _ => "TRUE".to_owned(),
+    };
+    let timestamp = policy.age_of.column();
+
+    let mut expired = format!("p.{timestamp} < {}", params.next());
+
+    if let Some(max_posts) = policy.max_posts {
+        // Posts as old as the last one to keep are kept too, so that ties
+        // don't get decided at random
+        expired = format!(
+            "({expired} OR p.{timestamp} < ({}))",
+            select(format!("np.{timestamp}"))
+                .from("Post".as_("np"))
+                .where_(in_scope("np"))
+                .order_by(format!("np.{timestamp}").desc())
+                .offset(max_posts - 1)
+                .limit(1)
+        );
+    }
+
+    let mut statement = select("p.uri")
+        .from("Post".as_("p"))
+        .where_(in_scope("p"))
+        .where_(expired);
+
+    if let Some(keep_liked) = policy.keep_liked {
+        statement = statement.where_(format!("p.like_count < {keep_liked}"));
+    }
+
+    statement
+}
+
+/// Condition checking that neither the profile nor the record have been banned
+fn is_not_banned(did_column: &str, uri_column: &str) -> String {
+    format!(
+        "NOT EXISTS ({}) AND NOT EXISTS ({})",
+        select("1")
+            .from("BannedProfile")
+            .where_(format!("BannedProfile.did = {did_column}")),
+        select("1")
+            .from("BannedPost")
+            .where_(format!("BannedPost.uri = {uri_column}")),
+    )
+}
+
+/// Indexes the pending posts of a freshly classified profile that were
+/// waiting for the country it was classified as living in, and drops the rest
+async fn settle_pending_posts(
+    connection: &mut PgConnection,
+    did: &str,
+    country: &str,
+) -> Result<()> {
+    let mut params = Parameters::new();
+    let [did_param, country_param] = params.next_array();
+
+    let awaiting = |columns: &[&str]| {
+        select(columns.join(", "))
+            .from("PendingPost".as_("pp"))
+            .where_(format!("pp.author_did = {did_param}"))
+            .where_(format!("pp.awaited_country = {country_param}"))
+    };
+
+    query(&format!(
+        "INSERT INTO Post (author_did, cid, uri, created_at, text, language, reply_parent_uri, reply_root_uri, embed_kind, quoted_uri, author_country) {} ON CONFLICT DO NOTHING",
+        awaiting(&[
+            "pp.author_did",
+            "pp.cid",
+            "pp.uri",
+            "pp.created_at",
+            "pp.text",
+            "pp.language",
+            "pp.reply_parent_uri",
+            "pp.reply_root_uri",
+            "pp.embed_kind",
+            "pp.quoted_uri",
+            "pp.awaited_country",
+        ])
+    ))
+    .bind(did)
+    .bind(country)
+    .execute(&mut *connection)
+    .await?;
+
+    query(&format!(
+        "INSERT INTO PostLabel (uri, src, value, created_at) {} ON CONFLICT DO NOTHING",
+        awaiting(&[
+            "pp.uri",
+            "pp.author_did",
+            "UNNEST(pp.self_labels)",
+            "pp.created_at"
+        ])
+    ))
+    .bind(did)
+    .bind(country)
+    .execute(&mut *connection)
+    .await?;
+
+    let mut params = Parameters::new();
+
+    query(
+        &delete_from("PendingPost")
+            .where_(format!("author_did = {}", params.next()))
+            .to_string(),
+    )
+    .bind(did)
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Keeps the country copied onto the posts of a profile in line with the
+/// profile itself, so that feeds can select posts without joining profiles
+async fn update_author_country_of_posts<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    did: &str,
+    country: Option<&str>,
+) -> Result<()> {
+    let mut params = Parameters::new();
+    let [country_param, did_param] = params.next_array();
+
+    query(
+        &update("Post")
+            .set("author_country", country_param.as_str())
+            .where_(format!("author_did = {did_param}"))
+            .where_(format!("author_country IS DISTINCT FROM {country_param}"))
+            .to_string(),
+    )
+    .bind(country)
+    .bind(did)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Sets the country of a profile by hand, creating the profile if needed
+async fn force_country(
+    connection: &mut PgConnection,
+    did: &str,
+    likely_country_of_living: &str,
+) -> Result<()> {
+    {
+        let mut params = Parameters::new();
+
+        query(
+            &insert_into("Profile")
+                .columns(("did",))
+                .values([params.next()])
+                .on_conflict()
+                .do_nothing()
+                .to_string(),
+        )
+        .bind(did)
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    {
+        let mut params = Parameters::new();
+        query(
+            &update("Profile")
+                .set("has_been_processed", "TRUE")
+                .set("classified_at", "NOW()")
+                .set("has_failed_classification", "FALSE")
+                .set("likely_country_of_living", params.next())
+                .where_(format!("did = {}", params.next()))
+                .to_string(),
+        )
+        .bind(likely_country_of_living)
+        .bind(did)
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    update_author_country_of_posts(&mut *connection, did, Some(likely_country_of_living)).await?;
+    settle_pending_posts(connection, did, likely_country_of_living).await?;
+
+    Ok(())
+}
+
+async fn log_moderation_action<'e>(
+    executor: impl Executor<'e, Database = Postgres>,
+    action: &str,
+    subject: &str,
+    reason: Option<&str>,
+    moderator: Option<&str>,
+) -> Result<()> {
+    let mut params = Parameters::new();
+
+    query(
+        &insert_into("ModerationLog")
+            .columns(("action", "subject", "reason", "moderator"))
+            .values([params.next_array()])
+            .to_string(),
+    )
+    .bind(action)
+    .bind(subject)
+    .bind(reason)
+    .bind(moderator)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+const LABEL_IS_ACTIVE: &str = "(l.expires_at IS NULL OR l.expires_at > NOW())";
+
+fn post_from_row(r: PgRow) -> Post {
+    Post {
+        created_at: r.get("created_at"),
+        author_did: r.get("author_did"),
+        cid: r.get("cid"),
+        uri: r.get("uri"),
+        labels: r.get("labels"),
+    }
+}
+
+/// Formats values defined in code as a list of SQL string literals
+fn string_literals(values: &[impl AsRef<str>]) -> String {
+    values
+        .iter()
+        .map(|v| format!("'{}'", v.as_ref().replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Expression extracting the DID of whoever made the record behind the
+/// `at://` URI in the given column
+fn author_of(uri_column: &str) -> String {
+    format!("SPLIT_PART({uri_column}, '/', 3)")
+}
+
+/// Condition checking that the record behind the URI in the given column was
+/// made by someone from the given country
+fn is_by_author_from(uri_column: &str, country: &str) -> String {
+    format!(
+        "EXISTS ({})",
+        select("1")
+            .from("Profile".as_("apr"))
+            .where_(format!("apr.did = {}", author_of(uri_column)))
+            .where_(format!("apr.likely_country_of_living = {country}"))
+    )
+}
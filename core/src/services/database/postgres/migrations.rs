@@ -0,0 +1,127 @@
+use anyhow::{Result, anyhow, bail};
+use log::info;
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::query;
+use sqlx::query_scalar;
+
+use super::{PostgresStorage, SchemaStatus};
+use crate::services::database::migrations::check_applied_migrations;
+
+/// Migrations in the `sql/` directory, embedded at build time
+static MIGRATOR: Migrator = sqlx::migrate!("../sql");
+
+impl PostgresStorage {
+    pub(super) async fn run_migrations(&self) -> Result<SchemaStatus> {
+        let status = self.check_schema().await?;
+
+        if status.pending > 0 {
+            info!(
+                "Applying {} database migrations up to version {}",
+                status.pending, status.expected_version
+            );
+
+            MIGRATOR.run(&self.connection_pool).await?;
+        }
+
+        Ok(SchemaStatus {
+            current_version: Some(status.expected_version),
+            pending: 0,
+            ..status
+        })
+    }
+
+    pub(super) async fn check_schema(&self) -> Result<SchemaStatus> {
+        let expected_version = expected_schema_version();
+
+        if !self.has_migration_history().await? {
+            if self.has_table("profile").await? {
+                bail!(
+                    "The database has tables but no migration history. If its schema is up to \
+                     date with the migrations in sql/, mark them as applied with \
+                     `cargo run --bin migrate -- baseline --up-to {}`",
+                    expected_version
+                );
+            }
+
+            return Ok(SchemaStatus {
+                current_version: None,
+                expected_version,
+                pending: MIGRATOR.iter().count(),
+            });
+        }
+
+        let mut connection = self.connection_pool.acquire().await?;
+
+        if let Some(version) = connection.dirty_version().await? {
+            bail!(
+                "Migration {} failed partway through and has to be fixed by hand",
+                version
+            );
+        }
+
+        let applied = connection.list_applied_migrations().await?;
+
+        check_applied_migrations(MIGRATOR.migrations.as_ref(), &applied)?;
+
+        Ok(SchemaStatus {
+            current_version: applied.last().map(|m| m.version),
+            expected_version,
+            pending: MIGRATOR.iter().count() - applied.len(),
+        })
+    }
+
+    pub(super) async fn record_migrations_up_to(&self, up_to: i64) -> Result<usize> {
+        if !MIGRATOR.version_exists(up_to) {
+            return Err(anyhow!("There's no migration with version {}", up_to));
+        }
+
+        let mut transaction = self.connection_pool.begin().await?;
+
+        transaction.ensure_migrations_table().await?;
+
+        let mut recorded = 0;
+        for migration in MIGRATOR.iter().filter(|m| m.version <= up_to) {
+            recorded += query(
+                "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) \
+                 VALUES ($1, $2, TRUE, $3, -1) \
+                 ON CONFLICT DO NOTHING",
+            )
+            .bind(migration.version)
+            .bind(migration.description.as_ref())
+            .bind(migration.checksum.as_ref())
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected() as usize;
+        }
+
+        transaction.commit().await?;
+
+        Ok(recorded)
+    }
+
+    async fn has_migration_history(&self) -> Result<bool> {
+        self.has_table("_sqlx_migrations").await
+    }
+
+    async fn has_table(&self, name: &str) -> Result<bool> {
+        Ok(query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(name)
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+}
+
+fn expected_schema_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embeds_all_migrations() {
+        assert_eq!(MIGRATOR.iter().next().map(|m| m.version), Some(1));
+        assert_eq!(MIGRATOR.iter().count() as i64, expected_schema_version());
+    }
+}
@@ -0,0 +1,317 @@
+//! Statements on the hot paths of the post indexer and the feed server,
+//! built once so that their text is the same on every call and Postgres only
+//! has to prepare them once per connection.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use scooby::postgres::{
+    Aliasable, Joinable, Orderable, Parameters, delete_from, insert_into, select, update,
+};
+
+use super::{author_of, is_not_banned, posts_by_authors_country_query};
+use crate::services::database::PostFilters;
+
+pub(super) static INSERT_POST: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+    let [
+        author,
+        cid,
+        uri,
+        created_at,
+        text,
+        language,
+        parent,
+        root,
+        embed_kind,
+        quoted,
+    ] = params.next_array();
+
+    let author_country = select("likely_country_of_living")
+        .from("Profile")
+        .where_(format!("did = {author}"));
+
+    insert_into("Post")
+        .columns((
+            "author_did",
+            "cid",
+            "uri",
+            "created_at",
+            "text",
+            "language",
+            "reply_parent_uri",
+            "reply_root_uri",
+            "embed_kind",
+            "quoted_uri",
+            "author_country",
+        ))
+        .values([[
+            author,
+            cid,
+            uri,
+            created_at,
+            text,
+            language,
+            parent,
+            root,
+            embed_kind,
+            quoted,
+            format!("({author_country})"),
+        ]])
+        .on_conflict()
+        .do_nothing()
+        .to_string()
+});
+
+pub(super) static INSERT_REPOST_IF_RELEVANT: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+    let [author, cid, uri, post, created_at, country] = params.next_array();
+
+    let resident = select("1")
+        .from("Profile")
+        .where_(format!("did = {author}"))
+        .where_(format!("likely_country_of_living = {country}"));
+
+    format!(
+        "INSERT INTO Repost (author_did, cid, uri, post_uri, created_at) {} ON CONFLICT DO NOTHING",
+        select((author, cid, uri, post, created_at)).where_(format!("EXISTS ({resident})"))
+    )
+});
+
+/// Stores a like and bumps the like count of its post in one round trip,
+/// returning whether the like was stored.
+pub(super) static INSERT_LIKE_IF_RELEVANT: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+    let [author, post, uri, created_at] = params.next_array();
+
+    let known_post = select("1").from("Post").where_(format!("uri = {post}"));
+
+    let increment = update("Post")
+        .set("like_count", "like_count + 1")
+        .where_(format!("uri IN ({})", select("post_uri").from("inserted")));
+
+    format!(
+        "WITH inserted AS (INSERT INTO PostLike (author_did, post_uri, uri, created_at) {} ON CONFLICT DO NOTHING RETURNING post_uri), incremented AS ({increment}) {}",
+        select((author, post, uri, created_at)).where_(format!("EXISTS ({known_post})")),
+        select(format!("EXISTS ({})", select("1").from("inserted")))
+    )
+});
+
+/// Deletes a like and lowers the like count of its post in one round trip,
+/// returning whether there was a like to delete.
+pub(super) static DELETE_LIKE: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+
+    let delete = delete_from("PostLike")
+        .where_(format!("uri = {}", params.next()))
+        .returning("post_uri");
+
+    let decrement = update("Post")
+        .set("like_count", "GREATEST(like_count - 1, 0)")
+        .where_(format!("uri IN ({})", select("post_uri").from("deleted")));
+
+    format!(
+        "WITH deleted AS ({delete}), decremented AS ({decrement}) {}",
+        select(format!("EXISTS ({})", select("1").from("deleted")))
+    )
+});
+
+pub(super) static INSERT_FOLLOW_IF_RELEVANT: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+    let [author, subject, uri, created_at] = params.next_array();
+
+    let known_profile = select("1")
+        .from("Profile")
+        .where_(format!("did IN ({author}, {subject})"));
+
+    format!(
+        "INSERT INTO Follow (author_did, subject_did, uri, created_at) {} ON CONFLICT DO NOTHING",
+        select((author, subject, uri, created_at)).where_(format!("EXISTS ({known_profile})"))
+    )
+});
+
+pub(super) static INSERT_PROFILE_IF_IT_DOESNT_EXIST: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+
+    insert_into("Profile")
+        .columns(("did",))
+        .values([params.next()])
+        .on_conflict()
+        .do_nothing()
+        .to_string()
+});
+
+pub(super) static FETCH_PROFILE_RESIDENCY: Lazy<String> = Lazy::new(fetch_profile_residency);
+
+pub(super) fn fetch_profile_residency() -> String {
+    let mut params = Parameters::new();
+
+    select((
+        "has_been_processed",
+        "has_failed_classification",
+        "likely_country_of_living",
+    ))
+    .from("Profile")
+    .where_(format!("did = {}", params.next()))
+    .to_string()
+}
+
+pub(super) static COUNT_POSTS: Lazy<String> =
+    Lazy::new(|| select("COUNT(*)").from("Post").to_string());
+
+pub(super) static COUNT_PROFILES_IN_COUNTRY: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+
+    select("COUNT(*)")
+        .from("Profile")
+        .where_(format!("likely_country_of_living = {}", params.next()))
+        .to_string()
+});
+
+pub(super) static FETCH_SUBSCRIPTION_CURSOR: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+
+    select("cursor")
+        .from("SubscriptionState")
+        .where_(format!("service = {}", params.next()))
+        .where_(format!("host = {}", params.next()))
+        .to_string()
+});
+
+pub(super) static UPDATE_SUBSCRIPTION_CURSOR: Lazy<String> = Lazy::new(|| {
+    let mut params = Parameters::new();
+
+    update("SubscriptionState")
+        .set("cursor", params.next())
+        .where_(format!("service = {}", params.next()))
+        .where_(format!("host = {}", params.next()))
+        .to_string()
+});
+
+/// Statements that also depend on something varying between calls, such as
+/// the filters of a feed or how many rows to fetch, built the first time each
+/// variation is needed. Limits are kept in the text rather than bound, since
+/// Postgres can't plan for a small limit without knowing it.
+pub(super) struct StatementCache<K> {
+    built: Mutex<HashMap<K, Arc<str>>>,
+    enabled: bool,
+}
+
+impl<K> Default for StatementCache<K> {
+    fn default() -> Self {
+        Self {
+            built: Mutex::new(HashMap::new()),
+            enabled: true,
+        }
+    }
+}
+
+impl<K> StatementCache<K> {
+    /// A cache that builds statements on every call, the way they used to be
+    /// built, for comparing the two in benchmarks
+    pub(super) fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+}
+
+impl<K: Hash + Eq> StatementCache<K> {
+    pub(super) fn get(&self, key: K, build: impl FnOnce() -> String) -> Arc<str> {
+        if !self.enabled {
+            return build().into();
+        }
+
+        let mut built = self.built.lock().expect("statement cache lock poisoned");
+
+        built.entry(key).or_insert_with(|| build().into()).clone()
+    }
+}
+
+/// Which posts of a feed a statement fetches. First pages and the ones after
+/// them get separate statements, since a single one handling both would keep
+/// Postgres from planning either of them well.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum FeedPage {
+    Latest,
+    LatestAfterCursor,
+    Top,
+}
+
+pub(super) fn posts_by_authors_country(
+    page: FeedPage,
+    filters: &PostFilters,
+    limit: usize,
+) -> String {
+    let mut params = Parameters::new();
+    let country = params.next();
+
+    let statement = posts_by_authors_country_query(&country, filters);
+
+    match page {
+        FeedPage::Latest => statement
+            .order_by(("p.created_at".desc(), "p.cid".desc()))
+            .limit(limit)
+            .to_string(),
+        FeedPage::LatestAfterCursor => statement
+            .where_(format!(
+                "(p.created_at, p.cid) < ({}, {})",
+                params.next(),
+                params.next()
+            ))
+            .order_by(("p.created_at".desc(), "p.cid".desc()))
+            .limit(limit)
+            .to_string(),
+        FeedPage::Top => {
+            let [as_of, since, offset] = params.next_array();
+
+            let score = format!(
+                "p.like_count / POWER(GREATEST(EXTRACT(EPOCH FROM {as_of} - p.created_at), 0) / 3600 + 2, 1.8)"
+            );
+
+            let statement = statement
+                .where_(format!("p.created_at <= {as_of}"))
+                .where_(format!("p.created_at > {since}"))
+                .order_by((score.desc(), "p.created_at".desc(), "p.cid".desc()))
+                .limit(limit);
+
+            format!("{statement} OFFSET {offset}")
+        }
+    }
+}
+
+pub(super) fn reposts_by_reposters_country(after_cursor: bool, limit: usize) -> String {
+    let mut params = Parameters::new();
+
+    let mut statement = select((
+        "r.created_at",
+        "r.author_did",
+        "r.cid",
+        "r.uri",
+        "r.post_uri",
+    ))
+    .from(
+        "Repost"
+            .as_("r")
+            .inner_join("Profile".as_("pr"))
+            .on("pr.did = r.author_did"),
+    )
+    .where_(format!("pr.likely_country_of_living = {}", params.next()))
+    .where_(is_not_banned("r.author_did", "r.uri"))
+    .where_(is_not_banned(&author_of("r.post_uri"), "r.post_uri"))
+    .order_by(("r.created_at".desc(), "r.cid".desc()))
+    .limit(limit);
+
+    if after_cursor {
+        statement = statement.where_(format!(
+            "(r.created_at, r.cid) < ({}, {})",
+            params.next(),
+            params.next()
+        ));
+    }
+
+    statement.to_string()
+}
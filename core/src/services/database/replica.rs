@@ -0,0 +1,167 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::Storage;
+
+/// Read-only copy of the database that feed and stats queries go to, to take
+/// load off the primary one. When it can't be reached, those queries go to
+/// the primary database for a while before the replica is tried again.
+pub(super) struct Replica {
+    storage: Box<dyn Storage>,
+    unavailable_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    pub(super) const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(2);
+    const RETRY_AFTER: Duration = Duration::from_secs(30);
+
+    pub(super) fn new(storage: Box<dyn Storage>) -> Self {
+        Self {
+            storage,
+            unavailable_until: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// The replica's storage, unless it failed recently
+    pub(super) fn available(&self) -> Option<&dyn Storage> {
+        let unavailable_until = self
+            .unavailable_until
+            .lock()
+            .expect("replica lock poisoned");
+
+        match *unavailable_until {
+            Some(until) if Instant::now() < until => None,
+            _ => Some(self.storage.as_ref()),
+        }
+    }
+
+    /// Stops using the replica for a while if the error means it can't be
+    /// reached. Other errors, such as ones in the query itself, would happen
+    /// on the primary database just the same, so they are passed on.
+    pub(super) fn fall_back(&self, error: anyhow::Error) -> anyhow::Result<()> {
+        if !is_connection_error(&error) {
+            return Err(error);
+        }
+
+        warn!(
+            "Read replica failed, using the primary database for {:?}: {}",
+            Self::RETRY_AFTER,
+            error
+        );
+
+        metrics::counter!("database_replica_failures_total").increment(1);
+
+        *self
+            .unavailable_until
+            .lock()
+            .expect("replica lock poisoned") = Some(Instant::now() + Self::RETRY_AFTER);
+
+        Ok(())
+    }
+}
+
+/// Whether the error came from not being able to talk to the database at
+/// all, as opposed to the database refusing a query
+fn is_connection_error(error: &anyhow::Error) -> bool {
+    let Some(error) = error.downcast_ref::<sqlx::Error>() else {
+        return false;
+    };
+
+    match error {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // Connection exceptions, and the server shutting down or starting up
+        sqlx::Error::Database(e) => e
+            .code()
+            .is_some_and(|code| code.starts_with("08") || code.starts_with("57P")),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::database::{
+        Database, InMemoryStorage, PoolConfig, SqliteStorage, Storage,
+    };
+
+    #[tokio::test]
+    async fn reads_from_replica_and_falls_back_to_primary() {
+        let replica = InMemoryStorage::default();
+        replica.force_profile_country("did:a", "nl").await.unwrap();
+        replica.force_profile_country("did:b", "nl").await.unwrap();
+
+        let database = Database::in_memory().with_replica(replica);
+        database.force_profile_country("did:a", "nl").await.unwrap();
+
+        assert_eq!(database.count_profiles_in_country("nl").await.unwrap(), 2);
+
+        // Nothing listens on this port, so the replica can't be connected to
+        let database = Database::in_memory()
+            .with_replica_url(
+                "postgres://localhost:1/nederlandskie",
+                &PoolConfig::default(),
+            )
+            .unwrap();
+        database.force_profile_country("did:a", "nl").await.unwrap();
+
+        assert_eq!(database.count_profiles_in_country("nl").await.unwrap(), 1);
+        assert!(database.available_replica().is_none());
+    }
+
+    #[tokio::test]
+    async fn passes_on_query_errors_from_replica() {
+        // Without migrations, every query against it fails
+        let replica = SqliteStorage::connect("sqlite::memory:", &PoolConfig::default())
+            .await
+            .unwrap();
+
+        let database = Database::in_memory().with_replica(replica);
+        database.force_profile_country("did:a", "nl").await.unwrap();
+
+        assert!(database.count_profiles_in_country("nl").await.is_err());
+        assert!(database.available_replica().is_some());
+    }
+
+    #[tokio::test]
+    async fn reports_pools_of_primary_and_replica() {
+        // In-memory SQLite databases are limited to a single connection, so
+        // use files to see the configured pool sizes
+        let directory = std::env::temp_dir().join(format!("nederlandskie-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let connect = |name: &str, max_connections| {
+            let url = format!("sqlite://{}", directory.join(name).display());
+            async move {
+                let pool = PoolConfig {
+                    max_connections,
+                    ..Default::default()
+                };
+                SqliteStorage::connect(&url, &pool).await.unwrap()
+            }
+        };
+
+        let database = Database::new(connect("primary.db", 3).await)
+            .with_replica(connect("replica.db", 2).await);
+
+        let statuses = database
+            .pool_statuses()
+            .into_iter()
+            .map(|(pool, status)| (pool, status.max_connections))
+            .collect::<Vec<_>>();
+
+        drop(database);
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(statuses, [("primary", 3), ("replica", 2)]);
+        assert!(Database::in_memory().pool_statuses().is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{TimeDelta, Utc};
+
+/// Which moment the age of a post is counted from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PostTimestamp {
+    /// When the author says the post was made
+    CreatedAt,
+    /// When the post got indexed
+    #[default]
+    IndexedAt,
+}
+
+impl PostTimestamp {
+    pub(super) fn column(&self) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::IndexedAt => "indexed_at",
+        }
+    }
+}
+
+impl FromStr for PostTimestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "created_at" => Ok(Self::CreatedAt),
+            "indexed_at" => Ok(Self::IndexedAt),
+            _ => Err(anyhow!(
+                "Unknown post timestamp {s:?}, expected created_at or indexed_at"
+            )),
+        }
+    }
+}
+
+/// Which posts the janitor deletes, and which ones it keeps regardless
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub max_age: TimeDelta,
+    pub age_of: PostTimestamp,
+    /// Only keep this many of the newest posts, however young the rest are
+    pub max_posts: Option<usize>,
+    /// Keep posts with at least this many likes, however old they are
+    pub keep_liked: Option<i32>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: TimeDelta::days(150),
+            age_of: PostTimestamp::IndexedAt,
+            max_posts: None,
+            keep_liked: None,
+        }
+    }
+}
+
+/// Parses comma-separated settings, e.g.
+/// `max_age_days=365,age_of=created_at,max_posts=100000,keep_liked=50`.
+/// Settings that are left out keep their defaults.
+impl FromStr for RetentionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = Self::default();
+
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .with_context(|| format!("Expected name=value, got {setting:?}"))?;
+
+            match name {
+                "max_age_days" => policy.max_age = parse_max_age_days(value)?,
+                "age_of" => policy.age_of = value.parse()?,
+                "max_posts" => match value.parse()? {
+                    0 => bail!("max_posts must be at least 1"),
+                    max_posts => policy.max_posts = Some(max_posts),
+                },
+                "keep_liked" => policy.keep_liked = Some(value.parse()?),
+                _ => bail!(
+                    "Unknown retention setting {name:?}, expected max_age_days, age_of, max_posts or keep_liked"
+                ),
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+/// Refuses ages that would have the janitor delete every post, or that are
+/// too far back to compute when posts have to be made by
+fn parse_max_age_days(value: &str) -> Result<TimeDelta> {
+    let days: i64 = value.parse()?;
+
+    if days < 1 {
+        bail!("max_age_days must be at least 1");
+    }
+
+    TimeDelta::try_days(days)
+        .filter(|max_age| Utc::now().checked_sub_signed(*max_age).is_some())
+        .with_context(|| format!("max_age_days is too large: {days}"))
+}
+
+/// Posts a retention policy applies to. Feeds pick posts by the country of
+/// their authors, so that's what policies are set up by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetentionScope<'a> {
+    /// Posts by authors from the country
+    Country(&'a str),
+    /// Posts by authors from none of these countries, including those not
+    /// known to live anywhere
+    OtherCountries(&'a [String]),
+}
+
+/// How the janitor goes about deleting posts
+#[derive(Clone, Debug)]
+pub struct RetentionConfig {
+    /// Policies for posts by authors from each country
+    pub by_country: BTreeMap<String, RetentionPolicy>,
+    /// Policy for posts by authors from all other countries
+    pub default: RetentionPolicy,
+    /// How many posts to delete in one statement, so that tables don't stay
+    /// locked for long
+    pub batch_size: usize,
+    /// Only report what would be deleted
+    pub dry_run: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            by_country: BTreeMap::new(),
+            default: RetentionPolicy::default(),
+            batch_size: 1000,
+            dry_run: false,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// Parses policies separated by semicolons, each prefixed with the country
+    /// it applies to, or `*` for all other countries, e.g.
+    /// `nl:max_age_days=365,keep_liked=50;*:max_age_days=30`
+    pub fn parse_policies(&mut self, s: &str) -> Result<()> {
+        for entry in s.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let (country, policy) = entry
+                .split_once(':')
+                .with_context(|| format!("Expected country:settings, got {entry:?}"))?;
+            let policy = policy
+                .parse()
+                .with_context(|| format!("Invalid retention policy for {country:?}"))?;
+
+            match country.trim() {
+                "*" => self.default = policy,
+                country => {
+                    self.by_country.insert(country.to_owned(), policy);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn countries(&self) -> Vec<String> {
+        self.by_country.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_policies_by_country() {
+        let mut config = RetentionConfig::default();
+        config
+            .parse_policies("nl:max_age_days=365,age_of=created_at,max_posts=1000,keep_liked=50; *:max_age_days=30")
+            .unwrap();
+
+        assert_eq!(
+            config.by_country["nl"],
+            RetentionPolicy {
+                max_age: TimeDelta::days(365),
+                age_of: PostTimestamp::CreatedAt,
+                max_posts: Some(1000),
+                keep_liked: Some(50),
+            }
+        );
+        assert_eq!(
+            config.default,
+            RetentionPolicy {
+                max_age: TimeDelta::days(30),
+                ..Default::default()
+            }
+        );
+        assert!(config.parse_policies("nl:max_posts=0").is_err());
+        assert!(config.parse_policies("nl:max_age=30d").is_err());
+    }
+
+    #[test]
+    fn refuses_max_ages_out_of_range() {
+        assert!("max_age_days=0".parse::<RetentionPolicy>().is_err());
+        assert!("max_age_days=-1".parse::<RetentionPolicy>().is_err());
+        assert!(
+            "max_age_days=1000000000000000"
+                .parse::<RetentionPolicy>()
+                .is_err()
+        );
+        assert!("max_age_days=100000000".parse::<RetentionPolicy>().is_err());
+        assert!("max_age_days=1".parse::<RetentionPolicy>().is_ok());
+    }
+}
@@ -0,0 +1,2486 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use scooby::postgres::{
+    Aliasable, Joinable, Orderable, Parameters, Select, delete_from, insert_into, select, update,
+};
+use sqlx::query;
+use sqlx::sqlite::{
+    SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqliteRow,
+};
+use sqlx::{Executor, Row, Sqlite};
+
+use super::{
+    Ban, BanKind, ClassificationOrder, ClassifiedProfile, ModerationLogEntry, NewPost, PoolConfig,
+    PoolStatus, Post, PostFilters, PostPreview, ProfileDetails, ReplyPolicy, Repost, Residency,
+    RetentionPolicy, RetentionScope, SchemaStatus, Storage, pool,
+};
+
+mod migrations;
+
+/// Storage on top of a single SQLite file, for deployments too small to be
+/// worth running Postgres for.
+///
+/// Queries are built the same way as for Postgres, since SQLite understands
+/// `$1` style parameters too, but anything relying on Postgres-specific
+/// functions or intervals is computed in Rust instead.
+pub struct SqliteStorage {
+    connection_pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub async fn connect(url: &str, config: &PoolConfig) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true)
+            .busy_timeout(config.acquire_timeout);
+
+        let mut pool_options = pool::pool_options(config);
+
+        // Every connection to an in-memory database gets a database of its own
+        if url.contains(":memory:") {
+            pool_options = pool_options.max_connections(1).idle_timeout(None);
+        }
+
+        Ok(Self {
+            connection_pool: pool_options.connect_with(options).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<SchemaStatus> {
+        self.run_migrations().await
+    }
+
+    async fn schema_status(&self) -> Result<SchemaStatus> {
+        self.check_schema().await
+    }
+
+    async fn baseline_schema(&self, _up_to: i64) -> Result<usize> {
+        Err(anyhow::anyhow!(
+            "SQLite databases are only ever set up through migrations, so there's nothing to baseline"
+        ))
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(pool::pool_status(&self.connection_pool))
+    }
+
+    async fn insert_post(&self, post: &NewPost<'_>) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [
+            author,
+            cid,
+            uri,
+            created_at,
+            text,
+            language,
+            parent,
+            root,
+            embed_kind,
+            quoted,
+        ] = params.next_array();
+
+        let author_country = select("likely_country_of_living")
+            .from("Profile")
+            .where_(format!("did = {author}"));
+
+        Ok(query(
+            &insert_into("Post")
+                .columns((
+                    "author_did",
+                    "cid",
+                    "uri",
+                    "created_at",
+                    "text",
+                    "language",
+                    "reply_parent_uri",
+                    "reply_root_uri",
+                    "embed_kind",
+                    "quoted_uri",
+                    "author_country",
+                ))
+                .values([[
+                    author,
+                    cid,
+                    uri,
+                    created_at,
+                    text,
+                    language,
+                    parent,
+                    root,
+                    embed_kind,
+                    quoted,
+                    format!("({author_country})"),
+                ]])
+                .on_conflict()
+                .do_nothing()
+                .to_string(),
+        )
+        .bind(post.author_did)
+        .bind(post.cid)
+        .bind(post.uri)
+        .bind(post.created_at)
+        .bind(post.text)
+        .bind(post.language)
+        .bind(post.reply_parent_uri)
+        .bind(post.reply_root_uri)
+        .bind(post.embed_kind.map(|k| k.as_str()))
+        .bind(post.quoted_uri)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_recent_post_texts(&self, author_did: &str, limit: usize) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("text")
+                .from(all_posts_by_author(&params.next()))
+                .where_("text IS NOT NULL")
+                .order_by("created_at".desc())
+                .limit(limit)
+                .to_string(),
+        )
+        .bind(author_did)
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_post_language_counts(&self, author_did: &str) -> Result<Vec<(String, i64)>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select(("language", "COUNT(*)"))
+                .from(all_posts_by_author(&params.next()))
+                .where_("language IS NOT NULL")
+                .group_by("language")
+                .order_by("COUNT(*)".desc())
+                .to_string(),
+        )
+        .bind(author_did)
+        .map(|r: SqliteRow| (r.get(0), r.get(1)))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        let mut params = Parameters::new();
+        let [country, now] = params.next_array();
+        let mut sql_builder = posts_by_authors_country_query(&country, &now, filters)
+            .order_by(("p.created_at".desc(), "p.cid".desc()))
+            .limit(limit);
+
+        if earlier_than.is_some() {
+            sql_builder = sql_builder.where_(format!(
+                "(p.created_at, p.cid) < ({}, {})",
+                params.next(),
+                params.next()
+            ));
+        }
+
+        let sql_string = sql_builder.to_string();
+
+        let mut query_object = query(&sql_string).bind(author_country).bind(Utc::now());
+
+        if let Some((last_created_at, last_cid)) = earlier_than {
+            query_object = query_object.bind(last_created_at).bind(last_cid);
+        }
+
+        query_object
+            .fetch_all(&self.connection_pool)
+            .await?
+            .into_iter()
+            .map(post_from_row)
+            .collect()
+    }
+
+    async fn fetch_top_posts_by_authors_country(
+        &self,
+        author_country: &str,
+        limit: usize,
+        offset: usize,
+        as_of: DateTime<Utc>,
+        max_age: TimeDelta,
+        filters: &PostFilters,
+    ) -> Result<Vec<Post>> {
+        let mut params = Parameters::new();
+        let [country, now, as_of_param, since] = params.next_array();
+
+        // SQLite may be built without math functions, so the ranking happens
+        // here. There are few enough recent posts in small deployments for
+        // that to not matter.
+        let rows = query(
+            &posts_by_authors_country_query(&country, &now, filters)
+                .where_(format!("p.created_at <= {as_of_param}"))
+                .where_(format!("p.created_at > {since}"))
+                .to_string(),
+        )
+        .bind(author_country)
+        .bind(Utc::now())
+        .bind(as_of)
+        .bind(as_of - max_age)
+        .fetch_all(&self.connection_pool)
+        .await?;
+
+        let mut scored = rows
+            .into_iter()
+            .map(|r| {
+                let like_count: i64 = r.get("like_count");
+                let post = post_from_row(r)?;
+                let hours = (as_of - post.created_at).num_seconds().max(0) as f64 / 3600.0;
+                Ok((like_count as f64 / (hours + 2.0).powf(1.8), post))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| (b.created_at, &b.cid).cmp(&(a.created_at, &a.cid)))
+        });
+
+        Ok(scored
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(_, post)| post)
+            .collect())
+    }
+
+    async fn delete_post(&self, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+        let uri_param = params.next();
+
+        let mut transaction = self.connection_pool.begin().await?;
+        let mut deleted = 0;
+
+        for table in ["PendingPost", "Post"] {
+            deleted += query(
+                &delete_from(table)
+                    .where_(format!("uri = {uri_param}"))
+                    .to_string(),
+            )
+            .bind(uri)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        }
+
+        transaction.commit().await?;
+
+        Ok(deleted > 0)
+    }
+
+    async fn count_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<u64> {
+        let mut params = Parameters::new();
+        let expired = posts_past_retention_query(scope, policy, &mut params);
+
+        let sql_string = select("COUNT(*)")
+            .from(format!("({expired})").as_("p"))
+            .to_string();
+        let mut query_object = query(&sql_string);
+
+        if let RetentionScope::Country(country) = scope {
+            query_object = query_object.bind(country);
+        }
+
+        Ok(query_object
+            .bind(now - policy.max_age)
+            .map(|r: SqliteRow| r.get::<i64, _>(0) as u64)
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn delete_posts_past_retention(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<u64> {
+        let mut params = Parameters::new();
+        let expired = posts_past_retention_query(scope, policy, &mut params)
+            .order_by(format!("p.{}", policy.age_of.column()))
+            .limit(limit);
+
+        let sql_string = delete_from("Post")
+            .where_(format!("uri IN ({expired})"))
+            .to_string();
+        let mut query_object = query(&sql_string);
+
+        if let RetentionScope::Country(country) = scope {
+            query_object = query_object.bind(country);
+        }
+
+        Ok(query_object
+            .bind(now - policy.max_age)
+            .execute(&self.connection_pool)
+            .await
+            .map(|result| result.rows_affected())?)
+    }
+
+    async fn insert_pending_post(
+        &self,
+        post: &NewPost<'_>,
+        self_labels: &[&str],
+        awaited_country: &str,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+        let values = params.next_array::<12>();
+
+        // Writes to SQLite are serialized, so the profile can't get classified
+        // between checking it and inserting the post
+        let unprocessed_author = format!(
+            "EXISTS ({})",
+            select("1")
+                .from("Profile")
+                .where_(format!("did = {}", values[0]))
+                .where_("has_been_processed = FALSE")
+        );
+
+        Ok(query(&format!(
+            "INSERT INTO PendingPost (author_did, cid, uri, created_at, text, language, reply_parent_uri, reply_root_uri, embed_kind, quoted_uri, self_labels, awaited_country) {} ON CONFLICT DO NOTHING",
+            select(values).where_(unprocessed_author)
+        ))
+        .bind(post.author_did)
+        .bind(post.cid)
+        .bind(post.uri)
+        .bind(post.created_at)
+        .bind(post.text)
+        .bind(post.language)
+        .bind(post.reply_parent_uri)
+        .bind(post.reply_root_uri)
+        .bind(post.embed_kind.map(|k| k.as_str()))
+        .bind(post.quoted_uri)
+        .bind(serde_json::to_string(self_labels)?)
+        .bind(awaited_country)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn delete_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("PendingPost")
+                .where_(format!("indexed_at < {}", params.next()))
+                .to_string(),
+        )
+        .bind(earlier_than)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected())?)
+    }
+
+    async fn count_old_pending_posts(&self, earlier_than: &DateTime<Utc>) -> Result<u64> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("COUNT(*)")
+                .from("PendingPost")
+                .where_(format!("indexed_at < {}", params.next()))
+                .to_string(),
+        )
+        .bind(earlier_than)
+        .map(|r: SqliteRow| r.get::<i64, _>(0) as u64)
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn insert_repost_if_relevant(
+        &self,
+        author_did: &str,
+        cid: &str,
+        uri: &str,
+        post_uri: &str,
+        created_at: DateTime<Utc>,
+        reposter_country: &str,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [
+            author,
+            cid_param,
+            uri_param,
+            post,
+            created_at_param,
+            country,
+        ] = params.next_array();
+
+        let resident = select("1")
+            .from("Profile")
+            .where_(format!("did = {author}"))
+            .where_(format!("likely_country_of_living = {country}"));
+
+        Ok(query(&format!(
+            "INSERT INTO Repost (author_did, cid, uri, post_uri, created_at) {} ON CONFLICT DO NOTHING",
+            select((author, cid_param, uri_param, post, created_at_param))
+                .where_(format!("EXISTS ({resident})"))
+        ))
+        .bind(author_did)
+        .bind(cid)
+        .bind(uri)
+        .bind(post_uri)
+        .bind(created_at)
+        .bind(reposter_country)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_reposts_by_reposters_country(
+        &self,
+        reposter_country: &str,
+        limit: usize,
+        earlier_than: Option<(DateTime<Utc>, &str)>,
+    ) -> Result<Vec<Repost>> {
+        let mut params = Parameters::new();
+        let mut sql_builder = select((
+            "r.created_at",
+            "r.author_did",
+            "r.cid",
+            "r.uri",
+            "r.post_uri",
+        ))
+        .from(
+            "Repost"
+                .as_("r")
+                .inner_join("Profile".as_("pr"))
+                .on("pr.did = r.author_did"),
+        )
+        .where_(format!("pr.likely_country_of_living = {}", params.next()))
+        .where_(is_not_banned("r.author_did", "r.uri"))
+        .where_(is_not_banned(&author_of("r.post_uri"), "r.post_uri"))
+        .order_by(("r.created_at".desc(), "r.cid".desc()))
+        .limit(limit);
+
+        if earlier_than.is_some() {
+            sql_builder = sql_builder.where_(format!(
+                "(r.created_at, r.cid) < ({}, {})",
+                params.next(),
+                params.next()
+            ));
+        }
+
+        let sql_string = sql_builder.to_string();
+
+        let mut query_object = query(&sql_string).bind(reposter_country);
+
+        if let Some((last_created_at, last_cid)) = earlier_than {
+            query_object = query_object.bind(last_created_at).bind(last_cid);
+        }
+
+        Ok(query_object
+            .map(|r: SqliteRow| Repost {
+                created_at: r.get("created_at"),
+                author_did: r.get("author_did"),
+                cid: r.get("cid"),
+                uri: r.get("uri"),
+                post_uri: r.get("post_uri"),
+            })
+            .fetch_all(&self.connection_pool)
+            .await?)
+    }
+
+    async fn delete_repost(&self, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("Repost")
+                .where_(format!("uri = {}", params.next()))
+                .to_string(),
+        )
+        .bind(uri)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn insert_label_if_relevant(
+        &self,
+        uri: &str,
+        src: &str,
+        value: &str,
+        created_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [
+            uri_param,
+            src_param,
+            value_param,
+            created_at_param,
+            expires_at_param,
+        ] = params.next_array();
+
+        let known_post = select("1")
+            .from("Post")
+            .where_(format!("uri = {uri_param}"));
+
+        Ok(query(&format!(
+            "INSERT INTO PostLabel (uri, src, value, created_at, expires_at) {} ON CONFLICT (uri, src, value) DO UPDATE SET created_at = excluded.created_at, expires_at = excluded.expires_at",
+            select((
+                uri_param,
+                src_param,
+                value_param,
+                created_at_param,
+                expires_at_param
+            ))
+            .where_(format!("EXISTS ({known_post})"))
+        ))
+        .bind(uri)
+        .bind(src)
+        .bind(value)
+        .bind(created_at)
+        .bind(expires_at)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn delete_label(&self, uri: &str, src: &str, value: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("PostLabel")
+                .where_(format!("uri = {}", params.next()))
+                .where_(format!("src = {}", params.next()))
+                .where_(format!("value = {}", params.next()))
+                .to_string(),
+        )
+        .bind(uri)
+        .bind(src)
+        .bind(value)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn insert_like_if_relevant(
+        &self,
+        author_did: &str,
+        post_uri: &str,
+        uri: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let inserted = {
+            let mut params = Parameters::new();
+            let [author, post, uri_param, created_at_param] = params.next_array();
+
+            let known_post = select("1").from("Post").where_(format!("uri = {post}"));
+
+            query(&format!(
+                "INSERT INTO PostLike (author_did, post_uri, uri, created_at) {} ON CONFLICT DO NOTHING",
+                select((author, post, uri_param, created_at_param))
+                    .where_(format!("EXISTS ({known_post})"))
+            ))
+            .bind(author_did)
+            .bind(post_uri)
+            .bind(uri)
+            .bind(created_at)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        if inserted {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Post")
+                    .set("like_count", "like_count + 1")
+                    .where_(format!("uri = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(post_uri)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(inserted)
+    }
+
+    async fn delete_like(&self, uri: &str) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let post_uri: Option<String> = {
+            let mut params = Parameters::new();
+
+            query(
+                &delete_from("PostLike")
+                    .where_(format!("uri = {}", params.next()))
+                    .returning("post_uri")
+                    .to_string(),
+            )
+            .bind(uri)
+            .map(|r: SqliteRow| r.get(0))
+            .fetch_optional(&mut *transaction)
+            .await?
+        };
+
+        if let Some(post_uri) = &post_uri {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Post")
+                    .set("like_count", "MAX(like_count - 1, 0)")
+                    .where_(format!("uri = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(post_uri)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(post_uri.is_some())
+    }
+
+    async fn fetch_post_author_dids(&self) -> Result<Vec<String>> {
+        Ok(
+            query(&select("author_did").distinct().from("Post").to_string())
+                .map(|r: SqliteRow| r.get(0))
+                .fetch_all(&self.connection_pool)
+                .await?,
+        )
+    }
+
+    async fn fetch_liker_dids(&self) -> Result<Vec<String>> {
+        Ok(
+            query(&select("author_did").distinct().from("PostLike").to_string())
+                .map(|r: SqliteRow| r.get(0))
+                .fetch_all(&self.connection_pool)
+                .await?,
+        )
+    }
+
+    async fn insert_follow_if_relevant(
+        &self,
+        author_did: &str,
+        subject_did: &str,
+        uri: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [author, subject, uri_param, created_at_param] = params.next_array();
+
+        let known_profile = select("1")
+            .from("Profile")
+            .where_(format!("did IN ({author}, {subject})"));
+
+        Ok(query(&format!(
+            "INSERT INTO Follow (author_did, subject_did, uri, created_at) {} ON CONFLICT DO NOTHING",
+            select((author, subject, uri_param, created_at_param))
+                .where_(format!("EXISTS ({known_profile})"))
+        ))
+        .bind(author_did)
+        .bind(subject_did)
+        .bind(uri)
+        .bind(created_at)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn delete_follow(&self, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &delete_from("Follow")
+                .where_(format!("uri = {}", params.next()))
+                .to_string(),
+        )
+        .bind(uri)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_followed_dids(&self, did: &str) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("subject_did")
+                .from("Follow")
+                .where_(format!("author_did = {}", params.next()))
+                .to_string(),
+        )
+        .bind(did)
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_classified_followers(&self, did: &str, country: &str) -> Result<(i64, i64)> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "COUNT(*)",
+                format!(
+                    "COUNT(*) FILTER (WHERE pr.likely_country_of_living = {})",
+                    params.next()
+                ),
+            ))
+            .from(
+                "Follow"
+                    .as_("f")
+                    .inner_join("Profile".as_("pr"))
+                    .on("pr.did = f.author_did"),
+            )
+            .where_(format!("f.subject_did = {}", params.next()))
+            .where_("pr.has_been_processed = TRUE")
+            .to_string(),
+        )
+        .bind(country)
+        .bind(did)
+        .map(|r: SqliteRow| (r.get(0), r.get(1)))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn insert_profile_if_it_doesnt_exist(&self, did: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &insert_into("Profile")
+                .columns(("did",))
+                .values([params.next()])
+                .on_conflict()
+                .do_nothing()
+                .to_string(),
+        )
+        .bind(did)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn claim_unprocessed_profile_dids(
+        &self,
+        limit: usize,
+        lease: TimeDelta,
+        order: ClassificationOrder,
+    ) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+        let [now, lease_ends_at] = params.next_array();
+
+        let mut claimable = unprocessed_profiles_query(select("did"), &now).limit(limit);
+
+        if order == ClassificationOrder::PostActivity {
+            claimable = claimable.order_by(
+                "(SELECT COUNT(*) FROM PendingPost WHERE PendingPost.author_did = Profile.did)"
+                    .desc(),
+            );
+        }
+
+        let claimable = claimable.order_by(("first_seen_at", "id"));
+
+        // Writes to SQLite are serialized, so there's no need to lock the
+        // claimed rows the way it's done for Postgres
+        let now_value = Utc::now();
+
+        Ok(query(
+            &update("Profile")
+                .set("classification_attempts", "classification_attempts + 1")
+                .set("next_classification_attempt_at", lease_ends_at)
+                .where_(format!("did IN ({claimable})"))
+                .returning("did")
+                .to_string(),
+        )
+        .bind(now_value)
+        .bind(now_value + lease)
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_unprocessed_profiles(&self) -> Result<i64> {
+        let mut params = Parameters::new();
+
+        Ok(
+            query(&unprocessed_profiles_query(select("COUNT(*)"), &params.next()).to_string())
+                .bind(Utc::now())
+                .map(|r: SqliteRow| r.get(0))
+                .fetch_one(&self.connection_pool)
+                .await?,
+        )
+    }
+
+    async fn record_profile_classification_failure(
+        &self,
+        did: &str,
+        error: &str,
+        max_attempts: i32,
+        backoff: TimeDelta,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let attempts: Option<i32> = {
+            let mut params = Parameters::new();
+
+            query(
+                &select("classification_attempts")
+                    .from("Profile")
+                    .where_(format!("did = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(did)
+            .map(|r: SqliteRow| r.get(0))
+            .fetch_optional(&mut *transaction)
+            .await?
+        };
+
+        let Some(attempts) = attempts else {
+            return Ok(false);
+        };
+
+        let has_failed = attempts >= max_attempts;
+        let multiplier = 2f64.powi((attempts - 1).min(16));
+        let delay =
+            TimeDelta::milliseconds((backoff.num_milliseconds() as f64 * multiplier) as i64);
+
+        {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Profile")
+                    .set("last_classification_error", params.next())
+                    .set("has_failed_classification", params.next())
+                    .set("next_classification_attempt_at", params.next())
+                    .where_(format!("did = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(error)
+            .bind(has_failed)
+            .bind(Utc::now() + delay)
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(has_failed)
+    }
+
+    async fn create_classification_batch(
+        &self,
+        batch_id: &str,
+        profiles: &[(String, String)],
+    ) -> Result<()> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        {
+            let mut params = Parameters::new();
+
+            query(
+                &insert_into("ClassificationBatch")
+                    .columns(("batch_id",))
+                    .values([params.next()])
+                    .to_string(),
+            )
+            .bind(batch_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        for (custom_id, did) in profiles {
+            let mut params = Parameters::new();
+
+            query(
+                &insert_into("ClassificationBatchProfile")
+                    .columns(("batch_id", "custom_id", "did"))
+                    .values([params.next_array()])
+                    .to_string(),
+            )
+            .bind(batch_id)
+            .bind(custom_id)
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn fetch_unfinished_classification_batches(
+        &self,
+    ) -> Result<Vec<(String, DateTime<Utc>)>> {
+        Ok(query(
+            &select(("batch_id", "submitted_at"))
+                .from("ClassificationBatch")
+                .where_("ended_at IS NULL")
+                .order_by("submitted_at")
+                .to_string(),
+        )
+        .map(|r: SqliteRow| (r.get(0), r.get(1)))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_classification_batch_profiles(
+        &self,
+        batch_id: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select(("custom_id", "did"))
+                .from("ClassificationBatchProfile")
+                .where_(format!("batch_id = {}", params.next()))
+                .to_string(),
+        )
+        .bind(batch_id)
+        .map(|r: SqliteRow| (r.get("custom_id"), r.get("did")))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn finish_classification_batch(&self, batch_id: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &update("ClassificationBatch")
+                .set("ended_at", params.next())
+                .where_(format!("batch_id = {}", params.next()))
+                .to_string(),
+        )
+        .bind(Utc::now())
+        .bind(batch_id)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn store_profile_details(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        residency_probability: Option<f64>,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let stored = {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Profile")
+                    .set("has_been_processed", "TRUE")
+                    .set("classified_at", params.next())
+                    .set("likely_country_of_living", params.next())
+                    .set("residency_probability", params.next())
+                    .set("next_classification_attempt_at", "NULL")
+                    .set("last_classification_error", "NULL")
+                    .where_(format!("did = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(Utc::now())
+            .bind(likely_country_of_living)
+            .bind(residency_probability)
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        update_author_country_of_posts(&mut *transaction, did, Some(likely_country_of_living))
+            .await?;
+        settle_pending_posts(&mut transaction, did, likely_country_of_living).await?;
+
+        transaction.commit().await?;
+
+        Ok(stored)
+    }
+
+    async fn force_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        force_country(&mut transaction, did, likely_country_of_living).await?;
+
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn override_profile_country(
+        &self,
+        did: &str,
+        likely_country_of_living: &str,
+        moderator: Option<&str>,
+    ) -> Result<()> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        force_country(&mut transaction, did, likely_country_of_living).await?;
+        log_moderation_action(
+            &mut *transaction,
+            "force_country",
+            did,
+            Some(likely_country_of_living),
+            moderator,
+        )
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn store_profile_description(
+        &self,
+        did: &str,
+        display_name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &update("Profile")
+                .set("display_name", params.next())
+                .set("description", params.next())
+                .where_(format!("did = {}", params.next()))
+                .to_string(),
+        )
+        .bind(display_name)
+        .bind(description)
+        .bind(did)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn fetch_recently_classified_profiles(
+        &self,
+        country: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ClassifiedProfile>> {
+        let mut params = Parameters::new();
+        let mut sql_builder = select((
+            "did",
+            "display_name",
+            "description",
+            "likely_country_of_living",
+            "residency_probability",
+            "classified_at",
+        ))
+        .from("Profile")
+        .where_("classified_at IS NOT NULL")
+        .order_by("classified_at".desc())
+        .limit(limit);
+
+        if country.is_some() {
+            sql_builder =
+                sql_builder.where_(format!("likely_country_of_living = {}", params.next()));
+        }
+
+        let sql_string = sql_builder.to_string();
+
+        let mut query_object = query(&sql_string);
+
+        if let Some(country) = country {
+            query_object = query_object.bind(country);
+        }
+
+        Ok(query_object
+            .map(|r: SqliteRow| ClassifiedProfile {
+                did: r.get("did"),
+                display_name: r.get("display_name"),
+                description: r.get("description"),
+                likely_country_of_living: r.get("likely_country_of_living"),
+                residency_probability: r.get("residency_probability"),
+                classified_at: r.get("classified_at"),
+            })
+            .fetch_all(&self.connection_pool)
+            .await?)
+    }
+
+    async fn fetch_recent_post_previews(
+        &self,
+        author_country: &str,
+        limit: usize,
+    ) -> Result<Vec<PostPreview>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "p.uri",
+                "p.author_did",
+                "pr.display_name",
+                "p.created_at",
+                "p.text",
+                "p.language",
+                "p.like_count",
+            ))
+            .from(
+                "Post"
+                    .as_("p")
+                    .inner_join("Profile".as_("pr"))
+                    .on("pr.did = p.author_did"),
+            )
+            .where_(format!("pr.likely_country_of_living = {}", params.next()))
+            .order_by(("p.created_at".desc(), "p.cid".desc()))
+            .limit(limit)
+            .to_string(),
+        )
+        .bind(author_country)
+        .map(|r: SqliteRow| PostPreview {
+            uri: r.get("uri"),
+            author_did: r.get("author_did"),
+            author_display_name: r.get("display_name"),
+            created_at: r.get("created_at"),
+            text: r.get("text"),
+            language: r.get("language"),
+            like_count: r.get("like_count"),
+        })
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_profile_details(&self, did: &str) -> Result<Option<ProfileDetails>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select([
+                "did",
+                "first_seen_at",
+                "has_been_processed",
+                "likely_country_of_living",
+                "residency_probability",
+                "classification_attempts",
+                "next_classification_attempt_at",
+                "has_failed_classification",
+                "last_classification_error",
+                "(SELECT COUNT(*) FROM Post WHERE Post.author_did = Profile.did) AS post_count",
+                "EXISTS (SELECT 1 FROM BannedProfile WHERE BannedProfile.did = Profile.did) AS is_banned",
+            ])
+            .from("Profile")
+            .where_(format!("did = {}", params.next()))
+            .to_string(),
+        )
+        .bind(did)
+        .map(|r: SqliteRow| ProfileDetails {
+            did: r.get("did"),
+            first_seen_at: r.get("first_seen_at"),
+            has_been_processed: r.get("has_been_processed"),
+            likely_country_of_living: r.get("likely_country_of_living"),
+            residency_probability: r.get("residency_probability"),
+            classification_attempts: r.get("classification_attempts"),
+            next_classification_attempt_at: r.get("next_classification_attempt_at"),
+            has_failed_classification: r.get("has_failed_classification"),
+            last_classification_error: r.get("last_classification_error"),
+            post_count: r.get("post_count"),
+            is_banned: r.get("is_banned"),
+        })
+        .fetch_optional(&self.connection_pool)
+        .await?)
+    }
+
+    async fn reset_profile_classification(&self, did: &str) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let reset = {
+            let mut params = Parameters::new();
+
+            query(
+                &update("Profile")
+                    .set("has_been_processed", "FALSE")
+                    .set("classified_at", "NULL")
+                    .set("likely_country_of_living", "NULL")
+                    .set("residency_probability", "NULL")
+                    .set("classification_attempts", "0")
+                    .set("next_classification_attempt_at", "NULL")
+                    .set("has_failed_classification", "FALSE")
+                    .set("last_classification_error", "NULL")
+                    .where_(format!("did = {}", params.next()))
+                    .to_string(),
+            )
+            .bind(did)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        update_author_country_of_posts(&mut *transaction, did, None).await?;
+
+        transaction.commit().await?;
+
+        Ok(reset)
+    }
+
+    async fn fetch_profile_residency(&self, did: &str, country: &str) -> Result<Option<Residency>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "has_been_processed",
+                "has_failed_classification",
+                "likely_country_of_living",
+            ))
+            .from("Profile")
+            .where_(format!("did = {}", params.next()))
+            .to_string(),
+        )
+        .bind(did)
+        .map(|r: SqliteRow| {
+            Residency::of(
+                r.get("has_been_processed"),
+                r.get("has_failed_classification"),
+                r.get("likely_country_of_living"),
+                country,
+            )
+        })
+        .fetch_optional(&self.connection_pool)
+        .await?
+        .flatten())
+    }
+
+    async fn count_posts(&self) -> Result<i64> {
+        Ok(query(&select("COUNT(*)").from("Post").to_string())
+            .map(|r: SqliteRow| r.get(0))
+            .fetch_one(&self.connection_pool)
+            .await?)
+    }
+
+    async fn count_classified_profiles_among(
+        &self,
+        dids: &[String],
+        country: &str,
+    ) -> Result<(i64, i64)> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select((
+                "COUNT(*)",
+                format!(
+                    "COUNT(*) FILTER (WHERE likely_country_of_living = {})",
+                    params.next()
+                ),
+            ))
+            .from("Profile")
+            .where_(format!(
+                "did IN (SELECT value FROM json_each({}))",
+                params.next()
+            ))
+            .where_("has_been_processed = TRUE")
+            .to_string(),
+        )
+        .bind(country)
+        .bind(serde_json::to_string(dids)?)
+        .map(|r: SqliteRow| (r.get(0), r.get(1)))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_failed_profiles(&self) -> Result<i64> {
+        Ok(query(
+            &select("COUNT(*)")
+                .from("Profile")
+                .where_("has_failed_classification = TRUE")
+                .to_string(),
+        )
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn count_profiles_in_country(&self, country: &str) -> Result<i64> {
+        let mut params = Parameters::new();
+        Ok(query(
+            &select("COUNT(*)")
+                .from("Profile")
+                .where_(format!("likely_country_of_living = {}", params.next()))
+                .to_string(),
+        )
+        .bind(country)
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_profile_dids_in_country(&self, country: &str) -> Result<Vec<String>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("did")
+                .from("Profile")
+                .where_(format!("likely_country_of_living = {}", params.next()))
+                .where_("has_been_processed = TRUE")
+                .order_by("did")
+                .to_string(),
+        )
+        .bind(country)
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn ban(
+        &self,
+        kind: BanKind,
+        subject: &str,
+        reason: &str,
+        moderator: Option<&str>,
+    ) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let banned = {
+            let mut params = Parameters::new();
+
+            query(
+                &insert_into(kind.table())
+                    .columns((kind.subject_column(), "reason"))
+                    .values([params.next_array()])
+                    .on_conflict()
+                    .do_nothing()
+                    .to_string(),
+            )
+            .bind(subject)
+            .bind(reason)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        if banned {
+            log_moderation_action(
+                &mut *transaction,
+                kind.action(true),
+                subject,
+                Some(reason),
+                moderator,
+            )
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(banned)
+    }
+
+    async fn unban(&self, kind: BanKind, subject: &str, moderator: Option<&str>) -> Result<bool> {
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let unbanned = {
+            let mut params = Parameters::new();
+
+            query(
+                &delete_from(kind.table())
+                    .where_(format!("{} = {}", kind.subject_column(), params.next()))
+                    .to_string(),
+            )
+            .bind(subject)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+                > 0
+        };
+
+        if unbanned {
+            log_moderation_action(
+                &mut *transaction,
+                kind.action(false),
+                subject,
+                None,
+                moderator,
+            )
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(unbanned)
+    }
+
+    async fn fetch_bans(&self, kind: BanKind) -> Result<Vec<Ban>> {
+        Ok(query(
+            &select((kind.subject_column().as_("subject"), "reason", "banned_at"))
+                .from(kind.table())
+                .order_by(("banned_at".desc(), "id".desc()))
+                .to_string(),
+        )
+        .map(|r: SqliteRow| Ban {
+            kind,
+            subject: r.get("subject"),
+            reason: r.get("reason"),
+            banned_at: r.get("banned_at"),
+        })
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn is_post_banned(&self, author_did: &str, uri: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+        let [author, uri_param] = params.next_array();
+
+        Ok(query(&format!(
+            "SELECT EXISTS ({}) OR EXISTS ({})",
+            select("1")
+                .from("BannedProfile")
+                .where_(format!("did = {author}")),
+            select("1")
+                .from("BannedPost")
+                .where_(format!("uri = {uri_param}")),
+        ))
+        .bind(author_did)
+        .bind(uri)
+        .map(|r: SqliteRow| r.get(0))
+        .fetch_one(&self.connection_pool)
+        .await?)
+    }
+
+    async fn record_moderation_action(
+        &self,
+        action: &str,
+        subject: &str,
+        reason: Option<&str>,
+        moderator: Option<&str>,
+    ) -> Result<()> {
+        log_moderation_action(&self.connection_pool, action, subject, reason, moderator).await
+    }
+
+    async fn fetch_moderation_log(&self, limit: usize) -> Result<Vec<ModerationLogEntry>> {
+        Ok(query(
+            &select(("performed_at", "action", "subject", "reason", "moderator"))
+                .from("ModerationLog")
+                .order_by(("performed_at".desc(), "id".desc()))
+                .limit(limit)
+                .to_string(),
+        )
+        .map(|r: SqliteRow| ModerationLogEntry {
+            performed_at: r.get("performed_at"),
+            action: r.get("action"),
+            subject: r.get("subject"),
+            reason: r.get("reason"),
+            moderator: r.get("moderator"),
+        })
+        .fetch_all(&self.connection_pool)
+        .await?)
+    }
+
+    async fn fetch_subscription_cursor(&self, host: &str, did: &str) -> Result<Option<i64>> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &select("cursor")
+                .from("SubscriptionState")
+                .where_(format!("service = {}", params.next()))
+                .where_(format!("host = {}", params.next()))
+                .to_string(),
+        )
+        .bind(did)
+        .bind(host)
+        .map(|r: SqliteRow| r.get("cursor"))
+        .fetch_optional(&self.connection_pool)
+        .await?)
+    }
+
+    async fn create_subscription_state(&self, host: &str, did: &str) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &insert_into("SubscriptionState")
+                .columns(("service", "cursor", "host"))
+                .values([params.next_array()])
+                .to_string(),
+        )
+        .bind(did)
+        .bind(0)
+        .bind(host)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+
+    async fn update_subscription_cursor(&self, host: &str, did: &str, cursor: i64) -> Result<bool> {
+        let mut params = Parameters::new();
+
+        Ok(query(
+            &update("SubscriptionState")
+                .set("cursor", params.next())
+                .where_(format!("service = {}", params.next()))
+                .where_(format!("host = {}", params.next()))
+                .to_string(),
+        )
+        .bind(cursor)
+        .bind(did)
+        .bind(host)
+        .execute(&self.connection_pool)
+        .await
+        .map(|result| result.rows_affected() > 0)?)
+    }
+}
+
+/// Selects (as `ap`) texts and languages of posts by the author, including
+/// the ones held back until the author is classified
+fn all_posts_by_author(author: &str) -> String {
+    let posts_in = |table: &str| {
+        select(("text", "language", "created_at"))
+            .from(table)
+            .where_(format!("author_did = {author}"))
+    };
+
+    format!(
+        "({} UNION ALL {}) AS ap",
+        posts_in("Post"),
+        posts_in("PendingPost")
+    )
+}
+
+fn unprocessed_profiles_query(statement: Select, now: &str) -> Select {
+    statement
+        .from("Profile")
+        .where_("has_been_processed = FALSE")
+        .where_("has_failed_classification = FALSE")
+        .where_(format!(
+            "(next_classification_attempt_at IS NULL OR next_classification_attempt_at <= {now})"
+        ))
+        .where_(format!(
+            "did NOT IN ({})",
+            select("bp.did")
+                .from(
+                    "ClassificationBatchProfile"
+                        .as_("bp")
+                        .inner_join("ClassificationBatch".as_("b"))
+                        .on("b.batch_id = bp.batch_id")
+                )
+                .where_("b.ended_at IS NULL")
+        ))
+}
+
+/// Selects posts (as `p`) by authors from the given country that pass the
+/// filters, in the shape expected by `post_from_row`. Labels are
+/// considered active if they don't expire before `now`.
+fn posts_by_authors_country_query(country: &str, now: &str, filters: &PostFilters) -> Select {
+    let label_is_active = format!("(l.expires_at IS NULL OR l.expires_at > {now})");
+
+    let labels = if filters.mark_labeled {
+        format!(
+            "({})",
+            select("json_group_array(DISTINCT l.value)")
+                .from("PostLabel".as_("l"))
+                .where_("l.uri = p.uri")
+                .where_(label_is_active.as_str())
+        )
+    } else {
+        "'[]'".to_owned()
+    };
+
+    let mut statement = select((
+        "p.created_at",
+        "p.author_did",
+        "p.cid",
+        "p.uri",
+        "p.like_count",
+        labels.as_("labels"),
+    ))
+    .from("Post".as_("p"))
+    .where_(format!("p.author_country = {country}"))
+    .where_(is_not_banned("p.author_did", "p.uri"));
+
+    statement = match filters.replies {
+        ReplyPolicy::Show => statement,
+        ReplyPolicy::Hide => statement.where_("p.reply_parent_uri IS NULL"),
+        ReplyPolicy::OnlyToFeedAuthors => statement.where_(format!(
+            "(p.reply_parent_uri IS NULL OR {})",
+            is_by_author_from("p.reply_parent_uri", country)
+        )),
+        ReplyPolicy::CollapseThreads => statement.where_(format!(
+            "NOT EXISTS ({})",
+            select("1")
+                .from("Post".as_("tp"))
+                .where_("COALESCE(tp.reply_root_uri, tp.uri) = COALESCE(p.reply_root_uri, p.uri)")
+                .where_(format!("tp.author_country = {country}"))
+                .where_("(tp.created_at, tp.cid) > (p.created_at, p.cid)")
+        )),
+    };
+
+    if !filters.embed_kinds.is_empty() {
+        let kinds = filters
+            .embed_kinds
+            .iter()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>();
+
+        statement = statement.where_(format!("p.embed_kind IN ({})", string_literals(&kinds)));
+    }
+
+    if !filters.hidden_labels.is_empty() {
+        statement = statement.where_(format!(
+            "NOT EXISTS ({})",
+            select("1")
+                .from("PostLabel".as_("l"))
+                .where_("l.uri = p.uri")
+                .where_(label_is_active.as_str())
+                .where_(format!(
+                    "l.value IN ({})",
+                    string_literals(&filters.hidden_labels)
+                ))
+        ));
+    }
+
+    if filters.hide_quotes_from_outside {
+        statement = statement.where_(format!(
+            "(p.quoted_uri IS NULL OR {})",
+            is_by_author_from("p.quoted_uri", country)
+        ));
+    }
+
+    statement
+}
+
+/// Selects URIs of posts (as `p`) in the scope that the policy says should be
+/// deleted. Binds the country of the scope if there is one, then the moment
+/// posts older than are deleted.
+fn posts_past_retention_query(
+    scope: RetentionScope<'_>,
+    policy: &RetentionPolicy,
+    params: &mut Parameters,
+) -> Select {
+    let country = match scope {
+        RetentionScope::Country(_) => Some(params.next()),
+        RetentionScope::OtherCountries(_) => None,
+    };
+    let in_scope = |alias: &str| match (scope, &country) {
+        (RetentionScope::Country(_), Some(country)) => {
+            format!("{alias}.author_country = {country}")
+        }
+        (RetentionScope::OtherCountries(countries), _) if !countries.is_empty() => format!(
+            "({alias}.author_country IS NULL OR {alias}.author_country NOT IN ({}))",
+            string_literals(countries)
+        ),
+        _ => "TRUE".to_owned(),
+    };
+    let timestamp = policy.age_of.column();
+
+    let mut expired = format!("p.{timestamp} < {}", params.next());
+
+    if let Some(max_posts) = policy.max_posts {
+        // Posts as old as the last one to keep are kept too, so that ties
+        // don't get decided at random
+        expired = format!(
+            "({expired} OR p.{timestamp} < ({}))",
+            select(format!("np.{timestamp}"))
+                .from("Post".as_("np"))
+                .where_(in_scope("np"))
+                .order_by(format!("np.{timestamp}").desc())
+                .offset(max_posts - 1)
+                .limit(1)
+        );
+    }
+
+    let mut statement = select("p.uri")
+        .from("Post".as_("p"))
+        .where_(in_scope("p"))
+        .where_(expired);
+
+    if let Some(keep_liked) = policy.keep_liked {
+        statement = statement.where_(format!("p.like_count < {keep_liked}"));
+    }
+
+    statement
+}
+
+/// Condition checking that neither the profile nor the record have been banned
+fn is_not_banned(did_column: &str, uri_column: &str) -> String {
+    format!(
+        "NOT EXISTS ({}) AND NOT EXISTS ({})",
+        select("1")
+            .from("BannedProfile")
+            .where_(format!("BannedProfile.did = {did_column}")),
+        select("1")
+            .from("BannedPost")
+            .where_(format!("BannedPost.uri = {uri_column}")),
+    )
+}
+
+/// Indexes the pending posts of a freshly classified profile that were
+/// waiting for the country it was classified as living in, and drops the rest
+async fn settle_pending_posts(
+    connection: &mut SqliteConnection,
+    did: &str,
+    country: &str,
+) -> Result<()> {
+    let mut params = Parameters::new();
+    let [did_param, country_param] = params.next_array();
+
+    let awaiting = |columns: &[&str]| {
+        select(columns.join(", "))
+            .from("PendingPost".as_("pp"))
+            .where_(format!("pp.author_did = {did_param}"))
+            .where_(format!("pp.awaited_country = {country_param}"))
+    };
+
+    query(&format!(
+        "INSERT INTO Post (author_did, cid, uri, created_at, text, language, reply_parent_uri, reply_root_uri, embed_kind, quoted_uri, author_country) {} ON CONFLICT DO NOTHING",
+        awaiting(&[
+            "pp.author_did",
+            "pp.cid",
+            "pp.uri",
+            "pp.created_at",
+            "pp.text",
+            "pp.language",
+            "pp.reply_parent_uri",
+            "pp.reply_root_uri",
+            "pp.embed_kind",
+            "pp.quoted_uri",
+            "pp.awaited_country",
+        ])
+    ))
+    .bind(did)
+    .bind(country)
+    .execute(&mut *connection)
+    .await?;
+
+    query(&format!(
+        "INSERT INTO PostLabel (uri, src, value, created_at) {} ON CONFLICT DO NOTHING",
+        awaiting(&["pp.uri", "pp.author_did", "l.value", "pp.created_at"])
+            .from("json_each(pp.self_labels)".as_("l"))
+    ))
+    .bind(did)
+    .bind(country)
+    .execute(&mut *connection)
+    .await?;
+
+    let mut params = Parameters::new();
+
+    query(
+        &delete_from("PendingPost")
+            .where_(format!("author_did = {}", params.next()))
+            .to_string(),
+    )
+    .bind(did)
+    .execute(&mut *connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Keeps the country copied onto the posts of a profile in line with the
+/// profile itself, so that feeds can select posts without joining profiles
+async fn update_author_country_of_posts<'e>(
+    executor: impl Executor<'e, Database = Sqlite>,
+    did: &str,
+    country: Option<&str>,
+) -> Result<()> {
+    let mut params = Parameters::new();
+    let [country_param, did_param] = params.next_array();
+
+    query(
+        &update("Post")
+            .set("author_country", country_param.as_str())
+            .where_(format!("author_did = {did_param}"))
+            .where_(format!("author_country IS NOT {country_param}"))
+            .to_string(),
+    )
+    .bind(country)
+    .bind(did)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Sets the country of a profile by hand, creating the profile if needed
+async fn force_country(
+    connection: &mut SqliteConnection,
+    did: &str,
+    likely_country_of_living: &str,
+) -> Result<()> {
+    {
+        let mut params = Parameters::new();
+
+        query(
+            &insert_into("Profile")
+                .columns(("did",))
+                .values([params.next()])
+                .on_conflict()
+                .do_nothing()
+                .to_string(),
+        )
+        .bind(did)
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    {
+        let mut params = Parameters::new();
+        query(
+            &update("Profile")
+                .set("has_been_processed", "TRUE")
+                .set("classified_at", params.next())
+                .set("has_failed_classification", "FALSE")
+                .set("likely_country_of_living", params.next())
+                .where_(format!("did = {}", params.next()))
+                .to_string(),
+        )
+        .bind(Utc::now())
+        .bind(likely_country_of_living)
+        .bind(did)
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    update_author_country_of_posts(&mut *connection, did, Some(likely_country_of_living)).await?;
+    settle_pending_posts(connection, did, likely_country_of_living).await?;
+
+    Ok(())
+}
+
+async fn log_moderation_action<'e>(
+    executor: impl Executor<'e, Database = Sqlite>,
+    action: &str,
+    subject: &str,
+    reason: Option<&str>,
+    moderator: Option<&str>,
+) -> Result<()> {
+    let mut params = Parameters::new();
+
+    query(
+        &insert_into("ModerationLog")
+            .columns(("action", "subject", "reason", "moderator"))
+            .values([params.next_array()])
+            .to_string(),
+    )
+    .bind(action)
+    .bind(subject)
+    .bind(reason)
+    .bind(moderator)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+fn post_from_row(r: SqliteRow) -> Result<Post> {
+    let mut labels: Vec<String> = serde_json::from_str(r.get("labels"))?;
+    labels.sort();
+
+    Ok(Post {
+        created_at: r.get("created_at"),
+        author_did: r.get("author_did"),
+        cid: r.get("cid"),
+        uri: r.get("uri"),
+        labels,
+    })
+}
+
+/// Formats values defined in code as a list of SQL string literals
+fn string_literals(values: &[impl AsRef<str>]) -> String {
+    values
+        .iter()
+        .map(|v| format!("'{}'", v.as_ref().replace('\'', "''")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Expression extracting the DID of whoever made the record behind the
+/// `at://` URI in the given column
+fn author_of(uri_column: &str) -> String {
+    format!("SUBSTR({uri_column}, 6, INSTR(SUBSTR({uri_column}, 6), '/') - 1)")
+}
+
+/// Condition checking that the record behind the URI in the given column was
+/// made by someone from the given country
+fn is_by_author_from(uri_column: &str, country: &str) -> String {
+    format!(
+        "EXISTS ({})",
+        select("1")
+            .from("Profile".as_("apr"))
+            .where_(format!("apr.did = {}", author_of(uri_column)))
+            .where_(format!("apr.likely_country_of_living = {country}"))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::database::tests::{
+        moves_posts_between_feeds, new_post, overrides_profile_country,
+        retries_classification_with_backoff, stores_and_counts_follows,
+    };
+    use crate::services::database::{Database, PostTimestamp};
+
+    async fn database() -> Database {
+        let database = Database::new(
+            SqliteStorage::connect("sqlite::memory:", &PoolConfig::default())
+                .await
+                .unwrap(),
+        );
+        database.migrate().await.unwrap();
+        database
+    }
+
+    #[tokio::test]
+    async fn serves_feeds_with_keyset_pagination_and_ranking() {
+        let database = database().await;
+
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+        database
+            .force_profile_country("did:de", "de")
+            .await
+            .unwrap();
+
+        for (uri, minutes_ago) in [
+            ("at://did:nl/app.bsky.feed.post/c", 1),
+            ("at://did:nl/app.bsky.feed.post/b", 2),
+            ("at://did:nl/app.bsky.feed.post/a", 3),
+            ("at://did:de/app.bsky.feed.post/d", 0),
+        ] {
+            let post = new_post(&uri[5..11], uri, minutes_ago);
+            database.insert_post(&post).await.unwrap();
+        }
+
+        database
+            .insert_like_if_relevant(
+                "did:de",
+                "at://did:nl/app.bsky.feed.post/a",
+                "at://did:de/app.bsky.feed.like/1",
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+        database
+            .insert_label_if_relevant(
+                "at://did:nl/app.bsky.feed.post/b",
+                "did:labeler",
+                "nudity",
+                Utc::now(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let filters = PostFilters {
+            mark_labeled: true,
+            ..Default::default()
+        };
+
+        let first_page = database
+            .fetch_posts_by_authors_country("nl", 2, None, &filters)
+            .await
+            .unwrap();
+        assert_eq!(
+            first_page
+                .iter()
+                .map(|p| p.cid.as_str())
+                .collect::<Vec<_>>(),
+            ["c", "b"]
+        );
+        assert_eq!(first_page[1].labels, ["nudity"]);
+
+        let last = first_page.last().unwrap();
+        let second_page = database
+            .fetch_posts_by_authors_country("nl", 2, Some((last.created_at, &last.cid)), &filters)
+            .await
+            .unwrap();
+        assert_eq!(
+            second_page
+                .iter()
+                .map(|p| p.cid.as_str())
+                .collect::<Vec<_>>(),
+            ["a"]
+        );
+
+        let top = database
+            .fetch_top_posts_by_authors_country(
+                "nl",
+                10,
+                0,
+                Utc::now(),
+                TimeDelta::days(1),
+                &PostFilters::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(top[0].cid, "a");
+
+        let strict_filters = PostFilters {
+            replies: ReplyPolicy::CollapseThreads,
+            hide_quotes_from_outside: true,
+            hidden_labels: vec!["nudity".to_owned()],
+            ..Default::default()
+        };
+        let filtered = database
+            .fetch_posts_by_authors_country("nl", 10, None, &strict_filters)
+            .await
+            .unwrap();
+        assert_eq!(
+            filtered.iter().map(|p| p.cid.as_str()).collect::<Vec<_>>(),
+            ["c", "a"]
+        );
+
+        // Only reposts by people living in the country are stored
+        for (reposter, stored) in [("did:nl", true), ("did:de", false)] {
+            let inserted = database
+                .insert_repost_if_relevant(
+                    reposter,
+                    &reposter[4..],
+                    &format!("at://{reposter}/app.bsky.feed.repost/r"),
+                    "at://did:de/app.bsky.feed.post/d",
+                    Utc::now(),
+                    "nl",
+                )
+                .await
+                .unwrap();
+            assert_eq!(inserted, stored);
+        }
+        let reposts = database
+            .fetch_reposts_by_reposters_country("nl", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(reposts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn moves_posts_between_feeds_when_authors_are_reclassified() {
+        moves_posts_between_feeds(&database().await).await;
+    }
+
+    #[tokio::test]
+    async fn stores_deletes_and_counts_follows() {
+        stores_and_counts_follows(&database().await).await;
+    }
+
+    #[tokio::test]
+    async fn overrides_profile_country_with_a_trace() {
+        overrides_profile_country(&database().await).await;
+    }
+
+    #[tokio::test]
+    async fn settles_pending_posts_once_authors_are_classified() {
+        let database = database().await;
+
+        for did in ["did:nl", "did:de"] {
+            database
+                .insert_profile_if_it_doesnt_exist(did)
+                .await
+                .unwrap();
+            assert_eq!(
+                database.fetch_profile_residency(did, "nl").await.unwrap(),
+                Some(Residency::Unclassified)
+            );
+        }
+
+        for uri in [
+            "at://did:nl/app.bsky.feed.post/a",
+            "at://did:nl/app.bsky.feed.post/b",
+            "at://did:de/app.bsky.feed.post/c",
+        ] {
+            let post = new_post(&uri[5..11], uri, 1);
+            assert!(
+                database
+                    .insert_pending_post(&post, &["nudity"], "nl")
+                    .await
+                    .unwrap()
+            );
+        }
+
+        assert!(
+            database
+                .delete_post("at://did:nl/app.bsky.feed.post/b")
+                .await
+                .unwrap()
+        );
+
+        database
+            .store_profile_details("did:nl", "nl", None)
+            .await
+            .unwrap();
+        database
+            .store_profile_details("did:de", "de", None)
+            .await
+            .unwrap();
+
+        let filters = PostFilters {
+            mark_labeled: true,
+            ..Default::default()
+        };
+        let posts = database
+            .fetch_posts_by_authors_country("nl", 10, None, &filters)
+            .await
+            .unwrap();
+        assert_eq!(
+            posts.iter().map(|p| p.cid.as_str()).collect::<Vec<_>>(),
+            ["a"]
+        );
+        assert_eq!(posts[0].labels, ["nudity"]);
+
+        assert_eq!(database.count_posts().await.unwrap(), 1);
+
+        // Posts by authors classified in the meantime aren't held anymore
+        assert!(
+            !database
+                .insert_pending_post(
+                    &new_post("did:nl", "at://did:nl/app.bsky.feed.post/d", 1),
+                    &[],
+                    "nl"
+                )
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            database
+                .delete_old_pending_posts(&(Utc::now() + TimeDelta::days(1)))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn samples_held_posts_of_unclassified_authors() {
+        let database = database().await;
+
+        database
+            .insert_profile_if_it_doesnt_exist("did:new")
+            .await
+            .unwrap();
+        database
+            .insert_post(&new_post("did:new", "at://did:new/app.bsky.feed.post/a", 3))
+            .await
+            .unwrap();
+
+        for (uri, minutes_ago) in [
+            ("at://did:new/app.bsky.feed.post/b", 2),
+            ("at://did:new/app.bsky.feed.post/c", 1),
+        ] {
+            let post = NewPost {
+                text: "hallo",
+                language: Some("nl"),
+                ..new_post("did:new", uri, minutes_ago)
+            };
+            database
+                .insert_pending_post(&post, &[], "nl")
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            database
+                .fetch_post_language_counts("did:new")
+                .await
+                .unwrap(),
+            [("nl".to_owned(), 2), ("ru".to_owned(), 1)]
+        );
+        assert_eq!(
+            database
+                .fetch_recent_post_texts("did:new", 2)
+                .await
+                .unwrap(),
+            ["hallo", "hallo"]
+        );
+    }
+
+    #[tokio::test]
+    async fn deletes_posts_past_retention_in_batches() {
+        let database = database().await;
+
+        for (did, country) in [
+            ("did:nl", Some("nl")),
+            ("did:de", Some("de")),
+            ("did:xx", None),
+        ] {
+            database
+                .insert_profile_if_it_doesnt_exist(did)
+                .await
+                .unwrap();
+
+            if let Some(country) = country {
+                database
+                    .store_profile_details(did, country, None)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        for (uri, minutes_ago) in [
+            ("at://did:nl/app.bsky.feed.post/a", 10),
+            ("at://did:nl/app.bsky.feed.post/b", 20),
+            ("at://did:nl/app.bsky.feed.post/c", 30),
+            ("at://did:de/app.bsky.feed.post/d", 40),
+            ("at://did:xx/app.bsky.feed.post/e", 50),
+        ] {
+            let post = new_post(&uri[5..11], uri, minutes_ago);
+            database.insert_post(&post).await.unwrap();
+        }
+
+        database
+            .insert_like_if_relevant(
+                "did:de",
+                "at://did:nl/app.bsky.feed.post/c",
+                "at://did:de/app.bsky.feed.like/1",
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        let nl = RetentionPolicy {
+            max_age: TimeDelta::days(1),
+            age_of: PostTimestamp::CreatedAt,
+            max_posts: Some(1),
+            keep_liked: Some(1),
+        };
+        let elsewhere = RetentionPolicy {
+            max_age: TimeDelta::minutes(35),
+            age_of: PostTimestamp::CreatedAt,
+            ..Default::default()
+        };
+        let countries = ["nl".to_owned()];
+
+        assert_eq!(
+            database
+                .count_posts_past_retention(RetentionScope::Country("nl"), &nl, now)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            database
+                .count_posts_past_retention(
+                    RetentionScope::OtherCountries(&countries),
+                    &elsewhere,
+                    now
+                )
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(database.count_posts().await.unwrap(), 5);
+
+        for _ in 0..2 {
+            assert_eq!(
+                database
+                    .delete_posts_past_retention(
+                        RetentionScope::OtherCountries(&countries),
+                        &elsewhere,
+                        now,
+                        1
+                    )
+                    .await
+                    .unwrap(),
+                1
+            );
+        }
+        assert_eq!(
+            database
+                .delete_posts_past_retention(RetentionScope::Country("nl"), &nl, now, 10)
+                .await
+                .unwrap(),
+            1
+        );
+
+        let posts = database
+            .fetch_posts_by_authors_country("nl", 10, None, &PostFilters::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            posts.iter().map(|p| p.cid.as_str()).collect::<Vec<_>>(),
+            ["a", "c"]
+        );
+        assert_eq!(database.count_posts().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn releases_profiles_of_finished_classification_batches() {
+        let database = database().await;
+
+        database
+            .insert_profile_if_it_doesnt_exist("did:batched")
+            .await
+            .unwrap();
+        database
+            .create_classification_batch(
+                "batch-1",
+                &[("profile-0".to_owned(), "did:batched".to_owned())],
+            )
+            .await
+            .unwrap();
+
+        let claim = || {
+            database.claim_unprocessed_profile_dids(
+                10,
+                TimeDelta::minutes(5),
+                ClassificationOrder::FirstSeen,
+            )
+        };
+
+        assert!(claim().await.unwrap().is_empty());
+
+        let batches = database
+            .fetch_unfinished_classification_batches()
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].0, "batch-1");
+        assert!(Utc::now() - batches[0].1 < TimeDelta::minutes(1));
+
+        assert!(
+            database
+                .finish_classification_batch("batch-1")
+                .await
+                .unwrap()
+        );
+        assert!(
+            database
+                .fetch_unfinished_classification_batches()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(claim().await.unwrap(), ["did:batched"]);
+    }
+
+    #[tokio::test]
+    async fn claims_profiles_in_either_order() {
+        for (order, expected) in [
+            (ClassificationOrder::FirstSeen, "did:old"),
+            (ClassificationOrder::PostActivity, "did:new"),
+        ] {
+            let database = database().await;
+
+            for did in ["did:old", "did:new"] {
+                database
+                    .insert_profile_if_it_doesnt_exist(did)
+                    .await
+                    .unwrap();
+            }
+
+            // Only posts held back until their authors are classified count
+            database
+                .insert_post(&new_post("did:old", "at://did:old/app.bsky.feed.post/a", 1))
+                .await
+                .unwrap();
+            database
+                .insert_pending_post(
+                    &new_post("did:new", "at://did:new/app.bsky.feed.post/b", 1),
+                    &[],
+                    "nl",
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                database
+                    .claim_unprocessed_profile_dids(1, TimeDelta::minutes(5), order)
+                    .await
+                    .unwrap(),
+                [expected],
+                "{order:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_classification_with_backoff_until_attempts_run_out() {
+        retries_classification_with_backoff(&database().await).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_track_of_classification_and_cursors() {
+        let database = database().await;
+
+        database
+            .insert_profile_if_it_doesnt_exist("did:new")
+            .await
+            .unwrap();
+        assert_eq!(database.count_unprocessed_profiles().await.unwrap(), 1);
+
+        let claimed = database
+            .claim_unprocessed_profile_dids(
+                10,
+                TimeDelta::minutes(5),
+                ClassificationOrder::FirstSeen,
+            )
+            .await
+            .unwrap();
+        assert_eq!(claimed, ["did:new"]);
+        assert_eq!(database.count_unprocessed_profiles().await.unwrap(), 0);
+
+        assert!(
+            !database
+                .record_profile_classification_failure("did:new", "oops", 2, TimeDelta::seconds(1))
+                .await
+                .unwrap()
+        );
+
+        database
+            .store_profile_details("did:new", "nl", Some(0.9))
+            .await
+            .unwrap();
+        assert_eq!(
+            database
+                .fetch_profile_residency("did:new", "nl")
+                .await
+                .unwrap(),
+            Some(Residency::InCountry)
+        );
+        assert_eq!(
+            database
+                .count_classified_profiles_among(&["did:new".to_owned()], "nl")
+                .await
+                .unwrap(),
+            (1, 1)
+        );
+
+        let details = database
+            .fetch_profile_details("did:new")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(details.classification_attempts, 1);
+        assert!(!details.is_banned);
+
+        database
+            .create_subscription_state("wss://bsky.network", "did:feed")
+            .await
+            .unwrap();
+        database
+            .update_subscription_cursor("wss://bsky.network", "did:feed", 42)
+            .await
+            .unwrap();
+        assert_eq!(
+            database
+                .fetch_subscription_cursor("wss://bsky.network", "did:feed")
+                .await
+                .unwrap(),
+            Some(42)
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{Result, bail};
+use log::info;
+use sqlx::migrate::{Migrate, Migrator};
+
+use super::{SchemaStatus, SqliteStorage};
+use crate::services::database::migrations::check_applied_migrations;
+
+/// Migrations in the `sql/sqlite/` directory, embedded at build time. SQLite
+/// databases start out from the current schema rather than replaying the
+/// history of the Postgres one.
+static MIGRATOR: Migrator = sqlx::migrate!("../sql/sqlite");
+
+impl SqliteStorage {
+    pub(super) async fn run_migrations(&self) -> Result<SchemaStatus> {
+        let status = self.check_schema().await?;
+
+        if status.pending > 0 {
+            info!(
+                "Applying {} database migrations up to version {}",
+                status.pending, status.expected_version
+            );
+
+            MIGRATOR.run(&self.connection_pool).await?;
+        }
+
+        Ok(SchemaStatus {
+            current_version: Some(status.expected_version),
+            pending: 0,
+            ..status
+        })
+    }
+
+    pub(super) async fn check_schema(&self) -> Result<SchemaStatus> {
+        let expected_version = expected_schema_version();
+
+        let mut connection = self.connection_pool.acquire().await?;
+
+        connection.ensure_migrations_table().await?;
+
+        if let Some(version) = connection.dirty_version().await? {
+            bail!(
+                "Migration {} failed partway through and has to be fixed by hand",
+                version
+            );
+        }
+
+        let applied = connection.list_applied_migrations().await?;
+
+        check_applied_migrations(MIGRATOR.migrations.as_ref(), &applied)?;
+
+        Ok(SchemaStatus {
+            current_version: applied.last().map(|m| m.version),
+            expected_version,
+            pending: MIGRATOR.iter().count() - applied.len(),
+        })
+    }
+}
+
+fn expected_schema_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
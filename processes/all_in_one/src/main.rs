@@ -0,0 +1,99 @@
+//! Runs every process against one database, for deployments small enough
+//! that running them separately isn't worth it, such as with a SQLite file.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use env_logger::Env;
+use lingua::LanguageDetectorBuilder;
+use log::info;
+
+use nederlandskie_core::config::Config;
+use nederlandskie_core::services::{AI, Bluesky, Database};
+use nederlandskie_feed_server::{FeedServer, feeds::initialize_all_feeds};
+use nederlandskie_janitor::Janitor;
+use nederlandskie_post_indexer::{
+    PostIndexer, indexers::initialize_all_indexers, labels::LabelIndexer,
+};
+use nederlandskie_profile_classifier::ProfileClassifier;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    info!("Loading configuration");
+
+    let config = Arc::new(Config::load()?);
+
+    info!("Connecting to the database");
+
+    let mut database =
+        Database::connect_with_pool(&config.database_url, &config.database_pool).await?;
+
+    if let Some(read_url) = &config.database_read_url {
+        info!("Sending feed queries to the read replica");
+        database = database.with_replica_url(read_url, &config.database_pool)?;
+    }
+
+    let database = Arc::new(database);
+
+    info!("Bringing the database schema up to date");
+    database.migrate().await?;
+
+    if config.metrics_enabled {
+        tokio::spawn(database.clone().report_pool_metrics());
+    }
+
+    info!("Initializing language detector");
+
+    let language_detector = Arc::new(
+        LanguageDetectorBuilder::from_all_languages_with_cyrillic_script()
+            .with_preloaded_language_models()
+            .build(),
+    );
+
+    if let Some(host) = config.labeler_host.clone() {
+        let label_indexer = LabelIndexer::new(
+            database.clone(),
+            Bluesky::unauthenticated(),
+            host,
+            config.feed_generator_did.to_string(),
+        );
+
+        info!("Starting Label Indexer");
+
+        tokio::spawn(label_indexer.start());
+    }
+
+    let post_indexer = PostIndexer::new(
+        database.clone(),
+        Bluesky::unauthenticated(),
+        initialize_all_indexers(language_detector.clone(), database.clone()),
+        language_detector,
+        config.as_ref().clone(),
+    );
+
+    let profile_classifier = ProfileClassifier::new(
+        database.clone(),
+        AI::new(&config.anthropic_api_key),
+        Bluesky::unauthenticated(),
+        &config,
+    );
+
+    let janitor = Janitor::new(database.clone(), config.retention.clone());
+
+    // Metrics of every process end up on the feed server's /metrics endpoint,
+    // since it installs the global recorder they all report to
+    let feed_server = FeedServer::new(database, config, Arc::new(initialize_all_feeds()));
+
+    info!("Starting Post Indexer, Profile Classifier, Janitor and Feed Server");
+
+    tokio::try_join!(
+        post_indexer.start(),
+        profile_classifier.start(),
+        janitor.start(),
+        feed_server.serve(),
+    )?;
+
+    Ok(())
+}
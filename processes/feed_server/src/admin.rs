@@ -0,0 +1,38 @@
+mod auth;
+mod pages;
+mod posts;
+mod profiles;
+mod stats;
+
+use axum::Router;
+use axum::middleware::from_fn_with_state;
+use axum::routing::{delete, get, post, put};
+
+use self::auth::{AdminToken, log_in, log_out, login_page, require_admin_token};
+use super::state::FeedServerState;
+
+/// Moderator name recorded in the moderation log for actions done through the API
+const MODERATOR: &str = "admin_api";
+
+/// Routes for operating the feed, all of them except logging in requiring the admin token
+pub fn router(token: &str, secure_cookie: bool) -> Router<FeedServerState> {
+    let token = AdminToken::new(token).with_secure_cookie(secure_cookie);
+
+    let login = Router::new()
+        .route("/login", get(login_page).post(log_in))
+        .with_state(token.clone());
+
+    Router::new()
+        .route("/", get(pages::dashboard))
+        .route("/classified", get(pages::profiles))
+        .route("/classified/{did}/country", post(pages::correct_country))
+        .route("/recent-posts", get(pages::posts))
+        .route("/logout", post(log_out).with_state(token.clone()))
+        .route("/stats", get(stats::stats))
+        .route("/profiles/{did}", get(profiles::profile))
+        .route("/profiles/{did}/country", put(profiles::force_country))
+        .route("/profiles/{did}/reclassify", post(profiles::reclassify))
+        .route("/posts", delete(posts::remove_post))
+        .layer(from_fn_with_state(token, require_admin_token))
+        .merge(login)
+}
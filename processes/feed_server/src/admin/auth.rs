@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::Form;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use http::header::{ACCEPT, AUTHORIZATION, COOKIE, SET_COOKIE};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::errors::AppError;
+
+/// Cookie that keeps the admin session for the dashboard pages
+const SESSION_COOKIE: &str = "nederlandskie_admin";
+
+#[derive(Clone)]
+pub struct AdminToken {
+    token: Arc<str>,
+    /// Derived from the token, so that the cookie doesn't give the token away
+    /// and sessions end whenever the token changes
+    session: Arc<str>,
+    /// Whether the session cookie is only ever sent over HTTPS
+    secure_cookie: bool,
+}
+
+impl AdminToken {
+    pub fn new(token: &str) -> Self {
+        let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(b"nederlandskie admin session");
+
+        let session = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+
+        Self {
+            token: token.into(),
+            session: session.into(),
+            secure_cookie: true,
+        }
+    }
+
+    /// Lets the session cookie be sent over plain HTTP too, for running the
+    /// dashboard locally without TLS in front of it
+    pub fn with_secure_cookie(mut self, secure_cookie: bool) -> Self {
+        self.secure_cookie = secure_cookie;
+        self
+    }
+
+    fn matches(&self, provided: &str) -> bool {
+        constant_time_eq(&self.token, provided)
+    }
+
+    fn matches_session(&self, provided: &str) -> bool {
+        constant_time_eq(&self.session, provided)
+    }
+
+    fn session_cookie(&self, value: &str) -> String {
+        let secure = if self.secure_cookie { "; Secure" } else { "" };
+
+        format!("{SESSION_COOKIE}={value}; Path=/admin; HttpOnly{secure}; SameSite=Strict")
+    }
+}
+
+/// Compares in constant time so that secrets can't be guessed byte by byte.
+/// Nothing matches an empty secret.
+fn constant_time_eq(expected: &str, provided: &str) -> bool {
+    let expected = expected.as_bytes();
+    let provided = provided.as_bytes();
+
+    !expected.is_empty()
+        && expected.len() == provided.len()
+        && expected
+            .iter()
+            .zip(provided)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+pub async fn require_admin_token(
+    State(token): State<AdminToken>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let headers = request.headers();
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let session = session_from_cookies(headers.get_all(COOKIE));
+
+    let authenticated = match (bearer, session) {
+        (Some(bearer), _) => token.matches(bearer),
+        (None, Some(session)) => token.matches_session(session),
+        (None, None) if wants_html(headers) => {
+            return Ok(Redirect::to("/admin/login").into_response());
+        }
+        (None, None) => false,
+    };
+
+    if authenticated {
+        Ok(next.run(request).await)
+    } else {
+        Err(AppError::Unauthorized)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/login.html")]
+struct LoginTemplate {
+    failed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    token: String,
+}
+
+pub async fn login_page() -> Result<Html<String>, AppError> {
+    Ok(Html(LoginTemplate { failed: false }.render()?))
+}
+
+pub async fn log_in(
+    State(token): State<AdminToken>,
+    Form(login): Form<Login>,
+) -> Result<Response, AppError> {
+    if !token.matches(&login.token) {
+        let rendered = LoginTemplate { failed: true }.render()?;
+        return Ok((StatusCode::UNAUTHORIZED, Html(rendered)).into_response());
+    }
+
+    let cookie = token.session_cookie(&token.session);
+
+    Ok(([(SET_COOKIE, cookie)], Redirect::to("/admin")).into_response())
+}
+
+pub async fn log_out(State(token): State<AdminToken>) -> Response {
+    let cookie = format!("{}; Max-Age=0", token.session_cookie(""));
+
+    ([(SET_COOKIE, cookie)], Redirect::to("/admin/login")).into_response()
+}
+
+fn session_from_cookies<'a>(
+    headers: impl IntoIterator<Item = &'a http::HeaderValue>,
+) -> Option<&'a str> {
+    headers
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Whether the request comes from a browser, which is better off being sent
+/// to the login page than shown a bare error
+fn wants_html(headers: &http::HeaderMap) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn status_with_headers(headers: &[(http::HeaderName, &str)]) -> StatusCode {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(from_fn_with_state(
+                AdminToken::new("secret"),
+                require_admin_token,
+            ));
+
+        let mut request = Request::builder().uri("/");
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    async fn status_with_authorization(authorization: Option<&str>) -> StatusCode {
+        match authorization {
+            Some(authorization) => status_with_headers(&[(AUTHORIZATION, authorization)]).await,
+            None => status_with_headers(&[]).await,
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_correct_token() {
+        assert_eq!(
+            status_with_authorization(Some("Bearer secret")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_or_wrong_token() {
+        assert_eq!(
+            status_with_authorization(None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_with_authorization(Some("Bearer secre")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_with_authorization(Some("secret")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_session_cookie_but_not_token_in_it() {
+        let session = format!(
+            "theme=dark; nederlandskie_admin={}",
+            AdminToken::new("secret").session
+        );
+
+        assert_eq!(
+            status_with_headers(&[(COOKIE, &session)]).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status_with_headers(&[(COOKIE, "nederlandskie_admin=secret")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_with_headers(&[(COOKIE, "nederlandskie_admin=")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn empty_token_matches_nothing() {
+        let token = AdminToken::new("");
+
+        assert!(!token.matches(""));
+        assert!(!token.matches_session(""));
+    }
+
+    #[test]
+    fn leaves_out_secure_only_when_asked_to() {
+        let token = AdminToken::new("secret");
+
+        assert!(token.session_cookie("x").contains("; Secure;"));
+        assert!(
+            !token
+                .with_secure_cookie(false)
+                .session_cookie("x")
+                .contains("Secure")
+        );
+    }
+
+    #[tokio::test]
+    async fn redirects_browsers_to_login() {
+        assert_eq!(
+            status_with_headers(&[(ACCEPT, "text/html,*/*")]).await,
+            StatusCode::SEE_OTHER
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::Form;
+use axum::extract::{Path, Query, State};
+use axum::response::{Html, Redirect};
+use serde::Deserialize;
+
+use super::profiles::apply_forced_country;
+use crate::errors::AppError;
+
+use nederlandskie_core::services::Database;
+use nederlandskie_core::services::database::{ClassifiedProfile, PostPreview};
+
+const PAGE_SIZE: usize = 50;
+
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardTemplate {
+    total_posts: i64,
+    nl_profiles: i64,
+    unprocessed_profiles: i64,
+    failed_profiles: i64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/profiles.html")]
+struct ProfilesTemplate {
+    country: Option<String>,
+    profiles: Vec<ClassifiedProfile>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/posts.html")]
+struct PostsTemplate {
+    posts: Vec<PostPreview>,
+}
+
+#[derive(Deserialize)]
+pub struct ProfilesQuery {
+    country: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CorrectCountry {
+    country: String,
+}
+
+pub async fn dashboard(State(database): State<Arc<Database>>) -> Result<Html<String>, AppError> {
+    let rendered = DashboardTemplate {
+        total_posts: database.count_posts().await?,
+        nl_profiles: database.count_profiles_in_country("nl").await?,
+        unprocessed_profiles: database.count_unprocessed_profiles().await?,
+        failed_profiles: database.count_failed_profiles().await?,
+    }
+    .render()?;
+
+    Ok(Html(rendered))
+}
+
+pub async fn profiles(
+    State(database): State<Arc<Database>>,
+    Query(query): Query<ProfilesQuery>,
+) -> Result<Html<String>, AppError> {
+    let country = query.country.filter(|c| !c.is_empty());
+
+    let profiles = database
+        .fetch_recently_classified_profiles(country.as_deref(), PAGE_SIZE)
+        .await?;
+
+    let rendered = ProfilesTemplate { country, profiles }.render()?;
+
+    Ok(Html(rendered))
+}
+
+pub async fn correct_country(
+    State(database): State<Arc<Database>>,
+    Path(did): Path<String>,
+    Form(form): Form<CorrectCountry>,
+) -> Result<Redirect, AppError> {
+    apply_forced_country(&database, &did, &form.country).await?;
+
+    Ok(Redirect::to("/admin/classified"))
+}
+
+pub async fn posts(State(database): State<Arc<Database>>) -> Result<Html<String>, AppError> {
+    let posts = database.fetch_recent_post_previews("nl", PAGE_SIZE).await?;
+
+    let rendered = PostsTemplate { posts }.render()?;
+
+    Ok(Html(rendered))
+}
+
+impl PostsTemplate {
+    /// Link to the post on the Bluesky web app, where it can be read in context
+    fn post_url(&self, uri: &str) -> String {
+        bsky_app_post_url(uri).unwrap_or_else(|| uri.to_owned())
+    }
+}
+
+fn bsky_app_post_url(uri: &str) -> Option<String> {
+    let (did, rkey) = uri
+        .strip_prefix("at://")?
+        .split_once("/app.bsky.feed.post/")?;
+
+    Some(format!("https://bsky.app/profile/{did}/post/{rkey}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_posts_to_bsky_app() {
+        assert_eq!(
+            bsky_app_post_url("at://did:plc:abc/app.bsky.feed.post/3kxyz").as_deref(),
+            Some("https://bsky.app/profile/did:plc:abc/post/3kxyz")
+        );
+        assert_eq!(
+            bsky_app_post_url("at://did:plc:abc/app.bsky.feed.repost/3kxyz"),
+            None
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use serde::Deserialize;
+
+use super::MODERATOR;
+use crate::errors::AppError;
+
+use nederlandskie_core::services::Database;
+
+#[derive(Deserialize)]
+pub struct RemovePost {
+    uri: String,
+}
+
+/// Deletes an indexed post. Unlike banning, nothing stops the post from
+/// being indexed again if it's ever re-sent on the firehose.
+pub async fn remove_post(
+    State(database): State<Arc<Database>>,
+    Query(query): Query<RemovePost>,
+) -> Result<(), AppError> {
+    if !database.delete_post(&query.uri).await? {
+        return Err(AppError::NotFound(query.uri));
+    }
+
+    database
+        .record_moderation_action("remove_post", &query.uri, None, Some(MODERATOR))
+        .await?;
+
+    Ok(())
+}
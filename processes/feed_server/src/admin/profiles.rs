@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Path, State};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::MODERATOR;
+use crate::errors::AppError;
+
+use nederlandskie_core::services::{Database, is_valid_country_code};
+
+#[derive(Serialize)]
+pub struct Profile {
+    did: String,
+    first_seen_at: DateTime<Utc>,
+    has_been_processed: bool,
+    likely_country_of_living: Option<String>,
+    residency_probability: Option<f64>,
+    classification_attempts: i32,
+    next_classification_attempt_at: Option<DateTime<Utc>>,
+    has_failed_classification: bool,
+    last_classification_error: Option<String>,
+    post_count: i64,
+    is_banned: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ForceCountry {
+    country: String,
+}
+
+pub async fn profile(
+    State(database): State<Arc<Database>>,
+    Path(did): Path<String>,
+) -> Result<Json<Profile>, AppError> {
+    let profile = database
+        .fetch_profile_details(&did)
+        .await?
+        .ok_or_else(|| AppError::NotFound(did))?;
+
+    Ok(Json(Profile {
+        did: profile.did,
+        first_seen_at: profile.first_seen_at,
+        has_been_processed: profile.has_been_processed,
+        likely_country_of_living: profile.likely_country_of_living,
+        residency_probability: profile.residency_probability,
+        classification_attempts: profile.classification_attempts,
+        next_classification_attempt_at: profile.next_classification_attempt_at,
+        has_failed_classification: profile.has_failed_classification,
+        last_classification_error: profile.last_classification_error,
+        post_count: profile.post_count,
+        is_banned: profile.is_banned,
+    }))
+}
+
+pub async fn force_country(
+    State(database): State<Arc<Database>>,
+    Path(did): Path<String>,
+    Json(body): Json<ForceCountry>,
+) -> Result<(), AppError> {
+    apply_forced_country(&database, &did, &body.country).await
+}
+
+/// Validates the country and forces the profile into it, leaving a trace in the moderation log
+pub async fn apply_forced_country(
+    database: &Database,
+    did: &str,
+    country: &str,
+) -> Result<(), AppError> {
+    let normalized = country.trim().to_lowercase();
+
+    if !is_valid_country_code(&normalized) {
+        return Err(AppError::BadRequest(format!(
+            "Expected an ISO 3166-1 alpha-2 country code, got {:?}",
+            country
+        )));
+    }
+
+    database
+        .override_profile_country(did, &normalized, Some(MODERATOR))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn reclassify(
+    State(database): State<Arc<Database>>,
+    Path(did): Path<String>,
+) -> Result<(), AppError> {
+    if !database.reset_profile_classification(&did).await? {
+        return Err(AppError::NotFound(did));
+    }
+
+    database
+        .record_moderation_action("reclassify", &did, None, Some(MODERATOR))
+        .await?;
+
+    Ok(())
+}
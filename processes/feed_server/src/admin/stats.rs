@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use serde::Serialize;
+
+use crate::errors::AppError;
+
+use nederlandskie_core::services::Database;
+
+#[derive(Serialize)]
+pub struct Stats {
+    total_posts: i64,
+    nl_profiles: i64,
+    unprocessed_profiles: i64,
+    failed_profiles: i64,
+}
+
+pub async fn stats(State(database): State<Arc<Database>>) -> Result<Json<Stats>, AppError> {
+    Ok(Json(Stats {
+        total_posts: database.count_posts().await?,
+        nl_profiles: database.count_profiles_in_country("nl").await?,
+        unprocessed_profiles: database.count_unprocessed_profiles().await?,
+        failed_profiles: database.count_failed_profiles().await?,
+    }))
+}
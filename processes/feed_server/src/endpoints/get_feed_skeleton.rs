@@ -1,14 +1,15 @@
 use std::sync::Arc;
 
 use anyhow::anyhow;
-use atrium_api::app::bsky::feed::defs::SkeletonFeedPostData;
+use atrium_api::app::bsky::feed::defs::{
+    SkeletonFeedPostData, SkeletonFeedPostReasonRefs, SkeletonReasonRepostData,
+};
 use atrium_api::app::bsky::feed::get_feed_skeleton::{
     OutputData as FeedSkeleton, ParametersData as FeedSkeletonQuery,
 };
-use atrium_api::types::{LimitedNonZeroU8, Object};
+use atrium_api::types::{LimitedNonZeroU8, Object, Union};
 use axum::Json;
 use axum::extract::{Query, State};
-use chrono::{DateTime, TimeZone, Utc};
 
 use crate::errors::AppError;
 use crate::feeds::Feeds;
@@ -33,51 +34,28 @@ pub async fn get_feed_skeleton(
     let limit = query
         .limit
         .unwrap_or(LimitedNonZeroU8::try_from(20).expect("this default limit should always work"));
-    let earlier_than = query.cursor.as_deref().map(parse_cursor).transpose()?;
-
-    let posts = feed
-        .fetch_posts(&database, limit.into(), earlier_than)
+    let page = feed
+        .fetch_page(&database, limit.into(), query.cursor.as_deref())
         .await?;
 
-    let feed = posts
-        .iter()
-        .map(|p| SkeletonFeedPostData {
-            post: p.uri.clone(),
-            feed_context: None,
-            reason: None,
+    let feed = page
+        .items
+        .into_iter()
+        .map(|item| SkeletonFeedPostData {
+            post: item.post_uri,
+            feed_context: item.feed_context,
+            reason: item.repost_uri.map(|repost| {
+                Union::Refs(SkeletonFeedPostReasonRefs::SkeletonReasonRepost(Box::new(
+                    SkeletonReasonRepostData { repost }.into(),
+                )))
+            }),
         })
         .map(Object::from)
         .collect();
 
-    let cursor = posts.last().map(|p| make_cursor(&p.created_at, &p.cid));
-
     Ok(Json(FeedSkeleton {
-        cursor,
+        cursor: page.cursor,
         feed,
         req_id: None,
     }))
 }
-
-fn make_cursor(date: &DateTime<Utc>, cid: &str) -> String {
-    format!("{}::{}", date.timestamp() * 1000, cid)
-}
-
-fn parse_cursor(cursor: &str) -> anyhow::Result<(DateTime<Utc>, &str)> {
-    let mut parts = cursor.split("::");
-
-    let created_at = parts
-        .next()
-        .ok_or_else(|| anyhow!("Malformed cursor: {cursor}"))?;
-    let cid = parts
-        .next()
-        .ok_or_else(|| anyhow!("Malformed cursor: {cursor}"))?;
-
-    if parts.next().is_some() {
-        return Err(anyhow!("Malformed cursor: {cursor}"));
-    }
-
-    let created_at: i64 = created_at.parse()?;
-    let created_at = Utc.timestamp_opt(created_at / 1000, 0).unwrap(); // TODO: handle error
-
-    Ok((created_at, cid))
-}
@@ -3,6 +3,9 @@ use axum::response::{IntoResponse, Response};
 
 pub enum AppError {
     FeedNotFound(String),
+    NotFound(String),
+    BadRequest(String),
+    Unauthorized,
     Other(anyhow::Error),
 }
 
@@ -12,6 +15,9 @@ impl IntoResponse for AppError {
             Self::FeedNotFound(name) => {
                 (StatusCode::NOT_FOUND, format!("Feed not found: {}", name))
             }
+            Self::NotFound(what) => (StatusCode::NOT_FOUND, format!("Not found: {}", what)),
+            Self::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            Self::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_owned()),
             Self::Other(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Something went wrong: {}", e),
@@ -1,28 +1,116 @@
+mod cursor;
 mod nederlandskie;
+mod nederlandskie_top;
 
 use std::collections::HashMap;
 
 use anyhow::Result;
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
 
-use nederlandskie_core::services::database::{self, Database};
+use nederlandskie_core::services::bluesky::EmbedKind;
+use nederlandskie_core::services::database::{self, Database, PostFilters, ReplyPolicy};
 
 pub use self::nederlandskie::NederlandskieFeed;
+pub use self::nederlandskie_top::NederlandskieTopFeed;
+
+/// Labels that make posts unsuitable for feeds that aren't explicitly meant
+/// for such content
+const ADULT_LABELS: [&str; 5] = ["!hide", "porn", "sexual", "nudity", "graphic-media"];
+
+/// A post to serve, along with the repost that brought it into the feed, if any
+pub struct FeedItem {
+    pub post_uri: String,
+    pub repost_uri: Option<String>,
+    /// Passed along to the app view, mentioning labels of the post if the
+    /// feed marks labeled content
+    pub feed_context: Option<String>,
+}
+
+impl From<database::Post> for FeedItem {
+    fn from(post: database::Post) -> Self {
+        Self {
+            feed_context: (!post.labels.is_empty())
+                .then(|| format!("labels={}", post.labels.join(","))),
+            post_uri: post.uri,
+            repost_uri: None,
+        }
+    }
+}
+
+impl From<database::Repost> for FeedItem {
+    fn from(repost: database::Repost) -> Self {
+        Self {
+            post_uri: repost.post_uri,
+            repost_uri: Some(repost.uri),
+            feed_context: None,
+        }
+    }
+}
+
+/// A page of feed items, along with the cursor to fetch the next one with.
+/// Each feed decides what goes into its cursors.
+pub struct FeedPage {
+    pub items: Vec<FeedItem>,
+    pub cursor: Option<String>,
+}
 
 #[async_trait]
 pub trait Feed {
-    async fn fetch_posts(
+    async fn fetch_page(
         &self,
         database: &Database,
         limit: u8,
-        earlier_than: Option<(DateTime<Utc>, &str)>,
-    ) -> Result<Vec<database::Post>>;
+        cursor: Option<&str>,
+    ) -> Result<FeedPage>;
 }
 
 pub fn initialize_all_feeds() -> Feeds {
+    let adult_labels: Vec<String> = ADULT_LABELS.map(str::to_owned).into();
+
     FeedsBuilder::new()
-        .add("nederlandskie", NederlandskieFeed::new())
+        .add(
+            "nederlandskie",
+            NederlandskieFeed::new(PostFilters {
+                hidden_labels: vec!["!hide".to_owned()],
+                mark_labeled: true,
+                ..Default::default()
+            }),
+        )
+        .add(
+            "nederlandskie-reposts",
+            NederlandskieFeed::new(PostFilters {
+                hidden_labels: vec!["!hide".to_owned()],
+                mark_labeled: true,
+                ..Default::default()
+            })
+            .with_reposts(),
+        )
+        .add(
+            "nederlandskie-top",
+            NederlandskieTopFeed::new(PostFilters {
+                replies: ReplyPolicy::Hide,
+                hidden_labels: adult_labels.clone(),
+                ..Default::default()
+            }),
+        )
+        .add(
+            "nederlandskie-media",
+            NederlandskieFeed::new(PostFilters {
+                embed_kinds: vec![EmbedKind::Images, EmbedKind::Video],
+                hide_quotes_from_outside: true,
+                hidden_labels: adult_labels.clone(),
+                ..Default::default()
+            }),
+        )
+        .add(
+            "nederlandskie-links",
+            NederlandskieFeed::new(PostFilters {
+                embed_kinds: vec![EmbedKind::External],
+                hide_quotes_from_outside: true,
+                hidden_labels: adult_labels,
+                ..Default::default()
+            }),
+        )
         .build()
 }
 
@@ -0,0 +1,82 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+
+/// Makes a cursor for feeds ordered by creation time, pointing at the last
+/// post that has been served. Keeps the creation time down to microseconds,
+/// the way the database does, so that the next page starts right after it.
+pub fn make_chronological_cursor(date: &DateTime<Utc>, cid: &str) -> String {
+    format!("{}::{}", date.timestamp_micros(), cid)
+}
+
+pub fn parse_chronological_cursor(cursor: &str) -> Result<(DateTime<Utc>, &str)> {
+    let (created_at, cid) = split_cursor(cursor)?;
+
+    let created_at = DateTime::from_timestamp_micros(created_at.parse()?)
+        .ok_or_else(|| anyhow!("Malformed cursor: {cursor}"))?;
+
+    Ok((created_at, cid))
+}
+
+/// Makes a cursor for ranked feeds, remembering the moment the ranking was
+/// computed as of and how many posts have been served so far
+pub fn make_ranked_cursor(as_of: &DateTime<Utc>, offset: usize) -> String {
+    format!("{}::{}", as_of.timestamp_millis(), offset)
+}
+
+pub fn parse_ranked_cursor(cursor: &str) -> Result<(DateTime<Utc>, usize)> {
+    let (as_of, offset) = split_cursor(cursor)?;
+
+    let as_of = DateTime::from_timestamp_millis(as_of.parse()?)
+        .ok_or_else(|| anyhow!("Malformed cursor: {cursor}"))?;
+
+    Ok((as_of, offset.parse()?))
+}
+
+fn split_cursor(cursor: &str) -> Result<(&str, &str)> {
+    let mut parts = cursor.split("::");
+
+    let first = parts
+        .next()
+        .ok_or_else(|| anyhow!("Malformed cursor: {cursor}"))?;
+    let second = parts
+        .next()
+        .ok_or_else(|| anyhow!("Malformed cursor: {cursor}"))?;
+
+    if parts.next().is_some() {
+        return Err(anyhow!("Malformed cursor: {cursor}"));
+    }
+
+    Ok((first, second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_ranked_cursor() {
+        let as_of = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let cursor = make_ranked_cursor(&as_of, 40);
+
+        assert_eq!(parse_ranked_cursor(&cursor).unwrap(), (as_of, 40));
+    }
+
+    #[test]
+    fn round_trips_chronological_cursor() {
+        let created_at = DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+        let cursor = make_chronological_cursor(&created_at, "bafy");
+
+        assert_eq!(
+            parse_chronological_cursor(&cursor).unwrap(),
+            (created_at, "bafy")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        assert!(parse_chronological_cursor("1700000000000").is_err());
+        assert!(parse_chronological_cursor("9223372036854775807::bafy").is_err());
+        assert!(parse_ranked_cursor("1700000000000::a::b").is_err());
+        assert!(parse_ranked_cursor("1700000000000::-1").is_err());
+    }
+}
@@ -1,36 +1,317 @@
+use std::collections::HashSet;
+
 use anyhow::Result;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 
-use super::Feed;
+use super::cursor::{make_chronological_cursor, parse_chronological_cursor};
+use super::{Feed, FeedItem, FeedPage};
 
-use nederlandskie_core::services::database::{self, Database};
+use nederlandskie_core::services::database::{Database, Post, PostFilters, Repost};
 
-/// A feed that serves posts written in Russian by people living in Netherlands
-pub struct NederlandskieFeed;
+/// A feed that serves posts written in Russian by people living in Netherlands,
+/// optionally along with whatever else those people repost
+pub struct NederlandskieFeed {
+    filters: PostFilters,
+    with_reposts: bool,
+}
 
 impl Default for NederlandskieFeed {
     fn default() -> Self {
-        Self::new()
+        Self::new(PostFilters::default())
     }
 }
 
 impl NederlandskieFeed {
-    pub fn new() -> Self {
-        Self
+    pub fn new(filters: PostFilters) -> Self {
+        Self {
+            filters,
+            with_reposts: false,
+        }
+    }
+
+    /// Also serves posts reposted by people living in Netherlands. Those
+    /// aren't subject to the filters, since we don't know much about them.
+    pub fn with_reposts(mut self) -> Self {
+        self.with_reposts = true;
+        self
     }
 }
 
 #[async_trait]
 impl Feed for NederlandskieFeed {
-    async fn fetch_posts(
+    async fn fetch_page(
         &self,
         database: &Database,
         limit: u8,
-        earlier_than: Option<(DateTime<Utc>, &str)>,
-    ) -> Result<Vec<database::Post>> {
-        Ok(database
-            .fetch_posts_by_authors_country("nl", limit as usize, earlier_than)
-            .await?)
+        cursor: Option<&str>,
+    ) -> Result<FeedPage> {
+        let earlier_than = cursor.map(parse_chronological_cursor).transpose()?;
+
+        let posts = database
+            .fetch_posts_by_authors_country("nl", limit as usize, earlier_than, &self.filters)
+            .await?;
+
+        let reposts = if self.with_reposts {
+            database
+                .fetch_reposts_by_reposters_country("nl", limit as usize, earlier_than)
+                .await?
+        } else {
+            Vec::new()
+        };
+
+        let entries = merge_chronologically(posts, reposts, limit as usize);
+
+        let cursor = entries
+            .last()
+            .map(|(created_at, cid, _)| make_chronological_cursor(created_at, cid));
+
+        Ok(FeedPage {
+            items: entries.into_iter().map(|(_, _, item)| item).collect(),
+            cursor,
+        })
+    }
+}
+
+/// Interleaves posts and reposts, both already sorted from newest to oldest,
+/// keeping the newest ones along with what's needed to make a cursor. A post
+/// that shows up more than once, such as when several people repost it, is
+/// only kept at its newest entry.
+fn merge_chronologically(
+    posts: Vec<Post>,
+    reposts: Vec<Repost>,
+    limit: usize,
+) -> Vec<(DateTime<Utc>, String, FeedItem)> {
+    let mut entries: Vec<_> = posts
+        .into_iter()
+        .map(|p| (p.created_at, p.cid.clone(), FeedItem::from(p)))
+        .chain(
+            reposts
+                .into_iter()
+                .map(|r| (r.created_at, r.cid.clone(), FeedItem::from(r))),
+        )
+        .collect();
+
+    entries.sort_by(|(a_date, a_cid, _), (b_date, b_cid, _)| {
+        b_date.cmp(a_date).then_with(|| b_cid.cmp(a_cid))
+    });
+
+    let mut seen = HashSet::new();
+    entries.retain(|(_, _, item)| seen.insert(item.post_uri.clone()));
+
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use nederlandskie_core::services::database::tests::new_post;
+    use nederlandskie_core::services::database::{NewPost, ReplyPolicy};
+
+    use super::*;
+
+    fn post(minute: u32, cid: &str) -> Post {
+        Post {
+            created_at: Utc.with_ymd_and_hms(2024, 1, 1, 12, minute, 0).unwrap(),
+            author_did: "did:plc:author".to_owned(),
+            cid: cid.to_owned(),
+            uri: format!("at://did:plc:author/app.bsky.feed.post/{cid}"),
+            labels: Vec::new(),
+        }
+    }
+
+    fn repost(minute: u32, cid: &str) -> Repost {
+        Repost {
+            created_at: Utc.with_ymd_and_hms(2024, 1, 1, 12, minute, 0).unwrap(),
+            author_did: "did:plc:reposter".to_owned(),
+            cid: cid.to_owned(),
+            uri: format!("at://did:plc:reposter/app.bsky.feed.repost/{cid}"),
+            post_uri: "at://did:plc:someone/app.bsky.feed.post/original".to_owned(),
+        }
+    }
+
+    #[test]
+    fn interleaves_posts_and_reposts() {
+        let entries = merge_chronologically(
+            vec![post(30, "p1"), post(10, "p2")],
+            vec![repost(20, "r1"), repost(5, "r2")],
+            3,
+        );
+
+        let cids: Vec<_> = entries.iter().map(|(_, cid, _)| cid.as_str()).collect();
+        assert_eq!(cids, ["p1", "r1", "p2"]);
+
+        assert!(entries[0].2.repost_uri.is_none());
+        assert_eq!(
+            entries[1].2.repost_uri.as_deref(),
+            Some("at://did:plc:reposter/app.bsky.feed.repost/r1")
+        );
+        assert_eq!(
+            entries[1].2.post_uri,
+            "at://did:plc:someone/app.bsky.feed.post/original"
+        );
+    }
+
+    #[test]
+    fn keeps_newest_entry_of_the_same_post() {
+        let entries = merge_chronologically(
+            vec![post(10, "p1")],
+            vec![repost(30, "r1"), repost(20, "r2")],
+            3,
+        );
+
+        let cids: Vec<_> = entries.iter().map(|(_, cid, _)| cid.as_str()).collect();
+        assert_eq!(cids, ["r1", "p1"]);
+    }
+
+    #[tokio::test]
+    async fn pages_through_posts_and_reposts() {
+        let database = Database::in_memory();
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+
+        // Cids sorting in a different order than the items were made in, so
+        // that pages can't be cut by cid alone
+        for (cid, minutes_ago) in [("z", 3), ("c", 1)] {
+            let uri = format!("at://did:nl/app.bsky.feed.post/{cid}");
+            database
+                .insert_post(&new_post("did:nl", &uri, minutes_ago))
+                .await
+                .unwrap();
+        }
+        database
+            .insert_repost_if_relevant(
+                "did:nl",
+                "m",
+                "at://did:nl/app.bsky.feed.repost/m",
+                "at://did:other/app.bsky.feed.post/x",
+                Utc::now() - TimeDelta::minutes(2),
+                "nl",
+            )
+            .await
+            .unwrap();
+
+        let feed = NederlandskieFeed::default().with_reposts();
+
+        let first_page = feed.fetch_page(&database, 2, None).await.unwrap();
+        assert_eq!(first_page.items.len(), 2);
+        assert_eq!(
+            first_page.items[0].post_uri,
+            "at://did:nl/app.bsky.feed.post/c"
+        );
+        assert!(first_page.items[1].repost_uri.is_some());
+
+        let second_page = feed
+            .fetch_page(&database, 2, first_page.cursor.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(second_page.items.len(), 1);
+        assert_eq!(
+            second_page.items[0].post_uri,
+            "at://did:nl/app.bsky.feed.post/z"
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_through_posts_made_within_the_same_second() {
+        let database = Database::in_memory();
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+
+        let second = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        for (cid, millis) in [("a", 300), ("b", 200), ("c", 100)] {
+            let uri = format!("at://did:nl/app.bsky.feed.post/{cid}");
+            database
+                .insert_post(&NewPost {
+                    created_at: second + TimeDelta::milliseconds(millis),
+                    ..new_post("did:nl", &uri, 0)
+                })
+                .await
+                .unwrap();
+        }
+
+        let feed = NederlandskieFeed::default();
+
+        let mut cids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = feed
+                .fetch_page(&database, 1, cursor.as_deref())
+                .await
+                .unwrap();
+            let Some(item) = page.items.first() else {
+                break;
+            };
+
+            cids.push(item.post_uri.rsplit('/').next().unwrap().to_owned());
+            cursor = page.cursor;
+        }
+
+        assert_eq!(cids, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn serves_replies_by_policy() {
+        let database = Database::in_memory();
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+        database
+            .force_profile_country("did:de", "de")
+            .await
+            .unwrap();
+
+        let root = "at://did:nl/app.bsky.feed.post/root";
+        let outside = "at://did:de/app.bsky.feed.post/outside";
+
+        for (cid, minutes_ago, reply) in [
+            ("root", 4, None),
+            ("own", 3, Some((root, root))),
+            ("outsider", 2, Some((outside, outside))),
+            (
+                "latest",
+                1,
+                Some(("at://did:nl/app.bsky.feed.post/own", root)),
+            ),
+        ] {
+            let uri = format!("at://did:nl/app.bsky.feed.post/{cid}");
+            database
+                .insert_post(&NewPost {
+                    reply_parent_uri: reply.map(|(parent, _)| parent),
+                    reply_root_uri: reply.map(|(_, root)| root),
+                    ..new_post("did:nl", &uri, minutes_ago)
+                })
+                .await
+                .unwrap();
+        }
+
+        for (replies, expected) in [
+            (
+                ReplyPolicy::Show,
+                &["latest", "outsider", "own", "root"][..],
+            ),
+            (ReplyPolicy::Hide, &["root"]),
+            (ReplyPolicy::OnlyToFeedAuthors, &["latest", "own", "root"]),
+            (ReplyPolicy::CollapseThreads, &["latest", "outsider"]),
+        ] {
+            let feed = NederlandskieFeed::new(PostFilters {
+                replies,
+                ..Default::default()
+            });
+
+            let page = feed.fetch_page(&database, 10, None).await.unwrap();
+            let cids: Vec<_> = page
+                .items
+                .iter()
+                .map(|item| item.post_uri.rsplit('/').next().unwrap())
+                .collect();
+            assert_eq!(cids, expected, "{replies:?}");
+        }
     }
 }
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{TimeDelta, Utc};
+
+use super::cursor::{make_ranked_cursor, parse_ranked_cursor};
+use super::{Feed, FeedItem, FeedPage};
+
+use nederlandskie_core::services::database::{Database, PostFilters};
+
+/// A feed that serves the most liked recent posts written in Russian by
+/// people living in Netherlands, with likes counting less as posts get older
+pub struct NederlandskieTopFeed {
+    filters: PostFilters,
+}
+
+impl Default for NederlandskieTopFeed {
+    fn default() -> Self {
+        Self::new(PostFilters::default())
+    }
+}
+
+impl NederlandskieTopFeed {
+    /// Posts older than this are not ranked at all
+    const MAX_POST_AGE: TimeDelta = TimeDelta::days(3);
+
+    pub fn new(filters: PostFilters) -> Self {
+        Self { filters }
+    }
+}
+
+#[async_trait]
+impl Feed for NederlandskieTopFeed {
+    async fn fetch_page(
+        &self,
+        database: &Database,
+        limit: u8,
+        cursor: Option<&str>,
+    ) -> Result<FeedPage> {
+        let (as_of, offset) = match cursor {
+            Some(cursor) => parse_ranked_cursor(cursor)?,
+            None => (Utc::now(), 0),
+        };
+
+        let posts = database
+            .fetch_top_posts_by_authors_country(
+                "nl",
+                limit as usize,
+                offset,
+                as_of,
+                Self::MAX_POST_AGE,
+                &self.filters,
+            )
+            .await?;
+
+        let cursor = (!posts.is_empty()).then(|| make_ranked_cursor(&as_of, offset + posts.len()));
+
+        Ok(FeedPage {
+            items: posts.into_iter().map(FeedItem::from).collect(),
+            cursor,
+        })
+    }
+}
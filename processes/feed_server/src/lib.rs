@@ -1,3 +1,4 @@
+mod admin;
 mod endpoints;
 mod errors;
 pub mod feeds;
@@ -20,7 +20,22 @@ async fn main() -> Result<()> {
 
     info!("Connecting to the database");
 
-    let database = Arc::new(Database::connect(&config.database_url).await?);
+    let mut database =
+        Database::connect_with_pool(&config.database_url, &config.database_pool).await?;
+
+    if let Some(read_url) = &config.database_read_url {
+        info!("Sending feed queries to the read replica");
+        database = database.with_replica_url(read_url, &config.database_pool)?;
+    }
+
+    let database = Arc::new(database);
+
+    info!("Bringing the database schema up to date");
+    database.migrate().await?;
+
+    if config.metrics_enabled {
+        tokio::spawn(database.clone().report_pool_metrics());
+    }
 
     info!("Initializing feeds");
 
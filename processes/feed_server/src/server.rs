@@ -9,6 +9,7 @@ use log::info;
 use nederlandskie_core::config::Config;
 use nederlandskie_core::services::Database;
 
+use super::admin;
 use super::endpoints::{describe_feed_generator, did_json, get_feed_skeleton, root};
 use super::feeds::Feeds;
 use super::state::FeedServerState;
@@ -39,12 +40,21 @@ impl FeedServer {
             .route(
                 "/xrpc/app.bsky.feed.getFeedSkeleton",
                 get(get_feed_skeleton),
-            )
-            .with_state(FeedServerState {
-                database: self.database,
-                config: self.config.clone(),
-                feeds: self.feeds,
-            });
+            );
+
+        if let Some(token) = &self.config.admin_token {
+            info!("Enabling admin API");
+            app = app.nest(
+                "/admin",
+                admin::router(token, self.config.admin_secure_cookie),
+            );
+        }
+
+        let mut app = app.with_state(FeedServerState {
+            database: self.database,
+            config: self.config.clone(),
+            feeds: self.feeds,
+        });
 
         if self.config.metrics_enabled {
             let (prometheus_layer, metrics_handle) = PrometheusMetricLayerBuilder::new()
@@ -0,0 +1,301 @@
+use std::iter;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info};
+
+use nederlandskie_core::services::Database;
+use nederlandskie_core::services::database::{RetentionConfig, RetentionPolicy, RetentionScope};
+
+pub struct Janitor {
+    database: Arc<Database>,
+    retention: RetentionConfig,
+}
+
+impl Janitor {
+    /// How long posts can wait for their authors to be classified
+    const MAX_PENDING_POST_AGE: TimeDelta = TimeDelta::days(7);
+    const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new(database: Arc<Database>, retention: RetentionConfig) -> Self {
+        Self {
+            database,
+            retention,
+        }
+    }
+
+    pub async fn start(self) -> Result<()> {
+        if self.retention.dry_run {
+            info!("Running dry, only reporting what would be deleted");
+        }
+
+        loop {
+            if let Err(e) = self.clean_up(Utc::now()).await {
+                error!("Problem with cleaning up: {:?}", e);
+            }
+
+            info!("Waiting...");
+
+            tokio::time::sleep(Self::INTERVAL).await;
+        }
+    }
+
+    /// Deletes everything that is past retention as of `now`. A policy that
+    /// fails doesn't keep the rest from being applied.
+    pub async fn clean_up(&self, now: DateTime<Utc>) -> Result<()> {
+        let countries = self.retention.countries();
+
+        let policies = self
+            .retention
+            .by_country
+            .iter()
+            .map(|(country, policy)| (RetentionScope::Country(country), policy))
+            .chain(iter::once((
+                RetentionScope::OtherCountries(&countries),
+                &self.retention.default,
+            )));
+
+        let mut failed = Vec::new();
+
+        for (scope, policy) in policies {
+            let country = country_of(scope);
+
+            if let Err(e) = self.apply_policy(scope, policy, now).await {
+                metrics::counter!("janitor_failures_total", "country" => country.to_owned())
+                    .increment(1);
+                error!(
+                    "Problem with cleaning up posts by authors from {country}: {:?}",
+                    e
+                );
+                failed.push(format!("posts by authors from {country}"));
+            }
+        }
+
+        if let Err(e) = self.clean_up_pending_posts(now).await {
+            metrics::counter!("janitor_failures_total", "country" => "pending").increment(1);
+            error!("Problem with cleaning up pending posts: {:?}", e);
+            failed.push("pending posts".to_owned());
+        }
+
+        if !failed.is_empty() {
+            bail!("Could not clean up {}", failed.join(", "));
+        }
+
+        Ok(())
+    }
+
+    /// Drops posts that have been waiting too long for their authors to be
+    /// classified
+    async fn clean_up_pending_posts(&self, now: DateTime<Utc>) -> Result<()> {
+        let earlier_than = now - Self::MAX_PENDING_POST_AGE;
+
+        if self.retention.dry_run {
+            let expired = self.database.count_old_pending_posts(&earlier_than).await?;
+
+            info!("Would delete {expired} pending posts");
+
+            return Ok(());
+        }
+
+        let deleted_pending_posts = self
+            .database
+            .delete_old_pending_posts(&earlier_than)
+            .await?;
+
+        if deleted_pending_posts > 0 {
+            metrics::counter!("pending_posts_janitor_deleted_total")
+                .increment(deleted_pending_posts);
+            info!("Deleted {} pending posts", deleted_pending_posts);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes posts the policy says should go, a batch at a time so that
+    /// the tables don't stay locked for long
+    async fn apply_policy(
+        &self,
+        scope: RetentionScope<'_>,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let country = country_of(scope);
+
+        if self.retention.dry_run {
+            let expired = self
+                .database
+                .count_posts_past_retention(scope, policy, now)
+                .await?;
+
+            info!("Would delete {expired} posts by authors from {country}");
+
+            return Ok(());
+        }
+
+        let mut deleted_posts = 0;
+
+        loop {
+            let deleted = self
+                .database
+                .delete_posts_past_retention(scope, policy, now, self.retention.batch_size)
+                .await?;
+
+            metrics::counter!("posts_janitor_deleted_total", "country" => country.to_owned())
+                .increment(deleted);
+            deleted_posts += deleted;
+
+            if deleted < self.retention.batch_size as u64 {
+                break;
+            }
+        }
+
+        if deleted_posts > 0 {
+            info!("Deleted {deleted_posts} posts by authors from {country}");
+        } else {
+            info!("No posts by authors from {country} to delete");
+        }
+
+        Ok(())
+    }
+}
+
+/// Country of the authors whose posts are in the scope, or `*` for all
+/// other countries
+fn country_of<'a>(scope: RetentionScope<'a>) -> &'a str {
+    match scope {
+        RetentionScope::Country(country) => country,
+        RetentionScope::OtherCountries(_) => "*",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nederlandskie_core::services::database::PostFilters;
+    use nederlandskie_core::services::database::tests::new_post;
+
+    use super::*;
+
+    async fn database_with_posts() -> Arc<Database> {
+        let database = Arc::new(Database::in_memory());
+
+        for (did, country) in [("did:nl", "nl"), ("did:de", "de")] {
+            database.force_profile_country(did, country).await.unwrap();
+        }
+
+        for (author_did, cid, days_ago) in [
+            ("did:nl", "a", 10),
+            ("did:nl", "b", 1),
+            ("did:de", "c", 10),
+            ("did:de", "d", 9),
+            ("did:de", "e", 1),
+        ] {
+            let uri = format!("at://{author_did}/app.bsky.feed.post/{cid}");
+            database
+                .insert_post(&new_post(author_did, &uri, days_ago * 24 * 60))
+                .await
+                .unwrap();
+        }
+
+        database
+    }
+
+    async fn cids_by_authors_from(database: &Database, country: &str) -> Vec<String> {
+        database
+            .fetch_posts_by_authors_country(country, 10, None, &PostFilters::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.cid)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn deletes_posts_past_retention_by_country() {
+        let database = database_with_posts().await;
+
+        let mut retention = RetentionConfig {
+            batch_size: 1,
+            dry_run: true,
+            ..Default::default()
+        };
+        retention
+            .parse_policies(
+                "nl:max_age_days=30,age_of=created_at;*:max_age_days=5,age_of=created_at",
+            )
+            .unwrap();
+
+        Janitor::new(database.clone(), retention.clone())
+            .clean_up(Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(database.count_posts().await.unwrap(), 5);
+
+        retention.dry_run = false;
+        Janitor::new(database.clone(), retention)
+            .clean_up(Utc::now())
+            .await
+            .unwrap();
+
+        assert_eq!(cids_by_authors_from(&database, "nl").await, ["b", "a"]);
+        assert_eq!(cids_by_authors_from(&database, "de").await, ["e"]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_posts_waiting_too_long_for_their_authors() {
+        let database = Arc::new(Database::in_memory());
+        let janitor = Janitor::new(database.clone(), RetentionConfig::default());
+
+        for (author_did, cid) in [("did:soon", "a"), ("did:late", "b")] {
+            let uri = format!("at://{author_did}/app.bsky.feed.post/{cid}");
+            database
+                .insert_profile_if_it_doesnt_exist(author_did)
+                .await
+                .unwrap();
+            database
+                .insert_pending_post(&new_post(author_did, &uri, 0), &[], "nl")
+                .await
+                .unwrap();
+        }
+
+        let dry_janitor = Janitor::new(
+            database.clone(),
+            RetentionConfig {
+                dry_run: true,
+                ..Default::default()
+            },
+        );
+        dry_janitor
+            .clean_up(Utc::now() + TimeDelta::days(8))
+            .await
+            .unwrap();
+        assert_eq!(
+            database
+                .count_old_pending_posts(&(Utc::now() + TimeDelta::days(1)))
+                .await
+                .unwrap(),
+            2
+        );
+
+        janitor
+            .clean_up(Utc::now() + TimeDelta::days(6))
+            .await
+            .unwrap();
+        database
+            .store_profile_details("did:soon", "nl", None)
+            .await
+            .unwrap();
+        assert_eq!(database.count_posts().await.unwrap(), 1);
+
+        janitor
+            .clean_up(Utc::now() + TimeDelta::days(8))
+            .await
+            .unwrap();
+        database
+            .store_profile_details("did:late", "nl", None)
+            .await
+            .unwrap();
+        assert_eq!(database.count_posts().await.unwrap(), 1);
+    }
+}
@@ -1,13 +1,15 @@
-use std::time::Duration;
+extern crate nederlandskie_janitor;
+
+use std::sync::Arc;
 
 use anyhow::Result;
-use chrono::{DateTime, TimeDelta, Utc};
 use env_logger::Env;
 use log::info;
 use metrics_exporter_prometheus::PrometheusBuilder;
 
 use nederlandskie_core::config::Config;
 use nederlandskie_core::services::Database;
+use nederlandskie_janitor::Janitor;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,23 +28,19 @@ async fn main() -> Result<()> {
 
     info!("Connecting to the database");
 
-    let database = Database::connect(&config.database_url).await?;
+    let database =
+        Arc::new(Database::connect_with_pool(&config.database_url, &config.database_pool).await?);
 
-    loop {
-        let now: DateTime<Utc> = Utc::now();
-        let earlier_than = now - TimeDelta::days(150);
+    info!("Bringing the database schema up to date");
+    database.migrate().await?;
 
-        info!("Deleting posts older than {}", earlier_than);
+    if config.metrics_enabled {
+        tokio::spawn(database.clone().report_pool_metrics());
+    }
 
-        let deleted_posts = database.delete_old_posts(&earlier_than).await?;
+    let janitor = Janitor::new(database, config.retention);
 
-        if deleted_posts > 0 {
-            metrics::counter!("posts_janitor_deleted_total").increment(deleted_posts as u64);
-            info!("Deleted {}", deleted_posts);
-        } else {
-            info!("No posts to delete, waiting...");
-        }
+    info!("Starting Janitor");
 
-        tokio::time::sleep(Duration::from_secs(60 * 60)).await;
-    }
+    janitor.start().await
 }
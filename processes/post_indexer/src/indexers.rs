@@ -12,10 +12,27 @@ use nederlandskie_core::services::database::Database;
 
 pub use self::nederlandskie::NederlandskieIndexer;
 
+/// What an indexer wants done with a post
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Index,
+    /// Hold on to the post until its author has been classified, and index it
+    /// if they turn out to live in the given country
+    HoldUntilClassified {
+        country: String,
+    },
+    Skip,
+}
+
 #[async_trait]
 pub trait Indexer {
-    async fn should_index_post(&self, author_did: &str, post: &bluesky::PostRecord)
-        -> Result<bool>;
+    async fn judge_post(&self, author_did: &str, post: &bluesky::PostRecord) -> Result<Verdict>;
+
+    /// Country whose residents the indexer is after, if any. Reposts made by
+    /// them get stored too.
+    fn country(&self) -> Option<&str> {
+        None
+    }
 }
 
 pub fn initialize_all_indexers(
@@ -49,6 +66,20 @@ impl Indexers {
     pub fn get_by_name(&self, name: &str) -> Option<&AnyIndexer> {
         self.indexers.get(name)
     }
+
+    pub fn countries(&self) -> Vec<String> {
+        let mut countries: Vec<_> = self
+            .indexers
+            .values()
+            .filter_map(|i| i.country())
+            .map(str::to_owned)
+            .collect();
+
+        countries.sort();
+        countries.dedup();
+
+        countries
+    }
 }
 
 #[derive(Default)]
@@ -5,10 +5,12 @@ use async_trait::async_trait;
 use lingua::Language::Russian;
 use lingua::LanguageDetector;
 
-use super::Indexer;
+use super::{Indexer, Verdict};
 
 use nederlandskie_core::services::bluesky;
-use nederlandskie_core::services::database::Database;
+use nederlandskie_core::services::database::{Database, Residency};
+
+const COUNTRY: &str = "nl";
 
 /// An indexer that indexes posts that are either in Russian, or made by profiles residing in Netherlands
 pub struct NederlandskieIndexer {
@@ -30,19 +32,28 @@ impl NederlandskieIndexer {
         self.language_detector.detect_language_of(&post.text) == Some(Russian)
     }
 
-    async fn is_profile_residing_in_netherlands(&self, did: &str) -> Result<bool> {
-        Ok(self.database.is_profile_in_this_country(did, "nl").await? == Some(true))
+    async fn residency_in_netherlands(&self, did: &str) -> Result<Option<Residency>> {
+        self.database.fetch_profile_residency(did, COUNTRY).await
     }
 }
 
 #[async_trait]
 impl Indexer for NederlandskieIndexer {
-    async fn should_index_post(
-        &self,
-        author_did: &str,
-        post: &bluesky::PostRecord,
-    ) -> Result<bool> {
-        Ok(self.is_post_in_russian(post)
-            || self.is_profile_residing_in_netherlands(author_did).await?)
+    async fn judge_post(&self, author_did: &str, post: &bluesky::PostRecord) -> Result<Verdict> {
+        if self.is_post_in_russian(post) {
+            return Ok(Verdict::Index);
+        }
+
+        Ok(match self.residency_in_netherlands(author_did).await? {
+            Some(Residency::InCountry) => Verdict::Index,
+            Some(Residency::Unclassified) => Verdict::HoldUntilClassified {
+                country: COUNTRY.to_owned(),
+            },
+            Some(Residency::Elsewhere) | None => Verdict::Skip,
+        })
+    }
+
+    fn country(&self) -> Option<&str> {
+        Some(COUNTRY)
     }
 }
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{debug, error, info};
+
+use nederlandskie_core::services::bluesky::{Bluesky, LabelProcessor, LabelsDetails};
+use nederlandskie_core::services::Database;
+
+use crate::metrics;
+
+/// Keeps labels that a labeler puts on indexed posts in sync
+pub struct LabelIndexer {
+    database: Arc<Database>,
+    bluesky: Bluesky,
+    host: String,
+    /// DID the subscription state is kept under, same as for the firehose
+    feed_generator_did: String,
+}
+
+impl LabelIndexer {
+    pub fn new(
+        database: Arc<Database>,
+        bluesky: Bluesky,
+        host: String,
+        feed_generator_did: String,
+    ) -> Self {
+        Self {
+            database,
+            bluesky,
+            host,
+            feed_generator_did,
+        }
+    }
+}
+
+impl LabelIndexer {
+    pub async fn start(self) -> Result<()> {
+        info!("Starting to index labels from {}", self.host);
+
+        loop {
+            if let Err(e) = self.process_from_last_point().await {
+                error!("Stopped indexing labels because of an error: {}", e);
+            }
+
+            info!("Waiting 10 seconds before reconnecting to the labeler...");
+
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    }
+
+    async fn process_from_last_point(&self) -> Result<()> {
+        let cursor = self
+            .database
+            .fetch_subscription_cursor(&self.host, &self.feed_generator_did)
+            .await?;
+
+        if cursor.is_none() {
+            self.database
+                .create_subscription_state(&self.host, &self.feed_generator_did)
+                .await?;
+        }
+
+        info!("Subscribing to labels with cursor {:?}", cursor);
+
+        self.bluesky
+            .subscribe_to_labels(&self.host, self, cursor)
+            .await
+    }
+}
+
+#[async_trait]
+impl LabelProcessor for LabelIndexer {
+    async fn process_labels(&self, details: &LabelsDetails) -> Result<()> {
+        for label in &details.labels {
+            if label.neg == Some(true) {
+                if self
+                    .database
+                    .delete_label(&label.uri, label.src.as_str(), &label.val)
+                    .await?
+                {
+                    metrics::labels_deleted();
+                }
+            } else if self
+                .database
+                .insert_label_if_relevant(
+                    &label.uri,
+                    label.src.as_str(),
+                    &label.val,
+                    label.cts.as_ref().with_timezone(&Utc),
+                    label.exp.as_ref().map(|e| e.as_ref().with_timezone(&Utc)),
+                )
+                .await?
+            {
+                info!("Received label {} for {}", label.val, label.uri);
+                metrics::labels_indexed();
+            }
+        }
+
+        debug!(
+            "Updating labels cursor for {} to {}",
+            self.host, details.seq
+        );
+
+        self.database
+            .update_subscription_cursor(&self.host, &self.feed_generator_did, details.seq)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 pub mod indexers;
+pub mod labels;
 pub mod metrics;
+pub mod posts;
+pub mod relevance;
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -9,8 +12,11 @@ use async_trait::async_trait;
 use log::{debug, error, info};
 
 use indexers::Indexers;
+use posts::{PostHandler, PostOutcome};
+use relevance::{repository_did, RelevantProfiles};
 
 use chrono::Utc;
+use lingua::LanguageDetector;
 
 use nederlandskie_core::config::Config;
 use nederlandskie_core::services::bluesky::{Bluesky, CommitDetails, CommitProcessor, Operation};
@@ -19,7 +25,8 @@ use nederlandskie_core::services::Database;
 pub struct PostIndexer {
     database: Arc<Database>,
     bluesky: Bluesky,
-    indexers: Indexers,
+    posts: PostHandler,
+    relevant: Arc<RelevantProfiles>,
     config: Config,
 }
 
@@ -28,12 +35,17 @@ impl PostIndexer {
         database: Arc<Database>,
         bluesky: Bluesky,
         indexers: Indexers,
+        language_detector: Arc<LanguageDetector>,
         config: Config,
     ) -> Self {
         Self {
+            relevant: Arc::new(RelevantProfiles::new(
+                database.clone(),
+                indexers.countries(),
+            )),
+            posts: PostHandler::new(database.clone(), indexers, language_detector),
             database,
             bluesky,
-            indexers,
             config,
         }
     }
@@ -43,6 +55,12 @@ impl PostIndexer {
     pub async fn start(self) -> Result<()> {
         info!("Starting");
 
+        if let Err(e) = self.relevant.reload().await {
+            error!("Could not load relevant profiles: {:?}", e);
+        }
+
+        tokio::spawn(self.relevant.clone().keep_reloaded());
+
         loop {
             if let Err(e) = self.process_from_last_point().await {
                 error!("Stopped because of an error: {}", e);
@@ -87,23 +105,10 @@ impl CommitProcessor for PostIndexer {
                 } => {
                     metrics::messages_of_interest();
 
-                    for indexer in self.indexers.iter_all() {
-                        if indexer.should_index_post(author_did, post).await? {
-                            info!("Received insertable post from {author_did}: {post:?}",);
-
-                            self.database
-                                .insert_profile_if_it_doesnt_exist(author_did)
-                                .await?;
-
-                            let created_at = post.created_at.as_ref().with_timezone(&Utc);
-                            self.database
-                                .insert_post(author_did, cid, uri, created_at)
-                                .await?;
-
-                            metrics::posts_indexed();
-
-                            break;
-                        }
+                    if self.posts.handle_post(author_did, cid, uri, post).await?
+                        == PostOutcome::Indexed
+                    {
+                        self.relevant.add_post_author(author_did);
                     }
                 }
                 Operation::DeletePost { uri } => {
@@ -115,7 +120,120 @@ impl CommitProcessor for PostIndexer {
                         metrics::posts_deleted();
                     }
                 }
-                _ => continue,
+                Operation::CreateLike {
+                    author_did,
+                    uri,
+                    like,
+                    ..
+                } => {
+                    // Likes are only stored for indexed posts, so most of
+                    // them can be told apart by who made the post
+                    if !repository_did(&like.subject.uri)
+                        .is_some_and(|did| self.relevant.is_post_author(did))
+                    {
+                        continue;
+                    }
+
+                    let created_at = like.created_at.as_ref().with_timezone(&Utc);
+
+                    if self
+                        .database
+                        .insert_like_if_relevant(author_did, &like.subject.uri, uri, created_at)
+                        .await?
+                    {
+                        metrics::messages_of_interest();
+                        metrics::likes_indexed();
+
+                        self.relevant.add_liker(author_did);
+                    }
+                }
+                Operation::DeleteLike { uri } => {
+                    if !repository_did(uri).is_some_and(|did| self.relevant.is_liker(did)) {
+                        continue;
+                    }
+
+                    if self.database.delete_like(uri).await? {
+                        metrics::messages_of_interest();
+                        metrics::likes_deleted();
+                    }
+                }
+                Operation::CreateRepost {
+                    author_did,
+                    cid,
+                    uri,
+                    repost,
+                } => {
+                    // Reposts are only stored if made by residents of the
+                    // countries the indexers are after, for feeds to serve
+                    let Some(country) = self.relevant.residence_of(author_did) else {
+                        continue;
+                    };
+
+                    let created_at = repost.created_at.as_ref().with_timezone(&Utc);
+
+                    if self
+                        .database
+                        .insert_repost_if_relevant(
+                            author_did,
+                            cid,
+                            uri,
+                            &repost.subject.uri,
+                            created_at,
+                            &country,
+                        )
+                        .await?
+                    {
+                        metrics::messages_of_interest();
+                        metrics::reposts_indexed();
+                    }
+                }
+                Operation::DeleteRepost { uri } => {
+                    if !repository_did(uri).is_some_and(|did| self.relevant.is_resident(did)) {
+                        continue;
+                    }
+
+                    if self.database.delete_repost(uri).await? {
+                        metrics::messages_of_interest();
+                        metrics::reposts_deleted();
+                    }
+                }
+                Operation::CreateFollow {
+                    author_did,
+                    uri,
+                    follow,
+                    ..
+                } => {
+                    // Nothing but the enriched classifier looks at the follow graph
+                    if self.config.classifier_enriched_country.is_none() {
+                        continue;
+                    }
+
+                    let created_at = follow.created_at.as_ref().with_timezone(&Utc);
+
+                    if self
+                        .database
+                        .insert_follow_if_relevant(
+                            author_did,
+                            follow.subject.as_str(),
+                            uri,
+                            created_at,
+                        )
+                        .await?
+                    {
+                        metrics::messages_of_interest();
+                        metrics::follows_indexed();
+                    }
+                }
+                Operation::DeleteFollow { uri } => {
+                    if self.config.classifier_enriched_country.is_none() {
+                        continue;
+                    }
+
+                    if self.database.delete_follow(uri).await? {
+                        metrics::messages_of_interest();
+                        metrics::follows_deleted();
+                    }
+                }
             }
         }
 
@@ -11,7 +11,9 @@ use metrics_exporter_prometheus::PrometheusBuilder;
 use nederlandskie_core::config::Config;
 use nederlandskie_core::services::{Bluesky, Database};
 
-use nederlandskie_post_indexer::{indexers::initialize_all_indexers, PostIndexer};
+use nederlandskie_post_indexer::{
+    indexers::initialize_all_indexers, labels::LabelIndexer, PostIndexer,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -31,7 +33,15 @@ async fn main() -> Result<()> {
     info!("Initializing service clients");
 
     let bluesky = Bluesky::unauthenticated();
-    let database = Arc::new(Database::connect(&config.database_url).await?);
+    let database =
+        Arc::new(Database::connect_with_pool(&config.database_url, &config.database_pool).await?);
+
+    info!("Bringing the database schema up to date");
+    database.migrate().await?;
+
+    if config.metrics_enabled {
+        tokio::spawn(database.clone().report_pool_metrics());
+    }
 
     info!("Initializing language detector");
 
@@ -41,9 +51,28 @@ async fn main() -> Result<()> {
             .build(),
     );
 
+    if let Some(host) = config.labeler_host.clone() {
+        let label_indexer = LabelIndexer::new(
+            database.clone(),
+            Bluesky::unauthenticated(),
+            host,
+            config.feed_generator_did.to_string(),
+        );
+
+        info!("Starting Label Indexer");
+
+        tokio::spawn(label_indexer.start());
+    }
+
     let indexers = initialize_all_indexers(language_detector.clone(), database.clone());
 
-    let post_indexer = PostIndexer::new(database.clone(), bluesky, indexers, config);
+    let post_indexer = PostIndexer::new(
+        database.clone(),
+        bluesky,
+        indexers,
+        language_detector.clone(),
+        config,
+    );
 
     info!("Starting Post Indexer");
 
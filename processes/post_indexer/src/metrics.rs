@@ -10,6 +10,46 @@ pub fn posts_indexed() {
     metrics::counter!("posts_indexed_total").increment(1);
 }
 
+pub fn posts_held() {
+    metrics::counter!("posts_held_total").increment(1);
+}
+
+pub fn banned_posts_skipped() {
+    metrics::counter!("banned_posts_skipped_total").increment(1);
+}
+
 pub fn posts_deleted() {
     metrics::counter!("posts_deleted_total").increment(1);
 }
+
+pub fn likes_indexed() {
+    metrics::counter!("likes_indexed_total").increment(1);
+}
+
+pub fn likes_deleted() {
+    metrics::counter!("likes_deleted_total").increment(1);
+}
+
+pub fn reposts_indexed() {
+    metrics::counter!("reposts_indexed_total").increment(1);
+}
+
+pub fn reposts_deleted() {
+    metrics::counter!("reposts_deleted_total").increment(1);
+}
+
+pub fn follows_indexed() {
+    metrics::counter!("follows_indexed_total").increment(1);
+}
+
+pub fn follows_deleted() {
+    metrics::counter!("follows_deleted_total").increment(1);
+}
+
+pub fn labels_indexed() {
+    metrics::counter!("labels_indexed_total").increment(1);
+}
+
+pub fn labels_deleted() {
+    metrics::counter!("labels_deleted_total").increment(1);
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::Utc;
+use lingua::LanguageDetector;
+use log::{debug, info};
+
+use nederlandskie_core::services::bluesky::{self_labels_of, PostEmbed, PostRecord};
+use nederlandskie_core::services::database::{NewPost, Residency};
+use nederlandskie_core::services::Database;
+
+use crate::indexers::{Indexers, Verdict};
+use crate::metrics;
+
+/// What became of a post after running it through the indexers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOutcome {
+    Indexed,
+    AlreadyIndexed,
+    /// Waiting for its author to be classified
+    Held,
+    Banned,
+    Skipped,
+}
+
+/// Runs new posts through the indexers and stores the ones they want, both
+/// for posts coming from the firehose and for ones backfilled from
+/// repositories
+pub struct PostHandler {
+    database: Arc<Database>,
+    indexers: Indexers,
+    language_detector: Arc<LanguageDetector>,
+}
+
+impl PostHandler {
+    pub fn new(
+        database: Arc<Database>,
+        indexers: Indexers,
+        language_detector: Arc<LanguageDetector>,
+    ) -> Self {
+        Self {
+            database,
+            indexers,
+            language_detector,
+        }
+    }
+
+    pub async fn handle_post(
+        &self,
+        author_did: &str,
+        cid: &str,
+        uri: &str,
+        post: &PostRecord,
+    ) -> Result<PostOutcome> {
+        loop {
+            let mut awaited_country = None;
+
+            for indexer in self.indexers.iter_all() {
+                match indexer.judge_post(author_did, post).await? {
+                    Verdict::Index => return self.index_post(author_did, cid, uri, post).await,
+                    Verdict::HoldUntilClassified { country } => {
+                        awaited_country.get_or_insert(country);
+                    }
+                    Verdict::Skip => {}
+                }
+            }
+
+            let Some(country) = awaited_country else {
+                return Ok(PostOutcome::Skipped);
+            };
+
+            // The author could have been classified since the indexers
+            // looked, in which case the post is judged again
+            if let Some(outcome) = self.hold_post(author_did, cid, uri, post, &country).await? {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    async fn index_post(
+        &self,
+        author_did: &str,
+        cid: &str,
+        uri: &str,
+        post: &PostRecord,
+    ) -> Result<PostOutcome> {
+        if self.database.is_post_banned(author_did, uri).await? {
+            info!("Skipping banned post from {author_did}: {uri}");
+            metrics::banned_posts_skipped();
+            return Ok(PostOutcome::Banned);
+        }
+
+        info!("Received insertable post from {author_did}: {post:?}",);
+
+        self.database
+            .insert_profile_if_it_doesnt_exist(author_did)
+            .await?;
+
+        let language = self.detect_language(post);
+        let new_post = new_post(author_did, cid, uri, post, language.as_deref());
+
+        if !self.database.insert_post(&new_post).await? {
+            return Ok(PostOutcome::AlreadyIndexed);
+        }
+
+        for label in self_labels_of(post) {
+            self.database
+                .insert_label_if_relevant(uri, author_did, label, new_post.created_at, None)
+                .await?;
+        }
+
+        metrics::posts_indexed();
+
+        Ok(PostOutcome::Indexed)
+    }
+
+    /// Keeps a post by a profile that is yet to be classified, so that it can
+    /// be indexed after all once the classifier gets to the profile. Returns
+    /// nothing if the profile has been classified in the meantime.
+    async fn hold_post(
+        &self,
+        author_did: &str,
+        cid: &str,
+        uri: &str,
+        post: &PostRecord,
+        awaited_country: &str,
+    ) -> Result<Option<PostOutcome>> {
+        if self.database.is_post_banned(author_did, uri).await? {
+            info!("Skipping banned post from {author_did}: {uri}");
+            metrics::banned_posts_skipped();
+            return Ok(Some(PostOutcome::Banned));
+        }
+
+        debug!("Holding post from unclassified {author_did}: {uri}");
+
+        let language = self.detect_language(post);
+
+        if self
+            .database
+            .insert_pending_post(
+                &new_post(author_did, cid, uri, post, language.as_deref()),
+                &self_labels_of(post),
+                awaited_country,
+            )
+            .await?
+        {
+            metrics::posts_held();
+            return Ok(Some(PostOutcome::Held));
+        }
+
+        let residency = self
+            .database
+            .fetch_profile_residency(author_did, awaited_country)
+            .await?;
+
+        Ok((residency == Some(Residency::Unclassified)).then_some(PostOutcome::Held))
+    }
+
+    fn detect_language(&self, post: &PostRecord) -> Option<String> {
+        self.language_detector
+            .detect_language_of(&post.text)
+            .map(|l| l.iso_code_639_1().to_string())
+    }
+}
+
+fn new_post<'a>(
+    author_did: &'a str,
+    cid: &'a str,
+    uri: &'a str,
+    post: &'a PostRecord,
+    language: Option<&'a str>,
+) -> NewPost<'a> {
+    let embed = PostEmbed::of(post);
+
+    NewPost {
+        author_did,
+        cid,
+        uri,
+        created_at: post.created_at.as_ref().with_timezone(&Utc),
+        text: &post.text,
+        language,
+        reply_parent_uri: post.reply.as_ref().map(|r| r.parent.uri.as_str()),
+        reply_root_uri: post.reply.as_ref().map(|r| r.root.uri.as_str()),
+        embed_kind: embed.as_ref().map(|e| e.kind),
+        quoted_uri: embed.as_ref().and_then(|e| e.quoted_uri),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lingua::LanguageDetectorBuilder;
+    use serde_json::json;
+
+    use nederlandskie_core::services::database::PostFilters;
+
+    use super::*;
+    use crate::indexers::initialize_all_indexers;
+
+    fn post(text: &str) -> PostRecord {
+        serde_json::from_value(json!({
+            "$type": "app.bsky.feed.post",
+            "text": text,
+            "createdAt": Utc::now().to_rfc3339(),
+        }))
+        .unwrap()
+    }
+
+    async fn handle_post(
+        posts: &PostHandler,
+        author_did: &str,
+        cid: &str,
+        text: &str,
+    ) -> PostOutcome {
+        let uri = format!("at://{author_did}/app.bsky.feed.post/{cid}");
+
+        posts
+            .handle_post(author_did, cid, &uri, &post(text))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn indexes_holds_or_skips_posts_by_residency_of_authors() {
+        let database = Arc::new(Database::in_memory());
+        let language_detector =
+            Arc::new(LanguageDetectorBuilder::from_all_languages_with_cyrillic_script().build());
+        let posts = PostHandler::new(
+            database.clone(),
+            initialize_all_indexers(language_detector.clone(), database.clone()),
+            language_detector,
+        );
+
+        database
+            .force_profile_country("did:nl", "nl")
+            .await
+            .unwrap();
+        database
+            .force_profile_country("did:de", "de")
+            .await
+            .unwrap();
+        database
+            .insert_profile_if_it_doesnt_exist("did:new")
+            .await
+            .unwrap();
+
+        let russian = "Всем привет, как дела у вас сегодня?";
+        let dutch = "Hallo allemaal, hoe gaat het vandaag met jullie?";
+
+        assert_eq!(
+            handle_post(&posts, "did:unknown", "a", russian).await,
+            PostOutcome::Indexed
+        );
+        assert_eq!(
+            handle_post(&posts, "did:unknown", "a", russian).await,
+            PostOutcome::AlreadyIndexed
+        );
+        assert_eq!(
+            handle_post(&posts, "did:nl", "b", dutch).await,
+            PostOutcome::Indexed
+        );
+        assert_eq!(
+            handle_post(&posts, "did:new", "c", dutch).await,
+            PostOutcome::Held
+        );
+        assert_eq!(
+            handle_post(&posts, "did:de", "d", dutch).await,
+            PostOutcome::Skipped
+        );
+        assert_eq!(
+            handle_post(&posts, "did:stranger", "e", dutch).await,
+            PostOutcome::Skipped
+        );
+
+        assert_eq!(database.count_posts().await.unwrap(), 2);
+
+        // Authors of Russian posts get queued up for classification
+        assert!(database
+            .fetch_profile_details("did:unknown")
+            .await
+            .unwrap()
+            .is_some());
+
+        database
+            .store_profile_details("did:new", "nl", None)
+            .await
+            .unwrap();
+
+        let cids = database
+            .fetch_posts_by_authors_country("nl", 10, None, &PostFilters::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.cid)
+            .collect::<Vec<_>>();
+        assert_eq!(cids, ["c", "b"]);
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, error};
+
+use nederlandskie_core::services::Database;
+
+/// Profiles that likes and reposts flowing through the firehose have to
+/// involve to be worth storing, kept in memory so that the likes and reposts
+/// of the whole network don't each cost a database query.
+///
+/// The sets are reloaded from the database in the background every so often
+/// to pick up changes made by other processes, such as posts indexed or
+/// profiles classified by the classifier. Likes and reposts involving those
+/// are missed until the next reload, and the sets from the last successful
+/// one keep being used while reloading fails.
+pub struct RelevantProfiles {
+    database: Arc<Database>,
+    /// Countries whose residents have their reposts stored
+    countries: Vec<String>,
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Authors of indexed posts, the only ones whose posts get likes stored
+    post_authors: HashSet<String>,
+    /// Profiles with likes stored, the only ones whose likes can be deleted
+    likers: HashSet<String>,
+    /// Countries that residents of `countries` live in, by their DIDs
+    residents: HashMap<String, String>,
+}
+
+impl RelevantProfiles {
+    const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(database: Arc<Database>, countries: Vec<String>) -> Self {
+        Self {
+            database,
+            countries,
+            state: Default::default(),
+        }
+    }
+
+    /// Keeps reloading the profiles for as long as the process runs
+    pub async fn keep_reloaded(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(Self::RELOAD_INTERVAL).await;
+
+            if let Err(e) = self.reload().await {
+                error!("Could not reload relevant profiles: {:?}", e);
+            }
+        }
+    }
+
+    pub async fn reload(&self) -> Result<()> {
+        let post_authors: HashSet<_> = self
+            .database
+            .fetch_post_author_dids()
+            .await?
+            .into_iter()
+            .collect();
+        let likers: HashSet<_> = self
+            .database
+            .fetch_liker_dids()
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut residents = HashMap::new();
+        for country in &self.countries {
+            for did in self.database.fetch_profile_dids_in_country(country).await? {
+                residents.insert(did, country.clone());
+            }
+        }
+
+        debug!(
+            "Reloaded {} post authors, {} likers and {} residents",
+            post_authors.len(),
+            likers.len(),
+            residents.len()
+        );
+
+        *self.state_mut() = State {
+            post_authors,
+            likers,
+            residents,
+        };
+
+        Ok(())
+    }
+
+    pub fn is_post_author(&self, did: &str) -> bool {
+        self.state().post_authors.contains(did)
+    }
+
+    pub fn add_post_author(&self, did: &str) {
+        self.state_mut().post_authors.insert(did.to_owned());
+    }
+
+    pub fn is_liker(&self, did: &str) -> bool {
+        self.state().likers.contains(did)
+    }
+
+    pub fn add_liker(&self, did: &str) {
+        self.state_mut().likers.insert(did.to_owned());
+    }
+
+    /// Returns the country the profile lives in, if it's one of those whose
+    /// residents have their reposts stored
+    pub fn residence_of(&self, did: &str) -> Option<String> {
+        self.state().residents.get(did).cloned()
+    }
+
+    pub fn is_resident(&self, did: &str) -> bool {
+        self.state().residents.contains_key(did)
+    }
+
+    fn state(&self) -> RwLockReadGuard<'_, State> {
+        self.state
+            .read()
+            .expect("relevant profiles lock is poisoned")
+    }
+
+    fn state_mut(&self) -> RwLockWriteGuard<'_, State> {
+        self.state
+            .write()
+            .expect("relevant profiles lock is poisoned")
+    }
+}
+
+/// Returns the DID of the repository that the record behind an `at://` URI
+/// lives in, which is the DID of its author
+pub fn repository_did(uri: &str) -> Option<&str> {
+    uri.strip_prefix("at://")?.split('/').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use nederlandskie_core::services::database::tests::new_post;
+
+    use super::*;
+
+    #[test]
+    fn extracts_repository_did_from_uri() {
+        assert_eq!(
+            repository_did("at://did:plc:abc/app.bsky.feed.post/1"),
+            Some("did:plc:abc")
+        );
+        assert_eq!(repository_did("did:plc:abc"), None);
+    }
+
+    #[tokio::test]
+    async fn loads_relevant_profiles() {
+        let database = Arc::new(Database::in_memory());
+
+        database
+            .insert_profile_if_it_doesnt_exist("did:author")
+            .await
+            .unwrap();
+        database
+            .insert_post(&new_post(
+                "did:author",
+                "at://did:author/app.bsky.feed.post/a",
+                0,
+            ))
+            .await
+            .unwrap();
+        database
+            .insert_like_if_relevant(
+                "did:liker",
+                "at://did:author/app.bsky.feed.post/a",
+                "at://did:liker/app.bsky.feed.like/1",
+                Utc::now(),
+            )
+            .await
+            .unwrap();
+
+        database
+            .force_profile_country("did:author", "nl")
+            .await
+            .unwrap();
+
+        let relevant = RelevantProfiles::new(database, vec!["nl".to_owned()]);
+        assert!(!relevant.is_post_author("did:author"));
+
+        relevant.reload().await.unwrap();
+        assert!(relevant.is_post_author("did:author"));
+        assert!(relevant.is_liker("did:liker"));
+        assert!(!relevant.is_post_author("did:liker"));
+        assert!(!relevant.is_liker("did:author"));
+        assert_eq!(relevant.residence_of("did:author").as_deref(), Some("nl"));
+        assert!(!relevant.is_resident("did:liker"));
+
+        relevant.add_post_author("did:new");
+        relevant.add_liker("did:new");
+        assert!(relevant.is_post_author("did:new"));
+        assert!(relevant.is_liker("did:new"));
+    }
+}
@@ -1,24 +1,74 @@
 pub mod metrics;
+mod rate_limiter;
+mod residency;
 
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
-use log::{error, info};
+use anyhow::{Context, Result, anyhow};
+use chrono::{TimeDelta, Utc};
+use futures::StreamExt;
+use log::{error, info, warn};
 
-use nederlandskie_core::services::{AI, Bluesky, Database};
+use nederlandskie_core::config::Config;
+use nederlandskie_core::services::bluesky::ProfileRecordData;
+use nederlandskie_core::services::database::ClassificationOrder;
+use nederlandskie_core::services::{
+    AI, BatchedProfile, Bluesky, Database, ProfileDescription, RateLimited, ResidencyContext,
+};
+
+use self::rate_limiter::RateLimiter;
+use self::residency::ResidencySignals;
 
 pub struct ProfileClassifier {
-    database: Database,
+    database: Arc<Database>,
     ai: AI,
     bluesky: Bluesky,
+    concurrency: usize,
+    batch_size: usize,
+    message_batch_threshold: Option<usize>,
+    max_attempts: i32,
+    order: ClassificationOrder,
+    enriched_country: Option<String>,
+    bluesky_rate_limiter: RateLimiter,
+    ai_rate_limiter: RateLimiter,
 }
 
 impl ProfileClassifier {
-    pub fn new(database: Database, ai: AI, bluesky: Bluesky) -> Self {
+    const MAX_RATE_LIMITED_ATTEMPTS: u32 = 5;
+    const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+    const MAX_MESSAGE_BATCH_SIZE: usize = 10_000;
+    const CLAIM_LEASE: TimeDelta = TimeDelta::minutes(30);
+    /// Message batches expire after a day, so ones that can't be collected
+    /// well after that never will be
+    const MESSAGE_BATCH_DEADLINE: TimeDelta = TimeDelta::days(2);
+    const RETRY_BACKOFF: TimeDelta = TimeDelta::minutes(1);
+    const RECENT_POSTS_SAMPLE_SIZE: usize = 10;
+    const FOLLOWS_SAMPLE_SIZE: usize = 300;
+
+    pub fn new(database: Arc<Database>, ai: AI, bluesky: Bluesky, config: &Config) -> Self {
+        let concurrency = config.classifier_concurrency.max(1);
+
         Self {
             database,
             ai,
             bluesky,
+            concurrency,
+            // The enriched classifier looks at one profile at a time
+            batch_size: match config.classifier_enriched_country {
+                Some(_) => 1,
+                None => config.classifier_batch_size.max(1),
+            },
+            message_batch_threshold: config.classifier_message_batch_threshold,
+            max_attempts: config.classifier_max_attempts,
+            order: config.classifier_order,
+            enriched_country: config.classifier_enriched_country.clone(),
+            bluesky_rate_limiter: RateLimiter::new(
+                config.bluesky_requests_per_second,
+                concurrency as u32,
+            ),
+            ai_rate_limiter: RateLimiter::new(config.anthropic_requests_per_second, 1),
         }
     }
 
@@ -36,56 +86,526 @@ impl ProfileClassifier {
     }
 
     async fn classify_unclassified_profiles(&self) -> Result<()> {
-        // TODO: Maybe streamify this so that each thing is processed in parallel
-
-        let dids = self.database.fetch_unprocessed_profile_dids().await?;
-
-        metrics::profiles_pending(dids.len());
-
-        if dids.is_empty() {
-            info!("No profiles to process");
-        } else {
-            info!("Classifying {} new profiles", dids.len());
-            for did in &dids {
-                match self.fill_in_profile_details(did).await {
-                    Ok(()) => {
-                        metrics::profiles_classified();
-                    }
-                    Err(e) => error!("Could not classify profile with did {}: {:?}", did, e),
+        if self.message_batch_threshold.is_some()
+            && let Err(e) = self.collect_finished_message_batches().await
+        {
+            metrics::profiles_classification_failed("collect_message_batches");
+            error!("Problem with collecting message batch results: {:?}", e);
+        }
+
+        loop {
+            let pending = self.database.count_unprocessed_profiles().await? as usize;
+
+            metrics::profiles_pending(pending);
+
+            if pending == 0 {
+                info!("No profiles to process");
+                return Ok(());
+            }
+
+            if let Some(threshold) = self.message_batch_threshold
+                && pending >= threshold
+            {
+                let dids = self
+                    .database
+                    .claim_unprocessed_profile_dids(
+                        self.message_batch_claim_size(),
+                        Self::CLAIM_LEASE,
+                        self.order,
+                    )
+                    .await?;
+
+                return self.submit_message_batch(&dids).await;
+            }
+
+            let dids = self
+                .database
+                .claim_unprocessed_profile_dids(self.claim_size(), Self::CLAIM_LEASE, self.order)
+                .await?;
+
+            if dids.is_empty() {
+                info!("All pending profiles are being processed elsewhere");
+                return Ok(());
+            }
+
+            self.classify_profiles(&dids).await;
+        }
+    }
+
+    async fn classify_profiles(&self, dids: &[String]) {
+        info!(
+            "Classifying {} new profiles with {} workers in batches of {}",
+            dids.len(),
+            self.concurrency,
+            self.batch_size
+        );
+
+        metrics::profiles_queued(dids.len());
+
+        futures::stream::iter(dids.chunks(self.batch_size))
+            .for_each_concurrent(self.concurrency, |batch| async move {
+                metrics::profiles_dequeued(batch.len());
+
+                match batch {
+                    [did] => self.classify_profile(did).await,
+                    _ => self.classify_batch(batch).await,
+                }
+            })
+            .await;
+    }
+
+    fn claim_size(&self) -> usize {
+        self.concurrency * self.batch_size * 10
+    }
+
+    /// Details of every profile in a message batch are fetched before it's
+    /// submitted, so claim no more than can be fetched in half of the lease,
+    /// or someone else would claim them again while we're still at it
+    fn message_batch_claim_size(&self) -> usize {
+        let fetchable = self
+            .bluesky_rate_limiter
+            .requests_within((Self::CLAIM_LEASE / 2).to_std().unwrap_or_default());
+
+        fetchable.clamp(1, Self::MAX_MESSAGE_BATCH_SIZE)
+    }
+
+    async fn classify_profile(&self, did: &str) {
+        let started_at = Instant::now();
+        let result = self.fill_in_profile_details(did).await;
+        self.report_result(did, result, started_at).await;
+    }
+
+    async fn classify_batch(&self, dids: &[String]) {
+        let started_at = Instant::now();
+
+        let mut described = Vec::with_capacity(dids.len());
+        for did in dids {
+            match self.fetch_profile_details(did).await {
+                Ok(Some(details)) => described.push((did, details)),
+                Ok(None) => {
+                    let result = self.store_profile_details(did, "xx").await;
+                    self.report_result(did, result, started_at).await;
                 }
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                Err(e) => self.report_result(did, Err(e), started_at).await,
+            }
+        }
+
+        if described.is_empty() {
+            return;
+        }
+
+        let profiles: Vec<_> = described
+            .iter()
+            .map(|(did, details)| ProfileDescription {
+                did,
+                display_name: details.display_name.as_deref().unwrap_or_default(),
+                description: details.description.as_deref().unwrap_or_default(),
+            })
+            .collect();
+
+        let mut countries = self
+            .call_ai(|| self.ai.infer_countries_of_living(&profiles))
+            .await
+            .unwrap_or_else(|e| {
+                metrics::profiles_classification_failed("infer_countries");
+                warn!(
+                    "Could not infer countries for a batch of {} profiles, falling back to one by one: {:?}",
+                    profiles.len(),
+                    e
+                );
+                HashMap::new()
+            });
+
+        for profile in &profiles {
+            let result = match countries.remove(profile.did) {
+                Some(country) => self.store_profile_details(profile.did, &country).await,
+                None => {
+                    metrics::batch_classification_fallback();
+                    self.infer_and_store_profile_details(
+                        profile.did,
+                        profile.display_name,
+                        profile.description,
+                    )
+                    .await
+                }
+            };
+
+            self.report_result(profile.did, result, started_at).await;
+        }
+    }
+
+    async fn submit_message_batch(&self, dids: &[String]) -> Result<()> {
+        let details: Vec<_> = futures::stream::iter(dids)
+            .map(|did| async move { (did, self.fetch_profile_details(did).await) })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        let mut described = Vec::with_capacity(details.len());
+        for (did, result) in details {
+            match result {
+                Ok(Some(details)) => described.push((did, details)),
+                Ok(None) => {
+                    let result = self.store_profile_details(did, "xx").await;
+                    self.report_result(did, result, Instant::now()).await;
+                }
+                Err(e) => self.report_result(did, Err(e), Instant::now()).await,
+            }
+        }
+
+        if described.is_empty() {
+            return Ok(());
+        }
+
+        let profiles: Vec<_> = described
+            .iter()
+            .enumerate()
+            .map(|(i, (did, details))| BatchedProfile {
+                custom_id: format!("p{i}"),
+                profile: ProfileDescription {
+                    did,
+                    display_name: details.display_name.as_deref().unwrap_or_default(),
+                    description: details.description.as_deref().unwrap_or_default(),
+                },
+            })
+            .collect();
+
+        let batch_id = match self
+            .call_ai(|| self.ai.submit_countries_of_living_batch(&profiles))
+            .await
+        {
+            Ok(batch_id) => batch_id,
+            Err(e) => {
+                metrics::profiles_classification_failed("submit_message_batch");
+                error!(
+                    "Could not submit message batch of {} profiles: {:?}",
+                    profiles.len(),
+                    e
+                );
+
+                // Otherwise they'd sit claimed until the lease runs out
+                // without ever counting towards the attempt cap
+                for profile in &profiles {
+                    let result = Err(anyhow!("Could not submit message batch: {e:#}"));
+                    self.report_result(profile.profile.did, result, Instant::now())
+                        .await;
+                }
+
+                return Ok(());
+            }
+        };
+
+        let custom_ids_and_dids: Vec<_> = profiles
+            .iter()
+            .map(|p| (p.custom_id.clone(), p.profile.did.to_owned()))
+            .collect();
+
+        self.database
+            .create_classification_batch(&batch_id, &custom_ids_and_dids)
+            .await?;
+
+        metrics::message_batch_submitted(profiles.len());
+        info!(
+            "Submitted message batch {batch_id} with {} profiles",
+            profiles.len()
+        );
+
+        Ok(())
+    }
+
+    async fn collect_finished_message_batches(&self) -> Result<()> {
+        for (batch_id, submitted_at) in self
+            .database
+            .fetch_unfinished_classification_batches()
+            .await?
+        {
+            let collected = match self.collect_message_batch(&batch_id).await {
+                Ok(collected) => collected,
+                Err(e) => {
+                    metrics::profiles_classification_failed("collect_message_batches");
+                    error!("Could not collect message batch {batch_id}: {:?}", e);
+                    false
+                }
+            };
+
+            if !collected && Utc::now() - submitted_at > Self::MESSAGE_BATCH_DEADLINE {
+                // Batches that are gone or never finish would otherwise keep
+                // their profiles from being classified forever
+                self.database.finish_classification_batch(&batch_id).await?;
+
+                metrics::message_batch_abandoned();
+                warn!(
+                    "Gave up on message batch {batch_id} submitted at {submitted_at}, its profiles will be classified again"
+                );
             }
         }
 
         Ok(())
     }
 
+    /// Stores results of the batch if it has ended. Returns false if it's
+    /// still in progress.
+    async fn collect_message_batch(&self, batch_id: &str) -> Result<bool> {
+        let countries = match self
+            .call_ai(|| self.ai.fetch_countries_of_living_batch_results(batch_id))
+            .await?
+        {
+            Some(countries) => countries,
+            None => {
+                info!("Message batch {batch_id} is still in progress");
+                return Ok(false);
+            }
+        };
+
+        let profiles = self
+            .database
+            .fetch_classification_batch_profiles(batch_id)
+            .await?;
+
+        let mut missing = 0;
+        for (custom_id, did) in &profiles {
+            match countries.get(custom_id) {
+                Some(country) => {
+                    let result = self.store_profile_details(did, country).await;
+                    self.report_result(did, result, Instant::now()).await;
+                }
+                None => {
+                    missing += 1;
+                    let result = Err(anyhow!("Missing from message batch {batch_id} results"));
+                    self.report_result(did, result, Instant::now()).await;
+                }
+            }
+        }
+
+        self.database.finish_classification_batch(batch_id).await?;
+
+        metrics::message_batch_finished();
+        info!(
+            "Collected message batch {batch_id}: {} profiles classified, {missing} left for retrying",
+            profiles.len() - missing
+        );
+
+        Ok(true)
+    }
+
     async fn fill_in_profile_details(&self, did: &str) -> Result<()> {
+        match self.fetch_profile_details(did).await? {
+            Some(details) => {
+                self.infer_and_store_profile_details(
+                    did,
+                    details.display_name.as_deref().unwrap_or_default(),
+                    details.description.as_deref().unwrap_or_default(),
+                )
+                .await
+            }
+            None => self.store_profile_details(did, "xx").await,
+        }
+    }
+
+    async fn fetch_profile_details(&self, did: &str) -> Result<Option<ProfileRecordData>> {
+        self.bluesky_rate_limiter.acquire().await;
+
+        let started_at = Instant::now();
         let details = self
             .bluesky
             .fetch_profile_details(did)
             .await
             .inspect_err(|_| metrics::profiles_classification_failed("fetch_profile"))
             .context("Could not fetch profile details")?;
+        metrics::external_request_duration("bluesky", started_at.elapsed());
 
-        let country = match details {
-            Some(details) => self
-                .ai
-                .infer_country_of_living(
-                    details.display_name.as_deref().unwrap_or_default(),
-                    details.description.as_deref().unwrap_or_default(),
+        if let Some(details) = &details {
+            self.database
+                .store_profile_description(
+                    did,
+                    details.display_name.as_deref(),
+                    details.description.as_deref(),
                 )
-                .await
-                .inspect_err(|_| metrics::profiles_classification_failed("infer_country"))
-                .context("Could not infer country of living")?,
-            None => "xx".to_owned(),
+                .await?;
+        }
+
+        Ok(details)
+    }
+
+    async fn infer_and_store_profile_details(
+        &self,
+        did: &str,
+        display_name: &str,
+        description: &str,
+    ) -> Result<()> {
+        if let Some(target_country) = &self.enriched_country {
+            return self
+                .infer_and_store_residency(did, display_name, description, target_country)
+                .await;
+        }
+
+        let country = self
+            .call_ai(|| self.ai.infer_country_of_living(display_name, description))
+            .await
+            .inspect_err(|_| metrics::profiles_classification_failed("infer_country"))
+            .context("Could not infer country of living")?;
+
+        self.store_profile_details(did, &country).await
+    }
+
+    /// Classifies the profile using its recent posts and follows on top of the
+    /// name and bio, combining everything into a probability of it living in
+    /// the target country
+    async fn infer_and_store_residency(
+        &self,
+        did: &str,
+        display_name: &str,
+        description: &str,
+        target_country: &str,
+    ) -> Result<()> {
+        let recent_posts = self
+            .database
+            .fetch_recent_post_texts(did, Self::RECENT_POSTS_SAMPLE_SIZE)
+            .await?;
+        let language_counts = self.database.fetch_post_language_counts(did).await?;
+
+        self.bluesky_rate_limiter.acquire().await;
+
+        let started_at = Instant::now();
+        let mut follows = self
+            .bluesky
+            .fetch_follows(did, Self::FOLLOWS_SAMPLE_SIZE)
+            .await
+            .inspect_err(|_| metrics::profiles_classification_failed("fetch_follows"))
+            .context("Could not fetch follows")?;
+        metrics::external_request_duration("bluesky", started_at.elapsed());
+
+        follows.extend(self.database.fetch_followed_dids(did).await?);
+        follows.sort_unstable();
+        follows.dedup();
+
+        let classified_follows = self
+            .database
+            .count_classified_profiles_among(&follows, target_country)
+            .await?;
+        let classified_followers = self
+            .database
+            .count_classified_followers(did, target_country)
+            .await?;
+
+        let context = ResidencyContext {
+            display_name,
+            description,
+            recent_posts: &recent_posts,
+            language_counts: &language_counts,
         };
 
+        let guess = self
+            .call_ai(|| self.ai.infer_residency(&context))
+            .await
+            .inspect_err(|_| metrics::profiles_classification_failed("infer_country"))
+            .context("Could not infer country of living")?;
+
+        let signals = ResidencySignals {
+            guess,
+            classified_follows,
+            classified_followers,
+        };
+
+        let probability = signals.probability_of_living_in(target_country);
+        let country = signals.decide(target_country, probability);
+
+        info!(
+            "Combined probability of {did} living in {target_country}: {probability:.2} (Claude said {} with confidence {:.2}, {} of {} classified follows and {} of {} classified followers live there)",
+            signals.guess.country,
+            signals.guess.confidence,
+            classified_follows.1,
+            classified_follows.0,
+            classified_followers.1,
+            classified_followers.0
+        );
+
         self.database
-            .store_profile_details(did, &country)
+            .store_profile_details(did, &country, Some(probability))
             .await
             .inspect_err(|_| metrics::profiles_classification_failed("store_profile"))?;
         info!("Stored inferred country of living for {did}: {country}");
         Ok(())
     }
+
+    async fn store_profile_details(&self, did: &str, country: &str) -> Result<()> {
+        self.database
+            .store_profile_details(did, country, None)
+            .await
+            .inspect_err(|_| metrics::profiles_classification_failed("store_profile"))?;
+        info!("Stored inferred country of living for {did}: {country}");
+        Ok(())
+    }
+
+    /// Makes a rate limited call to the AI, backing off and retrying when
+    /// Anthropic tells us to slow down
+    async fn call_ai<T, F, Fut>(&self, call: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            self.ai_rate_limiter.acquire().await;
+
+            let started_at = Instant::now();
+            let result = call().await;
+            metrics::external_request_duration("anthropic", started_at.elapsed());
+
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+
+            let rate_limited = match error.downcast_ref::<RateLimited>() {
+                Some(rate_limited) if attempt < Self::MAX_RATE_LIMITED_ATTEMPTS => rate_limited,
+                _ => return Err(error),
+            };
+
+            let retry_after = rate_limited
+                .retry_after
+                .unwrap_or(Self::DEFAULT_RETRY_AFTER);
+
+            metrics::rate_limited("anthropic");
+            warn!(
+                "Rate limited by Anthropic, pausing for {}s (attempt {attempt})",
+                retry_after.as_secs()
+            );
+
+            self.ai_rate_limiter.pause_for(retry_after).await;
+            attempt += 1;
+        }
+    }
+
+    async fn report_result(&self, did: &str, result: Result<()>, started_at: Instant) {
+        let error = match result {
+            Ok(()) => {
+                metrics::profiles_classified();
+                metrics::profile_classification_duration(started_at.elapsed());
+                return;
+            }
+            Err(e) => e,
+        };
+
+        error!("Could not classify profile with did {}: {:?}", did, error);
+
+        match self
+            .database
+            .record_profile_classification_failure(
+                did,
+                &format!("{error:#}"),
+                self.max_attempts,
+                Self::RETRY_BACKOFF,
+            )
+            .await
+        {
+            Ok(true) => {
+                metrics::profiles_dead_lettered();
+                warn!("Giving up on classifying profile with did {did}");
+            }
+            Ok(false) => {}
+            Err(e) => error!("Could not record classification failure for {did}: {:?}", e),
+        }
+    }
 }
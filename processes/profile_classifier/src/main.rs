@@ -1,5 +1,7 @@
 extern crate nederlandskie_profile_classifier;
 
+use std::sync::Arc;
+
 use anyhow::Result;
 use env_logger::Env;
 use log::info;
@@ -31,9 +33,17 @@ async fn main() -> Result<()> {
     let bluesky = Bluesky::unauthenticated();
 
     info!("Connecting to the database");
-    let database = Database::connect(&config.database_url).await?;
+    let database =
+        Arc::new(Database::connect_with_pool(&config.database_url, &config.database_pool).await?);
+
+    info!("Bringing the database schema up to date");
+    database.migrate().await?;
+
+    if config.metrics_enabled {
+        tokio::spawn(database.clone().report_pool_metrics());
+    }
 
-    let profile_classifier = ProfileClassifier::new(database, ai, bluesky);
+    let profile_classifier = ProfileClassifier::new(database, ai, bluesky, &config);
 
     info!("Starting Profile Classifier");
 
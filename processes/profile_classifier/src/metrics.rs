@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 pub fn profiles_classified() {
     metrics::counter!("profiles_classified_total").increment(1);
 }
@@ -9,3 +11,44 @@ pub fn profiles_pending(n: usize) {
 pub fn profiles_classification_failed(kind: &'static str) {
     metrics::counter!("profiles_classification_errors_total", "kind" => kind).increment(1);
 }
+
+pub fn profiles_queued(n: usize) {
+    metrics::gauge!("profiles_queue_depth").set(n as f64);
+}
+
+pub fn profiles_dequeued(n: usize) {
+    metrics::gauge!("profiles_queue_depth").decrement(n as f64);
+}
+
+pub fn profile_classification_duration(duration: Duration) {
+    metrics::histogram!("profile_classification_duration_seconds").record(duration);
+}
+
+pub fn external_request_duration(service: &'static str, duration: Duration) {
+    metrics::histogram!("external_request_duration_seconds", "service" => service).record(duration);
+}
+
+pub fn rate_limited(service: &'static str) {
+    metrics::counter!("rate_limited_total", "service" => service).increment(1);
+}
+
+pub fn batch_classification_fallback() {
+    metrics::counter!("profiles_batch_classification_fallbacks_total").increment(1);
+}
+
+pub fn message_batch_submitted(n: usize) {
+    metrics::counter!("message_batches_submitted_total").increment(1);
+    metrics::counter!("message_batch_profiles_submitted_total").increment(n as u64);
+}
+
+pub fn message_batch_finished() {
+    metrics::counter!("message_batches_finished_total").increment(1);
+}
+
+pub fn message_batch_abandoned() {
+    metrics::counter!("message_batches_abandoned_total").increment(1);
+}
+
+pub fn profiles_dead_lettered() {
+    metrics::counter!("profiles_dead_lettered_total").increment(1);
+}
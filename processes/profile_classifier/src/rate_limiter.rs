@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// A token bucket shared by all workers talking to one external service
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<State>,
+}
+
+struct State {
+    tokens: f64,
+    refilled_at: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+
+        Self {
+            rate: requests_per_second,
+            burst,
+            state: Mutex::new(State {
+                tokens: burst,
+                refilled_at: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a request is allowed to be made
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+
+                match state.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        state.paused_until = None;
+
+                        let elapsed = now - state.refilled_at;
+                        state.tokens =
+                            (state.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+                        state.refilled_at = now;
+
+                        if state.tokens >= 1.0 {
+                            state.tokens -= 1.0;
+                            return;
+                        }
+
+                        Duration::from_secs_f64((1.0 - state.tokens) / self.rate)
+                    }
+                }
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// How many requests can be made at most over the given duration
+    pub fn requests_within(&self, duration: Duration) -> usize {
+        (self.burst + duration.as_secs_f64() * self.rate) as usize
+    }
+
+    /// Stops handing out permits for the given duration, e.g. after being told
+    /// to back off by the service
+    pub async fn pause_for(&self, duration: Duration) {
+        let mut state = self.state.lock().await;
+        let until = Instant::now() + duration;
+
+        if state.paused_until.is_none_or(|current| current < until) {
+            state.paused_until = Some(until);
+        }
+
+        state.tokens = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn allows_burst_without_waiting() {
+        let limiter = RateLimiter::new(1.0, 3);
+        let started_at = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+
+        assert!(started_at.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn waits_for_tokens_to_refill() {
+        let limiter = RateLimiter::new(20.0, 1);
+        let started_at = Instant::now();
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+
+        assert!(started_at.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[test]
+    fn counts_requests_within_duration() {
+        let limiter = RateLimiter::new(5.0, 2);
+
+        assert_eq!(limiter.requests_within(Duration::from_secs(60)), 302);
+    }
+
+    #[tokio::test]
+    async fn waits_while_paused() {
+        let limiter = RateLimiter::new(1000.0, 10);
+        let started_at = Instant::now();
+
+        limiter.pause_for(Duration::from_millis(100)).await;
+        limiter.acquire().await;
+
+        assert!(started_at.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,172 @@
+use nederlandskie_core::services::ResidencyGuess;
+
+/// How many of the followed (or following) profiles need to be classified
+/// already before the follow graph is trusted to say anything
+const MIN_CLASSIFIED_FOLLOWS: i64 = 5;
+
+/// Keeps probabilities away from 0 and 1 so that a single overconfident
+/// signal can't outweigh all the others
+const MAX_CERTAINTY: f64 = 0.99;
+
+const LLM_WEIGHT: f64 = 1.0;
+const FOLLOWS_WEIGHT: f64 = 1.0;
+const FOLLOWERS_WEIGHT: f64 = 0.5;
+
+/// Signals collected about a profile's likely country of living. Only used
+/// with `CLASSIFIER_ENRICHED_COUNTRY` set, which is also the only time the post
+/// indexer stores follows for the follow graph to be known
+pub struct ResidencySignals {
+    pub guess: ResidencyGuess,
+    /// Number of followed profiles that have been classified, and how many of
+    /// those live in the country in question
+    pub classified_follows: (i64, i64),
+    /// Same as above, but for known profiles following this one
+    pub classified_followers: (i64, i64),
+}
+
+impl ResidencySignals {
+    /// Combines all signals into a probability of the profile living in the
+    /// given country, averaging them in log-odds space. Without any signals
+    /// there's no telling either way.
+    pub fn probability_of_living_in(&self, country: &str) -> f64 {
+        let mut signals = Vec::new();
+
+        // Being sure that the country can't be told says nothing about
+        // which one it is, so it's left out rather than dragging the others
+        // towards even odds
+        if self.guess.country == country {
+            signals.push((self.guess.confidence, LLM_WEIGHT));
+        } else if self.guess.country != "xx" {
+            signals.push((1.0 - self.guess.confidence, LLM_WEIGHT));
+        }
+
+        if let Some(probability) = graph_probability(self.classified_follows) {
+            signals.push((probability, FOLLOWS_WEIGHT));
+        }
+
+        if let Some(probability) = graph_probability(self.classified_followers) {
+            signals.push((probability, FOLLOWERS_WEIGHT));
+        }
+
+        if signals.is_empty() {
+            return 0.5;
+        }
+
+        let total_weight: f64 = signals.iter().map(|(_, weight)| weight).sum();
+        let log_odds: f64 = signals
+            .iter()
+            .map(|(probability, weight)| logit(*probability) * weight)
+            .sum::<f64>()
+            / total_weight;
+
+        1.0 / (1.0 + (-log_odds).exp())
+    }
+
+    /// Picks the country to store for the profile, given the combined
+    /// probability of it living in the country we care about
+    pub fn decide(&self, country: &str, probability: f64) -> String {
+        if probability >= 0.5 {
+            country.to_owned()
+        } else if self.guess.country == country {
+            "xx".to_owned()
+        } else {
+            self.guess.country.clone()
+        }
+    }
+}
+
+/// Share of classified neighbours living in the country, smoothed so that a
+/// handful of them can't produce certainty on their own
+fn graph_probability((classified, in_country): (i64, i64)) -> Option<f64> {
+    (classified >= MIN_CLASSIFIED_FOLLOWS)
+        .then(|| (in_country as f64 + 1.0) / (classified as f64 + 2.0))
+}
+
+fn logit(probability: f64) -> f64 {
+    let probability = probability.clamp(1.0 - MAX_CERTAINTY, MAX_CERTAINTY);
+    (probability / (1.0 - probability)).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals(country: &str, confidence: f64, classified_follows: (i64, i64)) -> ResidencySignals {
+        ResidencySignals {
+            guess: ResidencyGuess {
+                country: country.to_owned(),
+                confidence,
+            },
+            classified_follows,
+            classified_followers: (0, 0),
+        }
+    }
+
+    #[test]
+    fn uses_only_llm_when_follows_are_unknown() {
+        let signals = signals("nl", 0.8, (3, 3));
+        let probability = signals.probability_of_living_in("nl");
+
+        assert!((probability - 0.8).abs() < 1e-9);
+        assert_eq!(signals.decide("nl", probability), "nl");
+    }
+
+    #[test]
+    fn follows_can_outweigh_uncertain_llm() {
+        let signals = signals("xx", 0.6, (40, 35));
+        let probability = signals.probability_of_living_in("nl");
+
+        assert!(probability > 0.5);
+        assert_eq!(signals.decide("nl", probability), "nl");
+    }
+
+    #[test]
+    fn undetermined_country_is_neutral() {
+        let llm_only = signals("xx", 0.9, (0, 0)).probability_of_living_in("nl");
+        assert!((llm_only - 0.5).abs() < 1e-9);
+
+        let signals = signals("xx", 0.9, (10, 7));
+        let probability = signals.probability_of_living_in("nl");
+
+        assert!(probability > 0.5);
+        assert_eq!(signals.decide("nl", probability), "nl");
+    }
+
+    #[test]
+    fn undetermined_country_leaves_graph_evidence_as_is() {
+        let graph_only = graph_probability((10, 7)).unwrap();
+        let probability = signals("xx", 0.9, (10, 7)).probability_of_living_in("nl");
+
+        assert!((probability - graph_only).abs() < 1e-9);
+    }
+
+    #[test]
+    fn follows_can_cast_doubt_on_llm() {
+        let signals = signals("nl", 0.6, (40, 0));
+        let probability = signals.probability_of_living_in("nl");
+
+        assert!(probability < 0.5);
+        assert_eq!(signals.decide("nl", probability), "xx");
+    }
+
+    #[test]
+    fn followers_add_to_follows() {
+        let follows_only = signals("xx", 0.5, (10, 6)).probability_of_living_in("nl");
+
+        let mut signals = signals("xx", 0.5, (10, 6));
+        signals.classified_followers = (20, 18);
+        let probability = signals.probability_of_living_in("nl");
+
+        assert!(probability > follows_only);
+        assert_eq!(signals.decide("nl", probability), "nl");
+    }
+
+    #[test]
+    fn keeps_llm_country_when_unlikely_to_live_in_target() {
+        let signals = signals("de", 0.9, (10, 1));
+        let probability = signals.probability_of_living_in("nl");
+
+        assert!(probability < 0.5);
+        assert_eq!(signals.decide("nl", probability), "de");
+    }
+}
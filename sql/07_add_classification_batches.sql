@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS ClassificationBatch (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    batch_id TEXT UNIQUE,
+    submitted_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    ended_at TIMESTAMP WITH TIME ZONE NULL DEFAULT NULL
+);
+
+CREATE TABLE IF NOT EXISTS ClassificationBatchProfile (
+    batch_id TEXT REFERENCES ClassificationBatch(batch_id) ON DELETE CASCADE,
+    custom_id TEXT,
+    did TEXT REFERENCES Profile(did) ON DELETE CASCADE,
+    PRIMARY KEY (batch_id, custom_id)
+);
+
+CREATE INDEX ON ClassificationBatchProfile (did);
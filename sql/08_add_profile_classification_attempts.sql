@@ -0,0 +1,5 @@
+ALTER TABLE Profile ADD COLUMN classification_attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE Profile ADD COLUMN next_classification_attempt_at TIMESTAMP WITH TIME ZONE NULL DEFAULT NULL;
+ALTER TABLE Profile ADD COLUMN has_failed_classification BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE Profile ADD COLUMN last_classification_error TEXT NULL DEFAULT NULL;
+CREATE INDEX ON Profile (next_classification_attempt_at) WHERE has_been_processed = FALSE AND has_failed_classification = FALSE;
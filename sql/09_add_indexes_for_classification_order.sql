@@ -0,0 +1,2 @@
+CREATE INDEX ON Profile (first_seen_at) WHERE has_been_processed = FALSE AND has_failed_classification = FALSE;
+CREATE INDEX ON Post (author_did);
@@ -0,0 +1,3 @@
+ALTER TABLE Post ADD COLUMN text TEXT NULL DEFAULT NULL;
+ALTER TABLE Post ADD COLUMN language TEXT NULL DEFAULT NULL;
+ALTER TABLE Profile ADD COLUMN residency_probability DOUBLE PRECISION NULL DEFAULT NULL;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS Follow (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    indexed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    uri TEXT UNIQUE,
+    author_did TEXT NOT NULL,
+    subject_did TEXT NOT NULL
+);
+
+CREATE INDEX ON Follow (author_did);
+CREATE INDEX ON Follow (subject_did);
@@ -0,0 +1,12 @@
+ALTER TABLE Post ADD COLUMN like_count INT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS PostLike (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    indexed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    uri TEXT UNIQUE,
+    post_uri TEXT NOT NULL REFERENCES Post(uri) ON DELETE CASCADE,
+    author_did TEXT NOT NULL
+);
+
+CREATE INDEX ON PostLike (post_uri);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS Repost (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    indexed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    cid TEXT UNIQUE,
+    uri TEXT UNIQUE,
+    author_did TEXT NOT NULL REFERENCES Profile(did),
+    post_uri TEXT NOT NULL
+);
+
+CREATE INDEX ON Repost (created_at DESC);
+CREATE INDEX ON Repost (author_did);
@@ -0,0 +1,4 @@
+ALTER TABLE Post ADD COLUMN reply_parent_uri TEXT NULL DEFAULT NULL;
+ALTER TABLE Post ADD COLUMN reply_root_uri TEXT NULL DEFAULT NULL;
+
+CREATE INDEX ON Post ((COALESCE(reply_root_uri, uri)), created_at DESC);
@@ -0,0 +1,2 @@
+ALTER TABLE Post ADD COLUMN embed_kind TEXT NULL DEFAULT NULL;
+ALTER TABLE Post ADD COLUMN quoted_uri TEXT NULL DEFAULT NULL;
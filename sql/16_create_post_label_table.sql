@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS PostLabel (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    indexed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NULL DEFAULT NULL,
+    uri TEXT NOT NULL REFERENCES Post(uri) ON DELETE CASCADE,
+    src TEXT NOT NULL,
+    value TEXT NOT NULL,
+    UNIQUE (uri, src, value)
+);
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS BannedProfile (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    banned_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    did TEXT UNIQUE NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS BannedPost (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    banned_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    uri TEXT UNIQUE NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS ModerationLog (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    performed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    action TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    reason TEXT NULL,
+    moderator TEXT NULL
+);
+
+CREATE INDEX ON ModerationLog (performed_at DESC);
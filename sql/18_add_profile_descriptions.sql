@@ -0,0 +1,5 @@
+ALTER TABLE Profile ADD COLUMN display_name TEXT NULL DEFAULT NULL;
+ALTER TABLE Profile ADD COLUMN description TEXT NULL DEFAULT NULL;
+ALTER TABLE Profile ADD COLUMN classified_at TIMESTAMP WITH TIME ZONE NULL DEFAULT NULL;
+
+CREATE INDEX ON Profile (classified_at DESC) WHERE classified_at IS NOT NULL;
@@ -0,0 +1,8 @@
+ALTER TABLE Post ADD COLUMN author_country TEXT NULL DEFAULT NULL;
+
+UPDATE Post SET author_country = Profile.likely_country_of_living
+FROM Profile
+WHERE Profile.did = Post.author_did;
+
+CREATE INDEX ON Post (author_country, created_at DESC, cid DESC);
+CREATE INDEX ON Profile (likely_country_of_living);
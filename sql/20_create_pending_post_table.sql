@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS PendingPost (
+    id INT GENERATED ALWAYS AS IDENTITY,
+    indexed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    cid TEXT UNIQUE,
+    uri TEXT UNIQUE,
+    author_did TEXT NOT NULL REFERENCES Profile(did) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    text TEXT NULL,
+    language TEXT NULL,
+    reply_parent_uri TEXT NULL DEFAULT NULL,
+    reply_root_uri TEXT NULL DEFAULT NULL,
+    embed_kind TEXT NULL DEFAULT NULL,
+    quoted_uri TEXT NULL DEFAULT NULL,
+    self_labels TEXT[] NOT NULL DEFAULT '{}',
+    awaited_country TEXT NOT NULL
+);
+
+CREATE INDEX ON PendingPost (author_did);
+CREATE INDEX ON PendingPost (indexed_at);
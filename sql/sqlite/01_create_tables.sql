@@ -0,0 +1,142 @@
+-- The SQLite schema is kept in its own set of migrations, since it starts out
+-- at what the Postgres migrations in the parent directory add up to.
+--
+-- Timestamps are stored as RFC 3339 text in UTC, the way sqlx encodes them,
+-- so that they compare correctly as strings.
+
+CREATE TABLE IF NOT EXISTS Profile (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    first_seen_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    did TEXT UNIQUE,
+    has_been_processed BOOLEAN NOT NULL DEFAULT FALSE,
+    likely_country_of_living TEXT NULL DEFAULT NULL,
+    residency_probability REAL NULL DEFAULT NULL,
+    classification_attempts INTEGER NOT NULL DEFAULT 0,
+    next_classification_attempt_at TEXT NULL DEFAULT NULL,
+    has_failed_classification BOOLEAN NOT NULL DEFAULT FALSE,
+    last_classification_error TEXT NULL DEFAULT NULL,
+    display_name TEXT NULL DEFAULT NULL,
+    description TEXT NULL DEFAULT NULL,
+    classified_at TEXT NULL DEFAULT NULL
+);
+
+CREATE INDEX IF NOT EXISTS profile_country_idx ON Profile (likely_country_of_living);
+CREATE INDEX IF NOT EXISTS profile_classified_at_idx ON Profile (classified_at DESC) WHERE classified_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS profile_unprocessed_idx ON Profile (first_seen_at) WHERE has_been_processed = FALSE AND has_failed_classification = FALSE;
+
+CREATE TABLE IF NOT EXISTS Post (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    indexed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    cid TEXT UNIQUE,
+    uri TEXT UNIQUE,
+    author_did TEXT REFERENCES Profile(did),
+    created_at TEXT NOT NULL,
+    text TEXT NULL,
+    language TEXT NULL,
+    like_count INTEGER NOT NULL DEFAULT 0,
+    reply_parent_uri TEXT NULL DEFAULT NULL,
+    reply_root_uri TEXT NULL DEFAULT NULL,
+    embed_kind TEXT NULL DEFAULT NULL,
+    quoted_uri TEXT NULL DEFAULT NULL
+);
+
+CREATE INDEX IF NOT EXISTS post_author_did_idx ON Post (author_did);
+CREATE INDEX IF NOT EXISTS post_created_at_idx ON Post (created_at DESC);
+CREATE INDEX IF NOT EXISTS post_indexed_at_idx ON Post (indexed_at);
+CREATE INDEX IF NOT EXISTS post_thread_idx ON Post (COALESCE(reply_root_uri, uri), created_at DESC);
+
+CREATE TABLE IF NOT EXISTS PostLike (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    indexed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    created_at TEXT NOT NULL,
+    uri TEXT UNIQUE,
+    post_uri TEXT NOT NULL REFERENCES Post(uri) ON DELETE CASCADE,
+    author_did TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS postlike_post_uri_idx ON PostLike (post_uri);
+
+CREATE TABLE IF NOT EXISTS Repost (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    indexed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    created_at TEXT NOT NULL,
+    cid TEXT UNIQUE,
+    uri TEXT UNIQUE,
+    author_did TEXT NOT NULL REFERENCES Profile(did),
+    post_uri TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS repost_created_at_idx ON Repost (created_at DESC);
+CREATE INDEX IF NOT EXISTS repost_author_did_idx ON Repost (author_did);
+
+CREATE TABLE IF NOT EXISTS Follow (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    indexed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    created_at TEXT NOT NULL,
+    uri TEXT UNIQUE,
+    author_did TEXT NOT NULL,
+    subject_did TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS follow_author_did_idx ON Follow (author_did);
+CREATE INDEX IF NOT EXISTS follow_subject_did_idx ON Follow (subject_did);
+
+CREATE TABLE IF NOT EXISTS PostLabel (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    indexed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    created_at TEXT NOT NULL,
+    expires_at TEXT NULL DEFAULT NULL,
+    uri TEXT NOT NULL REFERENCES Post(uri) ON DELETE CASCADE,
+    src TEXT NOT NULL,
+    value TEXT NOT NULL,
+    UNIQUE (uri, src, value)
+);
+
+CREATE TABLE IF NOT EXISTS SubscriptionState (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    service TEXT,
+    cursor INTEGER,
+    host TEXT,
+    UNIQUE (service, host)
+);
+
+CREATE TABLE IF NOT EXISTS ClassificationBatch (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    batch_id TEXT UNIQUE,
+    submitted_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    ended_at TEXT NULL DEFAULT NULL
+);
+
+CREATE TABLE IF NOT EXISTS ClassificationBatchProfile (
+    batch_id TEXT REFERENCES ClassificationBatch(batch_id) ON DELETE CASCADE,
+    custom_id TEXT,
+    did TEXT REFERENCES Profile(did) ON DELETE CASCADE,
+    PRIMARY KEY (batch_id, custom_id)
+);
+
+CREATE INDEX IF NOT EXISTS classificationbatchprofile_did_idx ON ClassificationBatchProfile (did);
+
+CREATE TABLE IF NOT EXISTS BannedProfile (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    banned_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    did TEXT UNIQUE NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS BannedPost (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    banned_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    uri TEXT UNIQUE NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS ModerationLog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    performed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    action TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    reason TEXT NULL,
+    moderator TEXT NULL
+);
+
+CREATE INDEX IF NOT EXISTS moderationlog_performed_at_idx ON ModerationLog (performed_at DESC);
@@ -0,0 +1,7 @@
+ALTER TABLE Post ADD COLUMN author_country TEXT NULL DEFAULT NULL;
+
+UPDATE Post SET author_country = (
+    SELECT likely_country_of_living FROM Profile WHERE Profile.did = Post.author_did
+);
+
+CREATE INDEX IF NOT EXISTS post_author_country_idx ON Post (author_country, created_at DESC, cid DESC);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS PendingPost (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    indexed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    cid TEXT UNIQUE,
+    uri TEXT UNIQUE,
+    author_did TEXT NOT NULL REFERENCES Profile(did) ON DELETE CASCADE,
+    created_at TEXT NOT NULL,
+    text TEXT NULL,
+    language TEXT NULL,
+    reply_parent_uri TEXT NULL DEFAULT NULL,
+    reply_root_uri TEXT NULL DEFAULT NULL,
+    embed_kind TEXT NULL DEFAULT NULL,
+    quoted_uri TEXT NULL DEFAULT NULL,
+    self_labels TEXT NOT NULL DEFAULT '[]',
+    awaited_country TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS pendingpost_author_did_idx ON PendingPost (author_did);
+CREATE INDEX IF NOT EXISTS pendingpost_indexed_at_idx ON PendingPost (indexed_at);
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use lingua::LanguageDetectorBuilder;
+
+use nederlandskie_core::services::bluesky::read_posts_from_repo;
+use nederlandskie_core::services::{Bluesky, Database};
+use nederlandskie_post_indexer::indexers::initialize_all_indexers;
+use nederlandskie_post_indexer::posts::{PostHandler, PostOutcome};
+
+/// Indexes posts made before the firehose subscription started, by going
+/// through whole repositories of profiles and running every post in them
+/// through the indexers, same as if it had just come from the firehose
+#[derive(Parser, Debug)]
+struct Args {
+    /// DIDs of the profiles whose repositories to download, comma-separated
+    #[arg(long, value_delimiter(','))]
+    did: Vec<String>,
+
+    /// Download repositories of all profiles classified as living in this
+    /// country, two letters
+    #[arg(long)]
+    country: Option<String>,
+
+    /// Repositories exported as CAR files to read instead of downloading
+    /// them, comma-separated
+    #[arg(long, value_delimiter(','))]
+    car: Vec<PathBuf>,
+
+    /// Only index posts made at or after this moment, e.g. 2024-01-01T00:00:00Z
+    #[arg(long)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only index posts made before this moment, e.g. 2024-06-01T00:00:00Z
+    #[arg(long)]
+    until: Option<DateTime<Utc>>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv()?;
+
+    let args = Args::parse();
+
+    if args.did.is_empty() && args.country.is_none() && args.car.is_empty() {
+        bail!("Either --did, --country or --car must be supplied");
+    }
+
+    let database_url =
+        env::var("DATABASE_URL").context("DATABASE_URL environment variable must be set")?;
+
+    let bluesky = Bluesky::unauthenticated();
+    let database = Arc::new(Database::connect(&database_url).await?);
+
+    let language_detector = Arc::new(
+        LanguageDetectorBuilder::from_all_languages_with_cyrillic_script()
+            .with_preloaded_language_models()
+            .build(),
+    );
+
+    let posts = PostHandler::new(
+        database.clone(),
+        initialize_all_indexers(language_detector.clone(), database.clone()),
+        language_detector,
+    );
+
+    for path in &args.car {
+        let car = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Could not read {}", path.display()))?;
+
+        backfill(&posts, &args, &path.display().to_string(), &car).await?;
+    }
+
+    let mut dids = args.did.clone();
+
+    if let Some(country) = &args.country {
+        dids.extend(database.fetch_profile_dids_in_country(country).await?);
+    }
+
+    for did in &dids {
+        match bluesky.fetch_repo(did).await {
+            Ok(Some(car)) => {
+                if let Err(e) = backfill(&posts, &args, did, &car).await {
+                    println!("{did}: could not backfill: {e:#}");
+                }
+            }
+            Ok(None) => println!("{did}: repository is not available"),
+            Err(e) => println!("{did}: could not download repository: {e:#}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs posts from the repository that fall within the window through the
+/// indexers, and reports what became of them
+async fn backfill(posts: &PostHandler, args: &Args, name: &str, car: &[u8]) -> Result<()> {
+    let mut outcomes = BTreeMap::new();
+
+    for repo_post in read_posts_from_repo(car).await? {
+        let created_at = repo_post.post.created_at.as_ref().with_timezone(&Utc);
+
+        let within_window = args.since.is_none_or(|since| created_at >= since)
+            && args.until.is_none_or(|until| created_at < until);
+
+        let outcome = if within_window {
+            posts
+                .handle_post(
+                    &repo_post.author_did,
+                    &repo_post.cid,
+                    &repo_post.uri,
+                    &repo_post.post,
+                )
+                .await?
+        } else {
+            PostOutcome::Skipped
+        };
+
+        *outcomes.entry(format!("{outcome:?}")).or_insert(0) += 1;
+    }
+
+    println!("{name}: {outcomes:?}");
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use tokio::task::JoinSet;
+
+use nederlandskie_core::services::Database;
+use nederlandskie_core::services::database::{PoolConfig, PostFilters, PostgresStorage};
+
+/// Measures how many of the queries on the hot paths of the feed server and
+/// the post indexer the database gets through per second. Only reads, so it's
+/// safe to point at a database that's in use.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Country whose feed and profiles to query
+    #[arg(long, default_value = "nl")]
+    country: String,
+
+    /// How many times to run each query
+    #[arg(long, default_value_t = 5000)]
+    requests: usize,
+
+    /// How many queries to run at the same time
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// How many posts to fetch per feed page
+    #[arg(long, default_value_t = 30)]
+    page_size: usize,
+
+    /// Also run the queries building their statements anew on every call,
+    /// the way it used to be done, and report both. Postgres only.
+    #[arg(long)]
+    compare_uncached: bool,
+}
+
+/// What the queries are run with, picked from the data in the database
+struct Inputs {
+    country: String,
+    cursor: (DateTime<Utc>, String),
+    dids: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv()?;
+
+    let args = Args::parse();
+
+    let database_url =
+        env::var("DATABASE_URL").context("DATABASE_URL environment variable must be set")?;
+
+    let pool = PoolConfig {
+        max_connections: args.concurrency as u32,
+        ..PoolConfig::default()
+    };
+
+    let database = Arc::new(Database::connect_with_pool(&database_url, &pool).await?);
+    let inputs = Arc::new(pick_inputs(&database, &args).await?);
+
+    if args.compare_uncached {
+        if database_url.starts_with("sqlite:") {
+            bail!("Only Postgres builds statements once, there's nothing to compare on SQLite");
+        }
+
+        let uncached = Arc::new(Database::new(
+            PostgresStorage::connect(&database_url, &pool)
+                .await?
+                .without_statement_cache(),
+        ));
+
+        println!("Building statements on every call:");
+        benchmark(uncached, inputs.clone(), &args).await?;
+
+        println!("Building statements once:");
+    }
+
+    benchmark(database, inputs, &args).await
+}
+
+async fn pick_inputs(database: &Database, args: &Args) -> Result<Inputs> {
+    let first_page = database
+        .fetch_posts_by_authors_country(
+            &args.country,
+            args.page_size,
+            None,
+            &PostFilters::default(),
+        )
+        .await?;
+
+    let Some(last_post) = first_page.last() else {
+        bail!(
+            "There are no posts by authors from '{}' to query",
+            args.country
+        );
+    };
+
+    let dids: Vec<String> = database
+        .fetch_recently_classified_profiles(Some(&args.country), 100)
+        .await?
+        .into_iter()
+        .map(|p| p.did)
+        .collect();
+
+    if dids.is_empty() {
+        bail!("There are no profiles from '{}' to query", args.country);
+    }
+
+    Ok(Inputs {
+        country: args.country.clone(),
+        cursor: (last_post.created_at, last_post.cid.clone()),
+        dids,
+    })
+}
+
+async fn benchmark(database: Arc<Database>, inputs: Arc<Inputs>, args: &Args) -> Result<()> {
+    let page_size = args.page_size;
+
+    let elapsed = run(args.requests, args.concurrency, |i| {
+        let database = database.clone();
+        let inputs = inputs.clone();
+        async move {
+            // Alternate between first pages and following ones, like clients do
+            let earlier_than = (i % 2 == 1).then(|| (inputs.cursor.0, inputs.cursor.1.as_str()));
+
+            database
+                .fetch_posts_by_authors_country(
+                    &inputs.country,
+                    page_size,
+                    earlier_than,
+                    &PostFilters::default(),
+                )
+                .await
+                .map(|_| ())
+        }
+    })
+    .await?;
+
+    report("Feed pages", args.requests, elapsed);
+
+    let elapsed = run(args.requests, args.concurrency, |i| {
+        let database = database.clone();
+        let inputs = inputs.clone();
+        async move {
+            database
+                .fetch_profile_residency(&inputs.dids[i % inputs.dids.len()], &inputs.country)
+                .await
+                .map(|_| ())
+        }
+    })
+    .await?;
+
+    report("Profile country lookups", args.requests, elapsed);
+
+    Ok(())
+}
+
+/// Runs the query the given number of times, at most `concurrency` at a time
+async fn run<F, Fut>(requests: usize, concurrency: usize, make_query: F) -> Result<Duration>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let started_at = Instant::now();
+    let mut running = JoinSet::new();
+
+    for i in 0..requests {
+        if running.len() >= concurrency {
+            running.join_next().await.expect("tasks are running")??;
+        }
+
+        running.spawn(make_query(i));
+    }
+
+    while let Some(result) = running.join_next().await {
+        result??;
+    }
+
+    Ok(started_at.elapsed())
+}
+
+fn report(name: &str, requests: usize, elapsed: Duration) {
+    println!(
+        "{}: {} queries in {:.2?}, {:.0} per second",
+        name,
+        requests,
+        elapsed,
+        requests as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,79 @@
+use std::env;
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+
+use nederlandskie_core::services::Database;
+use nederlandskie_core::services::database::SchemaStatus;
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Apply all pending migrations
+    Run,
+
+    /// Show which version the database schema is at
+    Status,
+
+    /// Mark migrations as applied without running them, for databases that
+    /// were set up by applying the files in sql/ by hand
+    Baseline {
+        /// Version of the last migration that has already been applied
+        #[arg(long)]
+        up_to: i64,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv()?;
+
+    let args = Args::parse();
+
+    let database_url =
+        env::var("DATABASE_URL").context("DATABASE_URL environment variable must be set")?;
+
+    let database = Database::connect(&database_url).await?;
+
+    match args.command {
+        Command::Run => {
+            let status = database.migrate().await?;
+            print_status(&status);
+        }
+        Command::Status => {
+            let status = database.schema_status().await?;
+            print_status(&status);
+        }
+        Command::Baseline { up_to } => {
+            let recorded = database.baseline_schema(up_to).await?;
+            println!(
+                "Marked {} migrations up to version {} as applied",
+                recorded, up_to
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn print_status(status: &SchemaStatus) {
+    match status.current_version {
+        Some(version) => println!("Schema is at version {}", version),
+        None => println!("Schema has not been set up yet"),
+    }
+
+    if status.pending > 0 {
+        println!(
+            "{} migrations pending, up to version {}",
+            status.pending, status.expected_version
+        );
+    } else {
+        println!("Schema is up to date");
+    }
+}
@@ -0,0 +1,189 @@
+use std::env;
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+
+use nederlandskie_core::services::database::BanKind;
+use nederlandskie_core::services::{Bluesky, Database};
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Name of whoever is moderating, recorded in the moderation log
+    #[arg(long, global = true)]
+    moderator: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Keep all posts by a profile out of the feeds
+    BanProfile {
+        /// Handle of the user to ban
+        #[arg(long, conflicts_with = "did")]
+        handle: Option<String>,
+
+        /// DID of the user to ban
+        #[arg(long)]
+        did: Option<String>,
+
+        /// Why the profile is banned
+        #[arg(long)]
+        reason: String,
+    },
+
+    /// Let posts by a previously banned profile into the feeds again
+    UnbanProfile {
+        /// Handle of the user to unban
+        #[arg(long, conflicts_with = "did")]
+        handle: Option<String>,
+
+        /// DID of the user to unban
+        #[arg(long)]
+        did: Option<String>,
+    },
+
+    /// Keep a single post out of the feeds
+    BanPost {
+        /// AT URI of the post, e.g. at://did:plc:.../app.bsky.feed.post/...
+        #[arg(long)]
+        uri: String,
+
+        /// Why the post is banned
+        #[arg(long)]
+        reason: String,
+    },
+
+    /// Let a previously banned post into the feeds again
+    UnbanPost {
+        /// AT URI of the post
+        #[arg(long)]
+        uri: String,
+    },
+
+    /// List banned profiles and posts
+    List,
+
+    /// Show the most recent moderation actions
+    Log {
+        /// How many actions to show
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv()?;
+
+    let args = Args::parse();
+
+    let database_url =
+        env::var("DATABASE_URL").context("DATABASE_URL environment variable must be set")?;
+
+    let database = Database::connect(&database_url).await?;
+    let moderator = args.moderator.as_deref();
+
+    match args.command {
+        Command::BanProfile {
+            handle,
+            did,
+            reason,
+        } => {
+            let did = resolve_did(handle, did).await?;
+
+            if database
+                .ban(BanKind::Profile, &did, &reason, moderator)
+                .await?
+            {
+                println!("Banned profile with did '{}'", did);
+            } else {
+                println!("Profile with did '{}' is already banned", did);
+            }
+        }
+        Command::UnbanProfile { handle, did } => {
+            let did = resolve_did(handle, did).await?;
+
+            if database.unban(BanKind::Profile, &did, moderator).await? {
+                println!("Unbanned profile with did '{}'", did);
+            } else {
+                println!("Profile with did '{}' is not banned", did);
+            }
+        }
+        Command::BanPost { uri, reason } => {
+            check_post_uri(&uri)?;
+
+            if database
+                .ban(BanKind::Post, &uri, &reason, moderator)
+                .await?
+            {
+                println!("Banned post '{}'", uri);
+            } else {
+                println!("Post '{}' is already banned", uri);
+            }
+        }
+        Command::UnbanPost { uri } => {
+            check_post_uri(&uri)?;
+
+            if database.unban(BanKind::Post, &uri, moderator).await? {
+                println!("Unbanned post '{}'", uri);
+            } else {
+                println!("Post '{}' is not banned", uri);
+            }
+        }
+        Command::List => {
+            for kind in [BanKind::Profile, BanKind::Post] {
+                for ban in database.fetch_bans(kind).await? {
+                    println!(
+                        "{}\t{:?}\t{}\t{}",
+                        ban.banned_at.to_rfc3339(),
+                        ban.kind,
+                        ban.subject,
+                        ban.reason
+                    );
+                }
+            }
+        }
+        Command::Log { limit } => {
+            for entry in database.fetch_moderation_log(limit).await? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    entry.performed_at.to_rfc3339(),
+                    entry.moderator.as_deref().unwrap_or("-"),
+                    entry.action,
+                    entry.subject,
+                    entry.reason.as_deref().unwrap_or("-")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn resolve_did(handle: Option<String>, did: Option<String>) -> Result<String> {
+    match (handle, did) {
+        (_, Some(did)) => Ok(did),
+        (Some(handle), None) => {
+            let did = Bluesky::unauthenticated()
+                .resolve_handle(&handle)
+                .await?
+                .ok_or_else(|| anyhow!("No such user: {}", handle))?;
+
+            println!("Resolved handle '{}' to did '{}'", handle, did);
+
+            Ok(did)
+        }
+        (None, None) => bail!("Either --handle or --did must be supplied"),
+    }
+}
+
+fn check_post_uri(uri: &str) -> Result<()> {
+    if !uri.starts_with("at://") || !uri.contains("/app.bsky.feed.post/") {
+        bail!("Expected an AT URI of a post, got '{}'", uri);
+    }
+
+    Ok(())
+}